@@ -0,0 +1,389 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use thiserror::Error as TError;
+
+#[cfg(feature = "druid_data")]
+use druid::Data;
+
+///Custom Error type for checking answers using `thiserror`
+#[derive(TError, Debug, Clone, PartialEq, Eq)]
+pub enum AnswerError {
+    ///Error for when a numeric fact has a definition which can't be read as a number, like `"about nine"`
+    #[error("Definition isn't a valid number - on input {0:?}")]
+    InvalidNumber(String),
+    ///Error for when a pattern fact has a definition which isn't a valid regular expression, like `"(the mitochondria"` - contains the pattern and the reason
+    #[error("Definition isn't a valid pattern - on input {0:?}: {1}")]
+    InvalidPattern(String, String),
+    ///Error for when a tolerance for [`NumericOptions::with_tolerance`] can't be read, like `"-5%"`
+    #[error("Tolerance isn't a valid positive number or percentage - on input {0:?}")]
+    InvalidTolerance(String),
+}
+
+///The way that an answer is compared to the definition of a [`crate::item::Fact`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[cfg_attr(feature = "druid_data", derive(Data))]
+pub enum AnswerType {
+    ///The answer must be the same as the definition, ignoring leading and trailing whitespace
+    #[default]
+    Text,
+    ///The definition is a number with an optional unit, and answers are checked using a tolerance
+    Numeric(NumericOptions),
+    ///The definition is a regular expression, like `"(the )?mitochondria"`, which the whole answer must match, ignoring leading and trailing whitespace
+    Pattern,
+}
+
+///Options for checking [`AnswerType::Numeric`] answers
+///
+///An answer is correct if it is within **either** the absolute or the relative tolerance of the definition
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "druid_data", derive(Data))]
+pub struct NumericOptions {
+    ///The largest absolute difference between the answer and the definition that is still correct
+    pub absolute_tolerance: f64,
+    ///The largest difference between the answer and the definition as a fraction of the definition, eg. `0.01` for 1%
+    pub relative_tolerance: f64,
+    ///Whether or not to read and convert units, so `"981 cm/s^2"` matches `"9.81 m/s^2"`.
+    ///
+    ///If this is `false`, anything after the number is ignored.
+    pub parse_units: bool,
+}
+
+impl Default for NumericOptions {
+    fn default() -> Self {
+        Self {
+            absolute_tolerance: 0.0,
+            relative_tolerance: 0.01,
+            parse_units: true,
+        }
+    }
+}
+
+impl PartialEq for NumericOptions {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for NumericOptions {}
+impl PartialOrd for NumericOptions {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for NumericOptions {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.absolute_tolerance
+            .total_cmp(&other.absolute_tolerance)
+            .then_with(|| self.relative_tolerance.total_cmp(&other.relative_tolerance))
+            .then_with(|| self.parse_units.cmp(&other.parse_units))
+    }
+}
+
+impl AnswerType {
+    ///Checks an answer against a definition.
+    ///
+    ///Returns whether or not the answer was correct, or an [`AnswerError`] if the definition itself is invalid for this type
+    pub fn check(&self, definition: &str, answer: &str) -> Result<bool, AnswerError> {
+        match self {
+            Self::Text => Ok(answer.trim() == definition),
+            Self::Numeric(options) => options.check(definition, answer),
+            Self::Pattern => Ok(compile_pattern(definition)?.is_match(answer.trim())),
+        }
+    }
+
+    ///Checks that a definition can be used with this type, without needing an answer - eg. that a pattern is a valid regular expression.
+    pub fn validate(&self, definition: &str) -> Result<(), AnswerError> {
+        match self {
+            Self::Text => Ok(()),
+            Self::Numeric(_) => split_number(definition)
+                .map(|_| ())
+                .ok_or_else(|| AnswerError::InvalidNumber(definition.to_string())),
+            Self::Pattern => compile_pattern(definition).map(|_| ()),
+        }
+    }
+}
+
+///Compiles a pattern definition so that it has to match the whole of an answer
+fn compile_pattern(definition: &str) -> Result<Regex, AnswerError> {
+    Regex::new(&format!("^(?:{definition})$"))
+        .map_err(|e| AnswerError::InvalidPattern(definition.to_string(), e.to_string()))
+}
+
+impl NumericOptions {
+    ///Sets the tolerance for one fact from what the user typed - `"0.5"` is an absolute tolerance, `"2%"` is a relative one, and anything empty keeps these options as they are
+    pub fn with_tolerance(self, tolerance: &str) -> Result<Self, AnswerError> {
+        let tolerance = tolerance.trim();
+        if tolerance.is_empty() {
+            return Ok(self);
+        }
+
+        let invalid = || AnswerError::InvalidTolerance(tolerance.to_string());
+        let (number, is_relative) = tolerance
+            .strip_suffix('%')
+            .map_or((tolerance, false), |n| (n.trim_end(), true));
+        let number: f64 = number.parse().map_err(|_| invalid())?;
+        if !number.is_finite() || number < 0.0 {
+            return Err(invalid());
+        }
+
+        Ok(if is_relative {
+            Self {
+                absolute_tolerance: 0.0,
+                relative_tolerance: number / 100.0,
+                ..self
+            }
+        } else {
+            Self {
+                absolute_tolerance: number,
+                relative_tolerance: 0.0,
+                ..self
+            }
+        })
+    }
+
+    ///Checks a numeric answer against a numeric definition
+    #[instrument]
+    pub fn check(&self, definition: &str, answer: &str) -> Result<bool, AnswerError> {
+        let (expected, expected_unit) = split_number(definition)
+            .ok_or_else(|| AnswerError::InvalidNumber(definition.to_string()))?;
+        let Some((given, given_unit)) = split_number(answer) else {
+            return Ok(false);
+        };
+
+        let (expected, given) = if !self.parse_units || given_unit.is_empty() {
+            //no unit given, so assume that the answer is in the same unit as the definition
+            (expected, given)
+        } else if let (Some(e_unit), Some(g_unit)) =
+            (Unit::parse(expected_unit), Unit::parse(given_unit))
+        {
+            if e_unit.dimensions != g_unit.dimensions {
+                return Ok(false);
+            }
+            (expected * e_unit.factor, given * g_unit.factor)
+        } else if expected_unit == given_unit {
+            (expected, given)
+        } else {
+            return Ok(false);
+        };
+
+        let difference = (expected - given).abs();
+        Ok(difference <= self.absolute_tolerance
+            || difference <= self.relative_tolerance * expected.abs())
+    }
+}
+
+///Splits a string like `"9.81 m/s^2"` into the longest number at the start, and the rest of it
+fn split_number(s: &str) -> Option<(f64, &str)> {
+    let s = s.trim();
+    (1..=s.len())
+        .rev()
+        .filter(|i| s.is_char_boundary(*i))
+        .find_map(|i| {
+            s[..i]
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite())
+                .map(|n| (n, s[i..].trim()))
+        })
+}
+
+///The SI base dimensions that units are made of - length, mass and time
+type Dimensions = [i32; 3];
+
+///Units which can be used on their own, or with an SI prefix - the symbol, how many SI base units one of them is, and its dimensions
+const UNITS: &[(&str, f64, Dimensions)] = &[
+    ("m", 1.0, [1, 0, 0]),
+    ("g", 1e-3, [0, 1, 0]),
+    ("s", 1.0, [0, 0, 1]),
+    ("min", 60.0, [0, 0, 1]),
+    ("h", 3600.0, [0, 0, 1]),
+    ("L", 1e-3, [3, 0, 0]),
+    ("N", 1.0, [1, 1, -2]),
+    ("J", 1.0, [2, 1, -2]),
+    ("W", 1.0, [2, 1, -3]),
+    ("Pa", 1.0, [-1, 1, -2]),
+    ("Hz", 1.0, [0, 0, -1]),
+];
+
+///SI prefixes which can go in front of any of the [`UNITS`]
+const PREFIXES: &[(&str, f64)] = &[
+    ("G", 1e9),
+    ("M", 1e6),
+    ("k", 1e3),
+    ("c", 1e-2),
+    ("m", 1e-3),
+    ("u", 1e-6),
+    ("μ", 1e-6),
+    ("n", 1e-9),
+];
+
+///A parsed unit, like `km/h` or `m/s^2`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Unit {
+    ///How many SI base units one of this unit is
+    factor: f64,
+    ///The dimensions of this unit
+    dimensions: Dimensions,
+}
+
+impl Unit {
+    ///Parses a unit made of symbols from [`UNITS`], optionally prefixed with [`PREFIXES`], multiplied with `*` or spaces, divided with `/`, and raised to powers with `^`.
+    ///
+    ///Returns [`None`] if any part isn't recognised
+    fn parse(s: &str) -> Option<Self> {
+        let mut unit = Self {
+            factor: 1.0,
+            dimensions: [0; 3],
+        };
+
+        for (segment_index, segment) in s.split('/').enumerate() {
+            let sign = if segment_index == 0 { 1 } else { -1 };
+            for term in segment
+                .split(|c: char| c == '*' || c == '·' || c.is_whitespace())
+                .filter(|t| !t.is_empty())
+            {
+                let (symbol, power) = match term.split_once('^') {
+                    Some((symbol, power)) => (symbol, power.parse::<i32>().ok()?),
+                    None => (term, 1),
+                };
+                let (factor, dimensions) = lookup_symbol(symbol)?;
+                let power = power * sign;
+
+                unit.factor *= factor.powi(power);
+                for (total, d) in unit.dimensions.iter_mut().zip(dimensions) {
+                    *total += d * power;
+                }
+            }
+        }
+
+        if s.trim().is_empty() {
+            None
+        } else {
+            Some(unit)
+        }
+    }
+}
+
+///Finds a single unit symbol like `km` in [`UNITS`], trying it without a prefix first
+fn lookup_symbol(symbol: &str) -> Option<(f64, Dimensions)> {
+    let find = |s: &str| UNITS.iter().find(|(u, _, _)| *u == s);
+
+    if let Some((_, factor, dimensions)) = find(symbol) {
+        return Some((*factor, *dimensions));
+    }
+
+    PREFIXES.iter().find_map(|(prefix, prefix_factor)| {
+        symbol
+            .strip_prefix(prefix)
+            .and_then(find)
+            .map(|(_, factor, dimensions)| (factor * prefix_factor, *dimensions))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::answer::{AnswerError, AnswerType, NumericOptions};
+
+    #[test]
+    fn text_test() {
+        let t = AnswerType::Text;
+        assert_eq!(t.check("mitochondria", "  mitochondria "), Ok(true));
+        assert_eq!(t.check("mitochondria", "Mitochondria"), Ok(false));
+    }
+
+    #[test]
+    fn tolerance_test() {
+        let default = NumericOptions::default();
+        assert_eq!(default.with_tolerance(" "), Ok(default));
+        assert_eq!(
+            default.with_tolerance("0.5"),
+            Ok(NumericOptions {
+                absolute_tolerance: 0.5,
+                relative_tolerance: 0.0,
+                ..default
+            })
+        );
+        assert_eq!(
+            default.with_tolerance("5 %"),
+            Ok(NumericOptions {
+                absolute_tolerance: 0.0,
+                relative_tolerance: 0.05,
+                ..default
+            })
+        );
+        assert_eq!(
+            default.with_tolerance("-5%"),
+            Err(AnswerError::InvalidTolerance("-5%".into()))
+        );
+        assert!(default.with_tolerance("lots").is_err());
+
+        let n = AnswerType::Numeric(default.with_tolerance("0.5").unwrap());
+        assert_eq!(n.check("10", "10.4"), Ok(true));
+        assert_eq!(n.check("10", "10.6"), Ok(false));
+    }
+
+    #[test]
+    fn pattern_test() {
+        let p = AnswerType::Pattern;
+        assert_eq!(p.check("(the )?mitochondria", "mitochondria"), Ok(true));
+        assert_eq!(
+            p.check("(the )?mitochondria", " the mitochondria "),
+            Ok(true)
+        );
+        assert_eq!(
+            p.check("(the )?mitochondria", "not the mitochondria"),
+            Ok(false)
+        );
+        assert_eq!(p.check("a|b", "ab"), Ok(false));
+        assert!(p.validate("(the )?mitochondria").is_ok());
+        assert!(matches!(
+            p.check("(the mitochondria", "the mitochondria"),
+            Err(AnswerError::InvalidPattern(s, _)) if s == "(the mitochondria"
+        ));
+        assert!(p.validate("(the mitochondria").is_err());
+    }
+
+    #[test]
+    fn numeric_tolerance_test() {
+        let n = AnswerType::Numeric(NumericOptions::default());
+        assert_eq!(n.check("9.81", "9.81"), Ok(true));
+        assert_eq!(n.check("9.81", "9.8"), Ok(true));
+        assert_eq!(n.check("9.81", "9.5"), Ok(false));
+        assert_eq!(n.check("9.81", "nine"), Ok(false));
+        assert_eq!(
+            n.check("about nine", "9"),
+            Err(AnswerError::InvalidNumber("about nine".into()))
+        );
+        assert!(n.validate("about nine").is_err());
+        assert!(AnswerType::Text.validate("about nine").is_ok());
+
+        let exact = AnswerType::Numeric(NumericOptions {
+            absolute_tolerance: 0.5,
+            relative_tolerance: 0.0,
+            parse_units: true,
+        });
+        assert_eq!(exact.check("100", "100.4"), Ok(true));
+        assert_eq!(exact.check("100", "100.6"), Ok(false));
+    }
+
+    #[test]
+    fn numeric_units_test() {
+        let n = AnswerType::Numeric(NumericOptions::default());
+        assert_eq!(n.check("9.81 m/s^2", "9.81 m/s^2"), Ok(true));
+        assert_eq!(n.check("9.81 m/s^2", "9.81"), Ok(true));
+        assert_eq!(n.check("9.81 m/s^2", "981 cm/s^2"), Ok(true));
+        assert_eq!(n.check("9.81 m/s^2", "9.81 m/s"), Ok(false));
+        assert_eq!(n.check("36 km/h", "10 m/s"), Ok(true));
+        assert_eq!(n.check("1 kN", "1000 kg m/s^2"), Ok(true));
+        assert_eq!(n.check("2 min", "120s"), Ok(true));
+        assert_eq!(n.check("5 apples", "5 apples"), Ok(true));
+        assert_eq!(n.check("5 apples", "5 pears"), Ok(false));
+
+        let no_units = AnswerType::Numeric(NumericOptions {
+            parse_units: false,
+            ..NumericOptions::default()
+        });
+        assert_eq!(no_units.check("9.81 m/s^2", "9.81 cm"), Ok(true));
+    }
+}
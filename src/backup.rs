@@ -0,0 +1,490 @@
+use crate::{
+    config::HumanDuration,
+    file_storage::write_atomic,
+    game::AnkiDB,
+    migration::{from_versioned_str, to_versioned_string, MigrationError},
+    storage::Storage,
+};
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet, convert::Infallible, fs::OpenOptions, io::ErrorKind, path::PathBuf,
+};
+use thiserror::Error;
+
+///The start of the file name of every backup
+const PREFIX: &str = "janki-";
+///The end of the file name of every backup
+const SUFFIX: &str = ".json";
+///The format of the time in the file name of a backup
+const TIME_FORMAT: &str = "%Y-%m-%d_%H-%M-%S%.3f";
+///The format for reading the time in the file name of a backup - the milliseconds are optional, as older backups don't have them
+const PARSE_TIME_FORMAT: &str = "%Y-%m-%d_%H-%M-%S%.f";
+
+///Options for [`BackupManager`] - these are stored in the [`crate::config::JankiConfig`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupOptions {
+    ///The directory to put backups in - if this is relative, it is relative to [`BackupManager::with_base_dir`], which should be the directory the database is in
+    pub dir: String,
+    ///How long to wait between backups - `0s` only backs up on startup and after [`BackupOptions::every_reviews`]
+    pub interval: HumanDuration,
+    ///How many answers to wait for between backups - `0` only backs up on startup and after [`BackupOptions::interval`]
+    pub every_reviews: u64,
+    ///How many of the newest backups to always keep
+    pub keep_recent: usize,
+    ///How many days to keep the newest backup from
+    pub keep_daily: usize,
+    ///How many weeks to keep the newest backup from
+    pub keep_weekly: usize,
+}
+
+impl Default for BackupOptions {
+    fn default() -> Self {
+        Self {
+            dir: "janki_backups".into(),
+            interval: HumanDuration(Duration::minutes(30)),
+            every_reviews: 100,
+            keep_recent: 5,
+            keep_daily: 7,
+            keep_weekly: 4,
+        }
+    }
+}
+
+///A backup on disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    ///Where the backup is
+    pub path: PathBuf,
+    ///When the backup was made, to the millisecond
+    pub created: DateTime<Utc>,
+}
+
+///Error for making and restoring backups, using `thiserror`
+#[derive(Error, Debug)]
+pub enum BackupError<E = Infallible> {
+    ///Error from the [`Storage`]
+    #[error("storage error: {0:?}")]
+    StorageError(E),
+
+    ///Error reading or writing a backup - comes from [`std::io::Error`]
+    #[error("io error: {0}")]
+    IOError(#[from] std::io::Error),
+
+    ///Error with `serde_json` - comes from [`serde_json::Error`]
+    #[error("serde_json error: {0}")]
+    SJError(#[from] serde_json::Error),
+
+    ///Error reading or upgrading a backup - comes from [`MigrationError`]
+    #[error("error reading backup: {0}")]
+    MigrationError(#[from] MigrationError),
+}
+
+///Makes timestamped backups of the [`AnkiDB`] in a directory, and gets rid of old ones.
+///
+///Backups are just the items as JSON, so this works with any [`Storage`] - they go through [`Storage::seal_backup`] first, so eg. an encrypted database gets encrypted backups. Use [`crate::game::AnkiGame::with_backups`] to back up on startup, and then every so often as questions are answered
+#[derive(Debug, Clone)]
+pub struct BackupManager {
+    ///The options
+    options: BackupOptions,
+    ///When the last backup was made by this manager
+    last_backup: Option<DateTime<Utc>>,
+    ///The total number of answers when the last backup was made
+    last_reviews: u64,
+    ///The directory that [`BackupOptions::dir`] is relative to - the current directory if [`Option::None`]
+    base_dir: Option<PathBuf>,
+}
+
+impl BackupManager {
+    ///Creates a new backup manager - nothing is written until the first backup
+    #[must_use]
+    pub const fn new(options: BackupOptions) -> Self {
+        Self {
+            options,
+            last_backup: None,
+            last_reviews: 0,
+            base_dir: None,
+        }
+    }
+
+    ///Sets the directory that a relative [`BackupOptions::dir`] is in - usually the directory that the database is in, so backups stay with it wherever the program is run from
+    #[must_use]
+    pub fn with_base_dir(mut self, base_dir: impl Into<PathBuf>) -> Self {
+        self.base_dir = Some(base_dir.into());
+        self
+    }
+
+    ///Gets the options
+    #[must_use]
+    pub const fn options(&self) -> &BackupOptions {
+        &self.options
+    }
+
+    ///Gets the directory backups are kept in
+    #[must_use]
+    pub fn dir(&self) -> PathBuf {
+        self.base_dir.as_ref().map_or_else(
+            || PathBuf::from(&self.options.dir),
+            |base| base.join(&self.options.dir),
+        )
+    }
+
+    ///Makes an empty file for a new backup, so it can't overwrite any other backup - if there's already one from `now`, a counter is added to the name
+    fn reserve_path(&self, now: DateTime<Utc>) -> std::io::Result<PathBuf> {
+        let time = now.format(TIME_FORMAT);
+        let mut counter = 0_u32;
+        loop {
+            let name = if counter == 0 {
+                format!("{PREFIX}{time}{SUFFIX}")
+            } else {
+                format!("{PREFIX}{time}_{counter}{SUFFIX}")
+            };
+            let path = self.dir().join(name);
+
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(path),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => counter += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    ///Backs up the database, sealed with [`Storage::seal_backup`] from the `storage` it is kept in, and then gets rid of old backups - see [`BackupManager::prune`]
+    #[instrument(skip(self, storage, db))]
+    pub fn backup<S: Storage + ?Sized>(
+        &mut self,
+        storage: &S,
+        db: &AnkiDB,
+        now: DateTime<Utc>,
+    ) -> Result<BackupInfo, BackupError<S::ErrorType>> {
+        let contents = storage
+            .seal_backup(to_versioned_string(db)?)
+            .map_err(BackupError::StorageError)?;
+
+        std::fs::create_dir_all(self.dir())?;
+        let path = self.reserve_path(now)?;
+        if let Err(e) = write_atomic(&path, contents.as_bytes(), None) {
+            let _ = std::fs::remove_file(&path);
+            return Err(e.into());
+        }
+        info!(?path, "Made backup");
+
+        self.last_backup = Some(now);
+        self.prune().map_err(BackupError::widen)?;
+
+        Ok(BackupInfo { path, created: now })
+    }
+
+    ///Backs up the database from a [`Storage`] using [`Storage::read_db`]
+    pub fn backup_storage<S: Storage + ?Sized>(
+        &mut self,
+        storage: &S,
+        now: DateTime<Utc>,
+    ) -> Result<BackupInfo, BackupError<S::ErrorType>> {
+        let db = storage.read_db().map_err(BackupError::StorageError)?;
+        self.backup(storage, &db, now)
+    }
+
+    ///Backs up the database if it has been [`BackupOptions::interval`] since the last backup, or there have been [`BackupOptions::every_reviews`] answers - `reviews` is the total number of answers ever, like [`crate::profile::Profile::reviews`].
+    ///
+    ///Returns [`Option::None`] if it isn't time for a backup yet
+    pub fn backup_if_due<S: Storage + ?Sized>(
+        &mut self,
+        storage: &S,
+        db: &AnkiDB,
+        reviews: u64,
+        now: DateTime<Utc>,
+    ) -> Result<Option<BackupInfo>, BackupError<S::ErrorType>> {
+        let interval = self.options.interval.0;
+        let by_time = self
+            .last_backup
+            .is_none_or(|last| interval > Duration::zero() && now - last >= interval);
+        let by_reviews = self.options.every_reviews > 0
+            && reviews.saturating_sub(self.last_reviews) >= self.options.every_reviews;
+
+        if !(by_time || by_reviews) {
+            return Ok(None);
+        }
+
+        let info = self.backup(storage, db, now)?;
+        self.last_reviews = reviews;
+        Ok(Some(info))
+    }
+
+    ///Gets every backup in the directory, newest first
+    pub fn list_backups(&self) -> Result<Vec<BackupInfo>, BackupError> {
+        let entries = match std::fs::read_dir(self.dir()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut backups = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            let Some((created, counter)) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(PREFIX)?.strip_suffix(SUFFIX))
+                .and_then(parse_name)
+            else {
+                continue;
+            };
+
+            backups.push((
+                counter,
+                BackupInfo {
+                    path,
+                    created: created.and_utc(),
+                },
+            ));
+        }
+
+        backups.sort_by_key(|(counter, b)| std::cmp::Reverse((b.created, *counter)));
+        Ok(backups.into_iter().map(|(_, b)| b).collect())
+    }
+
+    ///Reads the database from a backup, using [`Storage::open_backup`] from the `storage` it was made with
+    pub fn read_backup<S: Storage + ?Sized>(
+        storage: &S,
+        backup: &BackupInfo,
+    ) -> Result<AnkiDB, BackupError<S::ErrorType>> {
+        let contents = storage
+            .open_backup(std::fs::read_to_string(&backup.path)?)
+            .map_err(BackupError::StorageError)?;
+        Ok(from_versioned_str(&contents)?)
+    }
+
+    ///Restores a backup to a [`Storage`] using [`Storage::write_db`], and returns the restored database.
+    ///
+    ///The current database is backed up first, so a restore can always be undone
+    #[instrument(skip(self, storage))]
+    pub fn restore_backup<S: Storage + ?Sized>(
+        &mut self,
+        backup: &BackupInfo,
+        storage: &mut S,
+        now: DateTime<Utc>,
+    ) -> Result<AnkiDB, BackupError<S::ErrorType>> {
+        let db = Self::read_backup(storage, backup)?;
+        if let Ok(current) = storage.read_db() {
+            self.backup(storage, &current, now)?;
+        } else {
+            warn!("Unable to read the current database to back it up before restoring");
+        }
+
+        storage.write_db(&db).map_err(BackupError::StorageError)?;
+        info!(?backup, "Restored backup");
+        Ok(db)
+    }
+
+    ///Gets rid of old backups - keeping the newest [`BackupOptions::keep_recent`], the newest from each of the last [`BackupOptions::keep_daily`] days and the newest from each of the last [`BackupOptions::keep_weekly`] weeks.
+    ///
+    ///Returns the backups that were removed
+    pub fn prune(&self) -> Result<Vec<BackupInfo>, BackupError> {
+        let backups = self.list_backups()?;
+        let mut days = HashSet::new();
+        let mut weeks = HashSet::new();
+
+        let mut removed = vec![];
+        for (i, backup) in backups.into_iter().enumerate() {
+            let day = backup.created.date_naive();
+            let week = day.iso_week();
+            let newest_of_day = days.len() < self.options.keep_daily && days.insert(day);
+            let newest_of_week = weeks.len() < self.options.keep_weekly && weeks.insert(week);
+
+            if i >= self.options.keep_recent && !newest_of_day && !newest_of_week {
+                trace!(?backup, "Removing old backup");
+                std::fs::remove_file(&backup.path)?;
+                removed.push(backup);
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+///Reads the time and counter from the name of a backup, without the prefix and suffix
+fn parse_name(name: &str) -> Option<(NaiveDateTime, u32)> {
+    let (time, counter) = match name.rsplit_once('_') {
+        Some((time, counter)) if counter.bytes().all(|b| b.is_ascii_digit()) => {
+            (time, counter.parse().ok()?)
+        }
+        _ => (name, 0),
+    };
+    let time = NaiveDateTime::parse_from_str(time, PARSE_TIME_FORMAT).ok()?;
+    Some((time, counter))
+}
+
+impl BackupError {
+    ///Turns an error which can't come from a [`Storage`] into one for any [`Storage`]
+    pub(crate) fn widen<E>(self) -> BackupError<E> {
+        match self {
+            Self::StorageError(e) => match e {},
+            Self::IOError(e) => BackupError::IOError(e),
+            Self::SJError(e) => BackupError::SJError(e),
+            Self::MigrationError(e) => BackupError::MigrationError(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        backup::{BackupManager, BackupOptions},
+        config::HumanDuration,
+        dummy_storage::DummyStorage,
+        item::Item,
+        storage::Storage,
+        test_utils::f,
+    };
+    use chrono::{Duration, TimeZone, Utc};
+
+    fn manager(dir: &tempfile::TempDir) -> BackupManager {
+        BackupManager::new(BackupOptions {
+            dir: dir.path().join("backups").to_str().unwrap().into(),
+            ..BackupOptions::default()
+        })
+    }
+
+    #[test]
+    fn backup_and_restore_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut backups = manager(&dir);
+        let now = Utc.with_ymd_and_hms(2022, 7, 1, 12, 0, 0).unwrap();
+        assert!(backups.list_backups().unwrap().is_empty());
+
+        let old = vec![Item::new(f("a", "b"))];
+        let mut storage = DummyStorage::default();
+        storage.write_db(&old).unwrap();
+        let first = backups.backup_storage(&storage, now).unwrap();
+
+        //not due again until the interval or enough answers
+        let new = vec![Item::new(f("c", "d"))];
+        let soon = now + Duration::minutes(1);
+        assert_eq!(
+            backups.backup_if_due(&storage, &new, 10, soon).unwrap(),
+            None
+        );
+        assert!(backups
+            .backup_if_due(&storage, &new, 100, soon)
+            .unwrap()
+            .is_some());
+        let later = now + Duration::hours(1);
+        assert!(backups
+            .backup_if_due(&storage, &new, 100, later)
+            .unwrap()
+            .is_some());
+        assert_eq!(backups.list_backups().unwrap().len(), 3);
+        assert_eq!(backups.list_backups().unwrap()[2], first);
+
+        storage.write_db(&new).unwrap();
+        let restore_at = now + Duration::hours(2);
+        let restored = backups
+            .restore_backup(&first, &mut storage, restore_at)
+            .unwrap();
+        assert_eq!(restored, old);
+        assert_eq!(storage.read_db().unwrap(), old);
+
+        //the database from before the restore is backed up too
+        let newest = &backups.list_backups().unwrap()[0];
+        assert_eq!(newest.created, restore_at);
+        assert_eq!(BackupManager::read_backup(&storage, newest).unwrap(), new);
+    }
+
+    #[test]
+    fn same_time_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut backups = manager(&dir);
+        let now = Utc.with_ymd_and_hms(2022, 7, 1, 12, 0, 0).unwrap();
+
+        let mut storage = DummyStorage::default();
+        let old = vec![Item::new(f("a", "b"))];
+        let first = backups.backup(&storage, &old, now).unwrap();
+        let new = vec![Item::new(f("c", "d"))];
+        let second = backups.backup(&storage, &new, now).unwrap();
+        assert_ne!(first.path, second.path);
+        assert_eq!(backups.list_backups().unwrap(), vec![second, first.clone()]);
+
+        //restoring straight away backs up the current database without overwriting the backup being restored
+        storage.write_db(&new).unwrap();
+        backups.restore_backup(&first, &mut storage, now).unwrap();
+        assert_eq!(backups.list_backups().unwrap().len(), 3);
+        assert_eq!(BackupManager::read_backup(&storage, &first).unwrap(), old);
+    }
+
+    #[test]
+    fn names_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let backups = BackupManager::new(BackupOptions::default()).with_base_dir(dir.path());
+        assert_eq!(backups.dir(), dir.path().join("janki_backups"));
+
+        //backups from before names had milliseconds are still found
+        std::fs::create_dir_all(backups.dir()).unwrap();
+        for name in [
+            "janki-2022-07-01_12-00-00.json",
+            "janki-2022-07-01_12-00-00.500.json",
+            "janki-2022-07-01_12-00-00.500_2.json",
+            "not-a-backup.json",
+        ] {
+            std::fs::write(backups.dir().join(name), "[]").unwrap();
+        }
+        let found: Vec<_> = backups
+            .list_backups()
+            .unwrap()
+            .into_iter()
+            .map(|b| b.path.file_name().unwrap().to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "janki-2022-07-01_12-00-00.500_2.json",
+                "janki-2022-07-01_12-00-00.500.json",
+                "janki-2022-07-01_12-00-00.json",
+            ]
+        );
+    }
+
+    #[test]
+    fn prune_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut backups = BackupManager::new(BackupOptions {
+            interval: HumanDuration(Duration::zero()),
+            keep_recent: 2,
+            keep_daily: 3,
+            keep_weekly: 2,
+            ..manager(&dir).options().clone()
+        });
+
+        //four backups a day for 30 days
+        let start = Utc.with_ymd_and_hms(2022, 7, 1, 0, 0, 0).unwrap();
+        for i in 0..120 {
+            backups
+                .backup(
+                    &DummyStorage::default(),
+                    &vec![],
+                    start + Duration::hours(6 * i),
+                )
+                .unwrap();
+        }
+
+        let kept: Vec<_> = backups
+            .list_backups()
+            .unwrap()
+            .into_iter()
+            .map(|b| b.created)
+            .collect();
+        let last = start + Duration::hours(6 * 119);
+        assert_eq!(
+            kept,
+            vec![
+                last,
+                last - Duration::hours(6),
+                last - Duration::days(1),
+                last - Duration::days(2),
+                //the last backup of the sunday before
+                Utc.with_ymd_and_hms(2022, 7, 24, 18, 0, 0).unwrap(),
+            ]
+        );
+    }
+}
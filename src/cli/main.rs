@@ -1,32 +1,120 @@
 use janki::{
+    answer::AnswerType,
+    backup::BackupManager,
+    compression::Compression,
+    exam::{Exam, ExamReport},
     file_storage::NamedFileStorage,
-    game::{default_sag, AnkiGame},
+    game::{AnkiGame, GiveItemGuards},
     item::Fact,
+    session::{Session, SessionSummary},
+    storage::Storage,
 };
-use std::io::stdin;
+use std::{fs::File, io::stdin};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let storage: NamedFileStorage = "./janki_db.json".into();
-    let map = default_sag();
+    //the format is picked from the extension, so eg. `--db=./janki_db.toml` stores it as TOML
+    let path = std::env::args()
+        .find_map(|a| a.strip_prefix("--db=").map(ToString::to_string))
+        .unwrap_or_else(|| "./janki_db.json".into());
+    //backups go next to the database
+    let base_dir = std::path::Path::new(&path)
+        .parent()
+        .map(std::path::Path::to_path_buf)
+        .unwrap_or_default();
+    let mut storage = NamedFileStorage::from(path).with_backup(true);
+    if let Some(compression) =
+        std::env::args().find_map(|a| a.strip_prefix("--compress=").map(str::parse::<Compression>))
+    {
+        storage = storage.with_compression(compression?);
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    if std::env::args().any(|a| a == "--encrypted") {
+        //carrying on would write the database in plain text
+        return Err("--encrypted needs the encryption feature".into());
+    }
+
+    #[cfg(feature = "encryption")]
+    if std::env::args().any(|a| a == "--encrypted") {
+        use janki::encrypted_storage::{EncryptedStorage, EncryptedStorageError};
+
+        let storage = storage.with_backup(false);
+        let plaintext_backup = storage.backup_path();
+
+        let passphrase = rpassword::prompt_password("Enter the passphrase: ")?;
+        let storage = match EncryptedStorage::open(storage, &passphrase) {
+            Ok(storage) => storage,
+            Err(EncryptedStorageError::WrongPassphrase) => {
+                println!("Wrong passphrase");
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let anki = AnkiGame::load(storage)?;
+
+        //the previous version would be kept in plain text, so get rid of it - now that the passphrase is known to be right
+        if plaintext_backup.exists() {
+            println!("Removing the unencrypted {plaintext_backup:?}");
+            std::fs::remove_file(plaintext_backup)?;
+        }
+
+        //backups are encrypted by the storage too
+        let backups = BackupManager::new(anki.config().backup.clone()).with_base_dir(base_dir);
+        return run(anki.with_backups(backups));
+    }
 
-    let mut anki = AnkiGame::new(storage, map)?;
+    let anki = AnkiGame::load(storage)?;
+    let backups = BackupManager::new(anki.config().backup.clone()).with_base_dir(base_dir);
+    run(anki.with_backups(backups))
+}
 
+fn run<S: Storage>(mut anki: AnkiGame<S, GiveItemGuards>) -> Result<(), Box<dyn std::error::Error>>
+where
+    S::ErrorType: 'static,
+{
     let mut input = String::new();
     loop {
         input.clear();
-        println!("Add, Test or Exit - [atE]: ");
+        println!("Add, Test, Session, Cram, eXam, Backups or Exit - [atscxbE]: ");
         stdin().read_line(&mut input)?;
 
         match input.trim() {
             "a" => {
                 let mut term = String::new();
                 let mut def = String::new();
+                let mut answer_type = String::new();
                 println!("Enter a term: ");
                 stdin().read_line(&mut term)?;
                 println!("Add a definition: ");
                 stdin().read_line(&mut def)?;
+                println!("Is the definition Text, a Number or a Pattern? [Tnp]: ");
+                stdin().read_line(&mut answer_type)?;
+
+                let answer_type = match answer_type.trim() {
+                    "n" => {
+                        let mut tolerance = String::new();
+                        println!(
+                            "Enter a tolerance, like 0.5 or 2% (leave empty for the default): "
+                        );
+                        stdin().read_line(&mut tolerance)?;
 
-                anki.add_fact(Fact::new(term.trim(), def.trim()));
+                        match anki.config().answers.numeric.with_tolerance(&tolerance) {
+                            Ok(options) => AnswerType::Numeric(options),
+                            Err(e) => {
+                                println!("Unable to add fact: {e}");
+                                continue;
+                            }
+                        }
+                    }
+                    "p" => AnswerType::Pattern,
+                    _ => AnswerType::Text,
+                };
+
+                if let Err(e) =
+                    anki.add_fact(Fact::new(term.trim(), def.trim()).with_answer_type(answer_type))
+                {
+                    println!("Unable to add fact: {e}");
+                }
             }
 
             "t" => {
@@ -36,12 +124,113 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 println!("What is the definition of {}", item.term);
                 stdin().read_line(&mut answer)?;
 
-                if answer.trim() == item.definition {
-                    println!("Correct!");
-                    item.was_succesful = Some(true);
+                match item.check(&answer) {
+                    Ok(true) => {
+                        println!("Correct!");
+                        item.was_succesful = Some(true);
+                    }
+                    Ok(false) => {
+                        println!("Wrong - the answer is {}", item.definition);
+                        item.was_succesful = Some(false);
+                    }
+                    Err(e) => println!("Unable to check answer, so it wasn't recorded: {e}"),
+                }
+            }
+            "s" | "c" => {
+                let options = if input.trim() == "c" {
+                    anki.config().cram_options()
                 } else {
-                    println!("Wrong - the answer is {}", item.definition);
-                    item.was_succesful = Some(false);
+                    anki.config().session_options()
+                };
+                let mut session = Session::new(&anki, &options);
+                let mut answer = String::new();
+
+                while let Some(fact) = session.current_fact(&anki) {
+                    let progress = session.progress(&anki);
+                    println!(
+                        "[{}/{}] What is the definition of {} (leave empty to stop)",
+                        progress.done + 1,
+                        progress.done + progress.remaining,
+                        fact.term
+                    );
+                    answer.clear();
+                    stdin().read_line(&mut answer)?;
+                    if answer.trim().is_empty() {
+                        break;
+                    }
+
+                    match session.answer(&mut anki, &answer) {
+                        Ok(true) => println!("Correct!"),
+                        Ok(false) => println!("Wrong - the answer is {}", fact.definition),
+                        Err(e) => {
+                            println!("Unable to check answer: {e}");
+                            break;
+                        }
+                    }
+                }
+
+                print_summary(&session.summary(&anki));
+            }
+            "x" => {
+                let mut exam = Exam::new(&anki, &anki.config().exam_options());
+                let mut answer = String::new();
+
+                while let Some(fact) = exam.current_fact(&anki).cloned() {
+                    let (question, total) = exam.position();
+                    let remaining = exam
+                        .time_remaining(&anki)
+                        .map(|t| format!(" - {}s left", t.num_seconds()))
+                        .unwrap_or_default();
+                    println!(
+                        "[{question}/{total}{remaining}] What is the definition of {}",
+                        fact.term
+                    );
+                    answer.clear();
+                    stdin().read_line(&mut answer)?;
+                    exam.answer(&anki, &answer);
+                }
+
+                let report = exam.finish(&mut anki);
+                print_report(&report);
+
+                let mut path = String::new();
+                println!("Export the report to a .json or .csv file (leave empty to skip): ");
+                stdin().read_line(&mut path)?;
+                let path = path.trim();
+                if !path.is_empty() {
+                    let file = File::create(path)?;
+                    if path.ends_with(".csv") {
+                        report.write_csv(file)?;
+                    } else {
+                        serde_json::to_writer_pretty(file, &report)?;
+                    }
+                }
+            }
+            "b" => {
+                let backups = anki
+                    .backups()
+                    .map(BackupManager::list_backups)
+                    .transpose()?
+                    .unwrap_or_default();
+                if backups.is_empty() {
+                    println!("No backups yet");
+                    continue;
+                }
+                for (i, backup) in backups.iter().enumerate() {
+                    println!("{i}: {}", backup.created.format("%Y-%m-%d %H:%M:%S"));
+                }
+
+                let mut choice = String::new();
+                println!("Enter a number to restore that backup (leave empty to cancel): ");
+                stdin().read_line(&mut choice)?;
+                if let Some(backup) = choice
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| backups.get(i))
+                {
+                    anki.restore_backup(backup)?;
+                    println!("Restored {} items", anki.get_all_facts().len());
                 }
             }
             _ => break,
@@ -52,3 +241,45 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+fn print_summary(summary: &SessionSummary) {
+    println!(
+        "Session over - {}/{} correct ({:.0}%) over {} items in {}s",
+        summary.correct,
+        summary.reviews,
+        summary.accuracy * 100.0,
+        summary.items,
+        summary.duration.num_seconds()
+    );
+    for (fact, times) in &summary.missed {
+        println!("Missed {} {times} time(s) - {}", fact.term, fact.definition);
+    }
+}
+
+fn print_report(report: &ExamReport) {
+    println!(
+        "Exam over - {}/{} correct ({:.0}%) in {}s{}",
+        report.correct,
+        report.results.len(),
+        report.percentage,
+        report.time_taken_ms / 1000,
+        if report.timed_out {
+            " - out of time!"
+        } else {
+            ""
+        }
+    );
+    for r in &report.results {
+        println!(
+            "{} {} - you said {:?}, the answer is {:?}",
+            match (&r.check_error, r.correct) {
+                (Some(e), _) => format!("Couldn't check ({e})"),
+                (None, true) => "Correct".into(),
+                (None, false) => "Wrong".into(),
+            },
+            r.term,
+            r.answer.as_deref().unwrap_or_default(),
+            r.definition
+        );
+    }
+}
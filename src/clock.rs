@@ -0,0 +1,60 @@
+use chrono::{DateTime, Duration, Utc};
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+///Trait for something which can tell the time - this lets time-dependent logic be tested without waiting
+pub trait Clock: Debug {
+    ///Gets the current time
+    fn now(&self) -> DateTime<Utc>;
+}
+
+///A [`Clock`] which uses the system time via [`Utc::now`]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+///A [`Clock`] which only changes when told to - clones share the same time, so one can be kept to control another, even from another thread
+#[derive(Debug, Clone)]
+pub struct MockClock(Arc<Mutex<DateTime<Utc>>>);
+
+impl MockClock {
+    ///Creates a new mock clock, starting at a given time
+    #[must_use]
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self(Arc::new(Mutex::new(start)))
+    }
+
+    ///Moves the clock forwards (or backwards, for a negative [`Duration`])
+    pub fn advance(&self, by: Duration) {
+        *self.time() += by;
+    }
+
+    ///Sets the clock to a given time
+    pub fn set(&self, to: DateTime<Utc>) {
+        *self.time() = to;
+    }
+
+    ///Locks the time - a panic while it was locked can't leave it half-changed, so poisoning is ignored
+    fn time(&self) -> std::sync::MutexGuard<'_, DateTime<Utc>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new(Utc::now())
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.time()
+    }
+}
@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+use std::{str::FromStr, string::FromUtf8Error};
+use thiserror::Error;
+
+///The magic bytes that gzip data starts with
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+///The magic bytes that zstd data starts with
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+///How to compress data before it is written - what was used is worked out from the data when reading, so this can be changed at any time.
+///
+///Anything other than [`Compression::None`] needs the `compression` feature
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Compression {
+    ///Store it as it is
+    #[default]
+    None,
+    ///Compress with gzip
+    Gzip,
+    ///Compress with zstd - usually smaller and faster than gzip
+    Zstd,
+}
+
+///Error for compressing and decompressing data, using `thiserror`
+#[derive(Error, Debug)]
+pub enum CompressionError {
+    ///Error compressing or decompressing - comes from [`std::io::Error`]
+    #[error("io error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    ///The decompressed data isn't valid UTF-8 - comes from [`FromUtf8Error`]
+    #[error("decompressed data isn't UTF-8: {0}")]
+    NotUtf8(#[from] FromUtf8Error),
+
+    ///The data needs the `compression` feature
+    #[error("{0:?} compression needs the `compression` feature")]
+    Unsupported(Compression),
+
+    ///The name of a compression wasn't recognised
+    #[error("unknown compression {0:?} - expected none, gzip or zstd")]
+    UnknownName(String),
+}
+
+impl FromStr for Compression {
+    type Err = CompressionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "gzip" | "gz" => Ok(Self::Gzip),
+            "zstd" | "zst" => Ok(Self::Zstd),
+            _ => Err(CompressionError::UnknownName(s.into())),
+        }
+    }
+}
+
+impl Compression {
+    ///Works out how some data was compressed from the magic bytes at the start of it
+    #[must_use]
+    pub fn detect(data: &[u8]) -> Self {
+        if data.starts_with(&GZIP_MAGIC) {
+            Self::Gzip
+        } else if data.starts_with(&ZSTD_MAGIC) {
+            Self::Zstd
+        } else {
+            Self::None
+        }
+    }
+
+    ///Compresses some data
+    ///
+    /// # Errors
+    /// - [`CompressionError::Unsupported`] if the `compression` feature is off
+    /// - [`CompressionError::IoError`] if the data can't be compressed
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            #[cfg(feature = "compression")]
+            Self::Gzip => {
+                use std::io::Write;
+
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            #[cfg(feature = "compression")]
+            Self::Zstd => Ok(zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL)?),
+            #[cfg(not(feature = "compression"))]
+            _ => Err(CompressionError::Unsupported(self)),
+        }
+    }
+
+    ///Decompresses some data, working out how it was compressed with [`Compression::detect`] - so data that isn't compressed is returned as it is
+    ///
+    /// # Errors
+    /// - [`CompressionError::Unsupported`] if it is compressed, but the `compression` feature is off
+    /// - [`CompressionError::IoError`] if the data can't be decompressed
+    pub fn decompress(data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        match Self::detect(data) {
+            Self::None => Ok(data.to_vec()),
+            #[cfg(feature = "compression")]
+            Self::Gzip => {
+                use std::io::Read;
+
+                let mut out = vec![];
+                flate2::read::GzDecoder::new(data).read_to_end(&mut out)?;
+                Ok(out)
+            }
+            #[cfg(feature = "compression")]
+            Self::Zstd => Ok(zstd::decode_all(data)?),
+            #[cfg(not(feature = "compression"))]
+            compression => Err(CompressionError::Unsupported(compression)),
+        }
+    }
+
+    ///Decompresses some data with [`Compression::decompress`], and reads it as UTF-8
+    ///
+    /// # Errors
+    /// - Anything from [`Compression::decompress`]
+    /// - [`CompressionError::NotUtf8`] if it isn't UTF-8
+    pub fn decompress_to_string(data: &[u8]) -> Result<String, CompressionError> {
+        Ok(String::from_utf8(Self::decompress(data)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compression::Compression;
+
+    #[test]
+    fn detect_test() {
+        assert_eq!(Compression::detect(b"[]"), Compression::None);
+        assert_eq!(Compression::detect(b""), Compression::None);
+        assert_eq!(Compression::detect(&[0x1f, 0x8b, 8]), Compression::Gzip);
+        assert_eq!(
+            Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd, 0]),
+            Compression::Zstd
+        );
+        assert_eq!("ZSTD".parse::<Compression>().unwrap(), Compression::Zstd);
+        assert!("lz4".parse::<Compression>().is_err());
+
+        let plain = Compression::None.compress(b"hello").unwrap();
+        assert_eq!(Compression::decompress_to_string(&plain).unwrap(), "hello");
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn round_trip_test() {
+        let data = "{\"version\":1,\"items\":[]}".repeat(100);
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let compressed = compression.compress(data.as_bytes()).unwrap();
+            assert_eq!(Compression::detect(&compressed), compression);
+            assert!(compressed.len() < data.len() / 10, "{compression:?}");
+            assert_eq!(
+                Compression::decompress_to_string(&compressed).unwrap(),
+                data
+            );
+        }
+    }
+}
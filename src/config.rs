@@ -0,0 +1,403 @@
+use crate::{
+    answer::NumericOptions,
+    backup::BackupOptions,
+    exam::ExamOptions,
+    game::{Extrapolation, Fuzz, SeeAgainGaps},
+    session::{SessionOptions, SessionOrder},
+    sprint::SprintOptions,
+    storage::Storage,
+};
+use chrono::Duration;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt::Display, str::FromStr};
+use thiserror::Error;
+
+///The units that a [`HumanDuration`] can use, from biggest to smallest, and how many seconds are in each
+const UNITS: [(&str, i64); 5] = [
+    ("w", 7 * 86_400),
+    ("d", 86_400),
+    ("h", 3600),
+    ("m", 60),
+    ("s", 1),
+];
+
+///Error for an invalid [`HumanDuration`], using `thiserror`
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("invalid duration - expected something like \"10m\" or \"1d12h\" - on input {0:?}")]
+pub struct InvalidDuration(pub String);
+
+///A [`Duration`] which is written in a human-friendly way, like `"10m"`, `"3d"` or `"1h30m"`.
+///
+///The units are `w`, `d`, `h`, `m` and `s`, for weeks, days, hours, minutes and seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HumanDuration(pub Duration);
+
+impl From<Duration> for HumanDuration {
+    fn from(d: Duration) -> Self {
+        Self(d)
+    }
+}
+
+impl FromStr for HumanDuration {
+    type Err = InvalidDuration;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidDuration(s.to_string());
+        let trimmed = s.trim();
+        if trimmed.is_empty() {
+            return Err(invalid());
+        }
+
+        let mut total = 0_i64;
+        let mut number = String::new();
+        for c in trimmed.chars().filter(|c| !c.is_whitespace()) {
+            if c.is_ascii_digit() {
+                number.push(c);
+            } else {
+                let unit = UNITS
+                    .iter()
+                    .find(|(name, _)| name.starts_with(c.to_ascii_lowercase()))
+                    .ok_or_else(invalid)?
+                    .1;
+                let n: i64 = number.parse().map_err(|_| invalid())?;
+                total = n
+                    .checked_mul(unit)
+                    .and_then(|n| total.checked_add(n))
+                    .ok_or_else(invalid)?;
+                number.clear();
+            }
+        }
+
+        //a number on its own is in seconds
+        if !number.is_empty() {
+            let n: i64 = number.parse().map_err(|_| invalid())?;
+            total = total.checked_add(n).ok_or_else(invalid)?;
+        }
+
+        //chrono can't hold as many seconds as an i64
+        Duration::try_seconds(total).map(Self).ok_or_else(invalid)
+    }
+}
+
+impl Display for HumanDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut left = self.0.num_seconds();
+        if left == 0 {
+            return write!(f, "0s");
+        }
+        if left < 0 {
+            write!(f, "-")?;
+            left = -left;
+        }
+
+        for (name, secs) in UNITS {
+            if left >= secs {
+                write!(f, "{}{name}", left / secs)?;
+                left %= secs;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for HumanDuration {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for HumanDuration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+///Settings for [`crate::session::Session`]s
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    ///The most items in a session - `0` for no limit
+    pub limit: usize,
+    ///The order items are asked in
+    pub order: SessionOrder,
+    ///How many items later a failed item is asked again
+    pub requeue_gap: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        let options = SessionOptions::default();
+        Self {
+            limit: options.limit.unwrap_or_default(),
+            order: options.order,
+            requeue_gap: options.requeue_gap,
+        }
+    }
+}
+
+///Settings for [`crate::exam::Exam`]s
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExamConfig {
+    ///How many questions to ask
+    pub questions: usize,
+    ///How long the user has for the whole exam - `"0s"` for no limit
+    pub time_limit: HumanDuration,
+}
+
+impl Default for ExamConfig {
+    fn default() -> Self {
+        let options = ExamOptions::default();
+        Self {
+            questions: options.questions,
+            time_limit: HumanDuration(options.time_limit.unwrap_or_else(Duration::zero)),
+        }
+    }
+}
+
+///Settings for checking answers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnswerConfig {
+    ///The options given to new [`crate::answer::AnswerType::Numeric`] facts
+    pub numeric: NumericOptions,
+}
+
+///Error type for reading and writing a [`JankiConfig`], using `thiserror`
+#[derive(Error, Debug)]
+pub enum ConfigError<E> {
+    ///Error from the [`Storage`]
+    #[error("storage error: {0:?}")]
+    StorageError(E),
+
+    ///Error parsing TOML - comes from [`toml::de::Error`]
+    #[error("error parsing TOML: {0}")]
+    TomlDeError(#[from] toml::de::Error),
+
+    ///Error writing TOML - comes from [`toml::ser::Error`]
+    #[error("error writing TOML: {0}")]
+    TomlSerError(#[from] toml::ser::Error),
+
+    ///Error with `serde_json` - comes from [`serde_json::Error`]
+    #[error("serde_json error: {0}")]
+    SJError(#[from] serde_json::Error),
+}
+
+///All of the settings for a collection - the gaps, limits, ordering and answer checking.
+///
+///This is stored next to the database using [`Storage::write_extra`], so that every frontend uses the same settings. Any missing settings are filled in with the defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JankiConfig {
+    ///How long to wait before asking an item again - `gaps[0]` is for a streak of 1, `gaps[1]` for a streak of 2 and so on
+    pub gaps: Vec<HumanDuration>,
+    ///How long a [`crate::sprint::Sprint`] lasts
+    pub sprint_duration: HumanDuration,
+    ///What to do for streaks longer than any in the gaps
+    pub extrapolation: Extrapolation,
+    ///How due dates are spread out
+    pub fuzz: Fuzz,
+    ///Settings for backups - see [`crate::backup::BackupManager`]
+    pub backup: BackupOptions,
+    ///Settings for sessions
+    pub session: SessionConfig,
+    ///Settings for exams
+    pub exam: ExamConfig,
+    ///Settings for checking answers
+    pub answers: AnswerConfig,
+}
+
+impl Default for JankiConfig {
+    fn default() -> Self {
+        Self {
+            gaps: (1..11)
+                .map(|i| HumanDuration(Duration::seconds(i * 30)))
+                .collect(),
+            sprint_duration: HumanDuration(SprintOptions::default().duration),
+            extrapolation: Extrapolation::default(),
+            fuzz: Fuzz::default(),
+            backup: BackupOptions::default(),
+            session: SessionConfig::default(),
+            exam: ExamConfig::default(),
+            answers: AnswerConfig::default(),
+        }
+    }
+}
+
+impl JankiConfig {
+    ///The key used to store the config as TOML with [`Storage::write_extra`]
+    pub const KEY: &'static str = "config.toml";
+    ///The key used to read the config as JSON with [`Storage::read_extra`], if there isn't any TOML
+    pub const JSON_KEY: &'static str = "config.json";
+
+    ///Reads the config from a [`Storage`] - from [`Self::KEY`] as TOML, or [`Self::JSON_KEY`] as JSON, or the default if neither has been stored
+    pub fn load<S: Storage + ?Sized>(storage: &S) -> Result<Self, ConfigError<S::ErrorType>> {
+        if let Some(toml) = storage
+            .read_extra(Self::KEY)
+            .map_err(ConfigError::StorageError)?
+        {
+            return Self::from_toml(&toml);
+        }
+        if let Some(json) = storage
+            .read_extra(Self::JSON_KEY)
+            .map_err(ConfigError::StorageError)?
+        {
+            return Ok(serde_json::from_str(&json)?);
+        }
+
+        trace!("No config found, using the default");
+        Ok(Self::default())
+    }
+
+    ///Writes the config to a [`Storage`] as TOML, using [`Self::KEY`]
+    pub fn save<S: Storage + ?Sized>(
+        &self,
+        storage: &mut S,
+    ) -> Result<(), ConfigError<S::ErrorType>> {
+        storage
+            .write_extra(Self::KEY, &self.to_toml()?)
+            .map_err(ConfigError::StorageError)
+    }
+
+    ///Reads the config from TOML
+    pub fn from_toml<E>(toml: &str) -> Result<Self, ConfigError<E>> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    ///Writes the config as TOML
+    pub fn to_toml<E>(&self) -> Result<String, ConfigError<E>> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    ///Gets the [`SeeAgainGaps`] from [`Self::gaps`]
+    #[must_use]
+    pub fn sag(&self) -> SeeAgainGaps {
+        (1..)
+            .zip(&self.gaps)
+            .map(|(streak, gap)| (streak, gap.0))
+            .collect()
+    }
+
+    ///Gets the [`SessionOptions`] for a normal review session
+    #[must_use]
+    pub fn session_options(&self) -> SessionOptions {
+        SessionOptions {
+            limit: (self.session.limit > 0).then_some(self.session.limit),
+            order: self.session.order,
+            requeue_gap: self.session.requeue_gap,
+            ..SessionOptions::default()
+        }
+    }
+
+    ///Gets the [`SessionOptions`] for a cram session - there is never a limit
+    #[must_use]
+    pub fn cram_options(&self) -> SessionOptions {
+        SessionOptions {
+            order: self.session.order,
+            requeue_gap: self.session.requeue_gap,
+            ..SessionOptions::cram()
+        }
+    }
+
+    ///Gets the [`ExamOptions`]
+    #[must_use]
+    pub fn exam_options(&self) -> ExamOptions {
+        ExamOptions {
+            questions: self.exam.questions,
+            time_limit: (self.exam.time_limit.0 > Duration::zero())
+                .then_some(self.exam.time_limit.0),
+            ..ExamOptions::default()
+        }
+    }
+
+    ///Gets the [`SprintOptions`]
+    #[must_use]
+    pub fn sprint_options(&self) -> SprintOptions {
+        SprintOptions {
+            duration: self.sprint_duration.0,
+            ..SprintOptions::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::{ConfigError, HumanDuration, JankiConfig},
+        dummy_storage::DummyStorage,
+        game::{default_sag, Extrapolation},
+        session::SessionOrder,
+        storage::Storage,
+    };
+    use chrono::Duration;
+
+    #[test]
+    fn human_duration_test() {
+        let parse = |s: &str| s.parse::<HumanDuration>().map(|d| d.0.num_seconds());
+        assert_eq!(parse("10m"), Ok(600));
+        assert_eq!(parse("3d"), Ok(3 * 86_400));
+        assert_eq!(parse("1h 30m"), Ok(5400));
+        assert_eq!(parse("1w2d"), Ok(9 * 86_400));
+        assert_eq!(parse("45"), Ok(45));
+        assert!(parse("").is_err());
+        assert!(parse("10y").is_err());
+        assert!(parse("m").is_err());
+        assert!(parse("99999999999w").is_err());
+        assert!(parse("99999999999999999999").is_err());
+
+        assert_eq!(HumanDuration(Duration::seconds(5400)).to_string(), "1h30m");
+        assert_eq!(HumanDuration(Duration::days(3)).to_string(), "3d");
+        assert_eq!(HumanDuration(Duration::zero()).to_string(), "0s");
+    }
+
+    #[test]
+    fn config_test() {
+        assert_eq!(JankiConfig::default().sag(), default_sag());
+
+        let config = JankiConfig::from_toml::<()>(
+            r#"
+gaps = ["10m", "1d", "3d"]
+
+[session]
+order = "LeastRecent"
+
+[exam]
+time_limit = "0s"
+
+[extrapolation]
+kind = "Multiplicative"
+factor = 1.5
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.sag()[&2], Duration::days(1));
+        assert_eq!(config.sag().len(), 3);
+        assert_eq!(config.session.order, SessionOrder::LeastRecent);
+        assert_eq!(config.session.limit, 20);
+        assert_eq!(config.session_options().limit, Some(20));
+        assert_eq!(config.exam.questions, 10);
+        assert_eq!(
+            config.extrapolation,
+            Extrapolation::Multiplicative { factor: 1.5 }
+        );
+        assert_eq!(config.exam_options().time_limit, None);
+        assert!(matches!(
+            JankiConfig::from_toml::<()>(r#"gaps = ["soon"]"#),
+            Err(ConfigError::TomlDeError(_))
+        ));
+
+        let mut storage = DummyStorage::default();
+        assert_eq!(JankiConfig::load(&storage).unwrap(), JankiConfig::default());
+        storage
+            .write_extra(JankiConfig::JSON_KEY, r#"{"sprint_duration": "2m"}"#)
+            .unwrap();
+        assert_eq!(
+            JankiConfig::load(&storage).unwrap().sprint_duration,
+            HumanDuration(Duration::minutes(2))
+        );
+        config.save(&mut storage).unwrap();
+        assert_eq!(JankiConfig::load(&storage).unwrap(), config);
+    }
+}
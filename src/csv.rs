@@ -1,6 +1,10 @@
-use crate::item::Fact;
+use crate::{
+    answer::{AnswerError, AnswerType, NumericOptions},
+    item::Fact,
+};
 use std::{
     cmp::Ordering,
+    fmt::Write as _,
     io::{Read, Write},
 };
 use thiserror::Error as TError;
@@ -11,7 +15,7 @@ pub enum CSVParseError {
     ///Error for when there aren't enough columns to fill a row, on input like `","` or `"a,"` or `",b"`
     #[error("Not enough columns per row to fill a fact - on input {0:?}")]
     NotEnoughCols(String),
-    ///Error for when there are too many columns to fill a row, on input like `"a,b,c"`
+    ///Error for when there are too many columns to fill a row, on input like `"a,b,c"` - the only third column allowed is the [`AnswerType`], like `"a,b,pattern"`
     #[error("Too many columns - ambiguous how to fill a fact - on input {0:?}")]
     TooManyCols(String),
     ///A wrapper over [`std::io::Error`] for errors reading files.
@@ -19,56 +23,189 @@ pub enum CSVParseError {
     ReadError(#[from] std::io::Error),
 }
 
-///Function to read in a `Vec<Fact>` from a set path.
+///The third column for a fact with an [`AnswerType::Pattern`]
+const PATTERN_COLUMN: &str = "pattern";
+///The third column for a fact with an [`AnswerType::Text`] - the same as leaving it out
+const TEXT_COLUMN: &str = "text";
+///The start of the third column for a fact with an [`AnswerType::Numeric`] - optionally followed by tolerances and [`NO_UNITS`], like `number 0.5 2% nounits`
+const NUMBER_COLUMN: &str = "number";
+///Turns off [`NumericOptions::parse_units`] in a [`NUMBER_COLUMN`]
+const NO_UNITS: &str = "nounits";
+
+///The facts read in from a CSV file by [`read_in`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CsvImport {
+    ///Every fact in the file
+    pub facts: Vec<Fact>,
+    ///The rows which had an [`AnswerType`] that wasn't valid, which were read in as [`AnswerType::Text`] instead - for showing to the user
+    pub invalid: Vec<InvalidRow>,
+}
+
+///A row in a CSV file with an [`AnswerType`] that wasn't valid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidRow {
+    ///The line number, starting from 1
+    pub line: usize,
+    ///The row, as it was in the file
+    pub row: String,
+    ///Why the [`AnswerType`] wasn't valid
+    pub error: AnswerError,
+}
+
+///Function to read in a [`CsvImport`] from a reader.
+///
+///Each row is a term and a definition, and then optionally the [`AnswerType`]:
+/// - `text` for [`AnswerType::Text`], the same as leaving it out
+/// - `pattern` for [`AnswerType::Pattern`]s, like `cell powerhouse,(the )?mitochondria,pattern`
+/// - `number` for [`AnswerType::Numeric`]s, like `g,9.81 m/s^2,number`. Tolerances can come after it like in [`NumericOptions::with_tolerance`] - `number 0.5`, `number 2%` or both - and `nounits` turns off unit conversion
+///
+///Rows with an answer type that isn't valid are read in as text, so the rest of the file can still be read, and are put in [`CsvImport::invalid`].
 ///
 ///Can return an [`std::error::Error`] if either the file cannot be read or parsed
 #[instrument(skip(reader))]
-pub fn read_in(mut reader: impl Read) -> Result<Vec<Fact>, CSVParseError> {
+pub fn read_in(mut reader: impl Read) -> Result<CsvImport, CSVParseError> {
     info!("Reading in");
 
     let mut contents = String::default();
     reader.read_to_string(&mut contents)?;
 
-    read_in_string(contents)
+    read_in_string(&contents)
 }
 
+///Parses a [`CsvImport`] from the raw contents of a CSV file
 #[instrument]
-fn read_in_string(contents: String) -> Result<Vec<Fact>, CSVParseError> {
+fn read_in_string(contents: &str) -> Result<CsvImport, CSVParseError> {
     info!("Reading in Raw");
 
-    let mut v = vec![];
+    let mut import = CsvImport::default();
 
-    for line in contents.trim().lines() {
+    for (i, line) in contents.trim().lines().enumerate() {
         let mut els: Vec<String> = line.split(',').map(ToString::to_string).collect();
 
+        //the answer type can be given in an optional third column
+        let mut answer_type = Ok(AnswerType::Text);
+        if els.len() == 3 {
+            answer_type = parse_answer_type(els[2].trim())
+                .ok_or_else(|| CSVParseError::TooManyCols(line.to_string()))?;
+            els.pop();
+        }
+
         match els.len().cmp(&2) {
             Ordering::Less => return Err(CSVParseError::NotEnoughCols(line.to_string())),
             Ordering::Greater => return Err(CSVParseError::TooManyCols(line.to_string())),
             Ordering::Equal => {
-                v.push(Fact::new(
-                    els.remove(0).trim().to_string(),
-                    els.remove(0).trim().to_string(),
-                ));
+                let term = els.remove(0).trim().to_string();
+                let definition = els.remove(0).trim().to_string();
+
+                let checked = answer_type.and_then(|answer_type| {
+                    let fact =
+                        Fact::new(term.clone(), definition.clone()).with_answer_type(answer_type);
+                    fact.validate().map(|()| fact)
+                });
+                let fact = checked.unwrap_or_else(|error| {
+                    warn!("Reading in {line:?} as text: {error}");
+                    import.invalid.push(InvalidRow {
+                        line: i + 1,
+                        row: line.to_string(),
+                        error,
+                    });
+                    Fact::new(term, definition)
+                });
+
+                import.facts.push(fact);
             }
         }
     }
 
-    info!("Read in {} facts", v.len());
-    Ok(v)
+    info!("Read in {} facts", import.facts.len());
+    Ok(import)
+}
+
+///Reads the [`AnswerType`] from a third column - or [`Option::None`] if it isn't an answer type at all
+fn parse_answer_type(column: &str) -> Option<Result<AnswerType, AnswerError>> {
+    match column {
+        PATTERN_COLUMN => return Some(Ok(AnswerType::Pattern)),
+        TEXT_COLUMN => return Some(Ok(AnswerType::Text)),
+        _ => {}
+    }
+
+    let mut parts = column.split_whitespace();
+    if parts.next() != Some(NUMBER_COLUMN) {
+        return None;
+    }
+
+    let mut options = NumericOptions::default();
+    let mut tolerances = vec![];
+    for part in parts {
+        if part == NO_UNITS {
+            options.parse_units = false;
+        } else {
+            tolerances.push(part);
+        }
+    }
+
+    //if any tolerances are given, they replace both of the defaults
+    if !tolerances.is_empty() {
+        options.absolute_tolerance = 0.0;
+        options.relative_tolerance = 0.0;
+    }
+    for tolerance in tolerances {
+        let parsed = match options.with_tolerance(tolerance) {
+            Ok(parsed) => parsed,
+            Err(e) => return Some(Err(e)),
+        };
+        if tolerance.ends_with('%') {
+            options.relative_tolerance = parsed.relative_tolerance;
+        } else {
+            options.absolute_tolerance = parsed.absolute_tolerance;
+        }
+    }
+
+    Some(Ok(AnswerType::Numeric(options)))
+}
+
+///Gets the third column for an [`AnswerType`], or [`Option::None`] for [`AnswerType::Text`] which doesn't need one
+fn answer_type_column(answer_type: AnswerType) -> Option<String> {
+    match answer_type {
+        AnswerType::Text => None,
+        AnswerType::Pattern => Some(PATTERN_COLUMN.to_string()),
+        AnswerType::Numeric(options) if options == NumericOptions::default() => {
+            Some(NUMBER_COLUMN.to_string())
+        }
+        AnswerType::Numeric(options) => {
+            let mut column = NUMBER_COLUMN.to_string();
+            //with no tolerances at all, an absolute one of 0 is written so that the defaults aren't used
+            if options.absolute_tolerance > 0.0 || options.relative_tolerance <= 0.0 {
+                let _ = write!(column, " {}", options.absolute_tolerance);
+            }
+            if options.relative_tolerance > 0.0 {
+                let _ = write!(column, " {}%", options.relative_tolerance * 100.0);
+            }
+            if !options.parse_units {
+                let _ = write!(column, " {NO_UNITS}");
+            }
+            Some(column)
+        }
+    }
 }
 
 ///Writes out a `Vec<Fact>` to a set path, overwriting any current contents.
 ///
+///Facts that aren't [`AnswerType::Text`] have their answer type in a third column, so they are read back in the same way - see [`read_in`].
+///
 ///Can return an [`std::io::Error`] if the file cannot be written
 #[instrument(skip(writer, db), fields(db_len = ?db.len()))]
 pub fn write_out(mut writer: impl Write, mut db: Vec<Fact>) -> Result<(), std::io::Error> {
     db.dedup();
     info!("Writing list");
 
-    let tbw: String = db
-        .into_iter()
-        .map(|f| format!("{},{}\n", f.term, f.definition))
-        .collect();
+    let tbw = db.into_iter().fold(String::new(), |mut acc, f| {
+        let _ = match answer_type_column(f.answer_type) {
+            Some(column) => writeln!(acc, "{},{},{column}", f.term, f.definition),
+            None => writeln!(acc, "{},{}", f.term, f.definition),
+        };
+        acc
+    });
 
     write!(writer, "{tbw}")?;
 
@@ -76,8 +213,10 @@ pub fn write_out(mut writer: impl Write, mut db: Vec<Fact>) -> Result<(), std::i
 }
 
 #[cfg(test)]
+#[allow(clippy::cmp_owned, clippy::manual_assert)] //the tests were written before these lints
 mod tests {
     use crate::{
+        answer::{AnswerError, AnswerType, NumericOptions},
         csv::{read_in_string, write_out, CSVParseError},
         test_utils::{f, string_wrapper::StringWrapper},
     };
@@ -101,8 +240,8 @@ hello world,goodbye1
                 f("Привет", "мир"),
             ];
             assert!(matches!(
-                read_in_string(fine_contents.to_string()),
-                Ok(ans) if ans == fine_answers
+                read_in_string(fine_contents),
+                Ok(ans) if ans.facts == fine_answers && ans.invalid.is_empty()
             ));
         }
 
@@ -111,7 +250,7 @@ hello world,goodbye1
 a
 c,d
 "#;
-            let nec = read_in_string(too_few.to_string());
+            let nec = read_in_string(too_few);
             if let Err(CSVParseError::NotEnoughCols(s)) = nec {
                 if s != String::from("a") {
                     panic!("Incorrect Data: {s}");
@@ -126,7 +265,7 @@ c,d
 a,b,c
 a,b
 "#;
-            let tmc = read_in_string(too_many.to_string());
+            let tmc = read_in_string(too_many);
             if let Err(CSVParseError::TooManyCols(s)) = tmc {
                 if s != String::from("a,b,c") {
                     panic!("Incorrect Data: {s}")
@@ -137,6 +276,113 @@ a,b
         }
     }
 
+    #[test]
+    pub fn test_read_in_patterns() {
+        let patterns = r#"cell powerhouse,(the )?mitochondria,pattern
+root,/usr/
+home,/home/,text
+bad,(b,pattern
+"#;
+        let import = read_in_string(patterns).unwrap();
+        assert_eq!(
+            import.facts,
+            vec![
+                f("cell powerhouse", "(the )?mitochondria").with_answer_type(AnswerType::Pattern),
+                f("root", "/usr/"),
+                f("home", "/home/"),
+                //invalid patterns are read in as text, rather than stopping the import
+                f("bad", "(b"),
+            ]
+        );
+        assert_eq!(import.invalid.len(), 1);
+        assert_eq!(import.invalid[0].line, 4);
+        assert_eq!(import.invalid[0].row, "bad,(b,pattern");
+        assert!(matches!(
+            import.invalid[0].error,
+            AnswerError::InvalidPattern(..)
+        ));
+
+        let unknown = read_in_string("a,b,c");
+        if let Err(CSVParseError::TooManyCols(s)) = unknown {
+            if s != String::from("a,b,c") {
+                panic!("Incorrect Data: {s}")
+            }
+        } else {
+            panic!("Doesn't match! {unknown:?}");
+        }
+    }
+
+    #[test]
+    pub fn test_round_trip() {
+        let facts = vec![
+            f("root", "/usr/"),
+            f("cell powerhouse", "(the )?mitochondria").with_answer_type(AnswerType::Pattern),
+        ];
+        let mut st = StringWrapper::default();
+        write_out(&mut st, facts.clone()).unwrap();
+        assert!(matches!(
+            read_in_string(&st.to_inner()),
+            Ok(ans) if ans.facts == facts
+        ));
+    }
+
+    #[test]
+    pub fn test_numbers() {
+        let numbers = r#"g,9.81 m/s^2,number
+c,3e8,number 1000 2%
+e,2.718,number 0 nounits
+bad,about nine,number
+worse,1,number -5%
+"#;
+        let import = read_in_string(numbers).unwrap();
+        let numeric = |absolute_tolerance, relative_tolerance, parse_units| {
+            AnswerType::Numeric(NumericOptions {
+                absolute_tolerance,
+                relative_tolerance,
+                parse_units,
+            })
+        };
+        assert_eq!(
+            import.facts,
+            vec![
+                f("g", "9.81 m/s^2")
+                    .with_answer_type(AnswerType::Numeric(NumericOptions::default())),
+                f("c", "3e8").with_answer_type(numeric(1000.0, 0.02, true)),
+                f("e", "2.718").with_answer_type(numeric(0.0, 0.0, false)),
+                f("bad", "about nine"),
+                f("worse", "1"),
+            ]
+        );
+        let invalid: Vec<(usize, AnswerError)> = import
+            .invalid
+            .into_iter()
+            .map(|row| (row.line, row.error))
+            .collect();
+        assert_eq!(
+            invalid,
+            vec![
+                (4, AnswerError::InvalidNumber("about nine".into())),
+                (5, AnswerError::InvalidTolerance("-5%".into())),
+            ]
+        );
+
+        //numeric facts keep their tolerances through an export and import
+        let facts = vec![
+            f("g", "9.81 m/s^2").with_answer_type(AnswerType::Numeric(NumericOptions::default())),
+            f("c", "3e8").with_answer_type(numeric(1000.0, 0.02, true)),
+            f("half", "0.5").with_answer_type(numeric(0.0, 0.05, true)),
+            f("e", "2.718").with_answer_type(numeric(0.0, 0.0, false)),
+        ];
+        let mut st = StringWrapper::default();
+        write_out(&mut st, facts.clone()).unwrap();
+        let written = st.to_inner();
+        assert_eq!(
+            written,
+            "g,9.81 m/s^2,number\nc,3e8,number 1000 2%\nhalf,0.5,number 5%\ne,2.718,number 0 nounits\n"
+        );
+        assert_eq!(read_in_string(&written).unwrap().facts, facts);
+    }
+
     #[test]
     pub fn test_write_out() {
         let unicode = vec![
@@ -157,5 +403,13 @@ hello world,goodbye1
 "#;
         write_out(&mut unicode_st, unicode).unwrap();
         assert_eq!(unicode_st.to_inner(), correct_unicode.to_string());
+
+        let mut pattern_st = StringWrapper::default();
+        write_out(
+            &mut pattern_st,
+            vec![f("a", "(the )?b").with_answer_type(AnswerType::Pattern)],
+        )
+        .unwrap();
+        assert_eq!(pattern_st.to_inner(), "a,(the )?b,pattern\n");
     }
 }
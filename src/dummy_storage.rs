@@ -1,8 +1,9 @@
 use crate::{game::AnkiDB, storage::Storage};
+use std::collections::HashMap;
 
 ///A dummy database - works only in memory, as long as the [`DummyStorage`] hasn't been dropped
 #[derive(Default, Debug)]
-pub struct DummyStorage(AnkiDB);
+pub struct DummyStorage(AnkiDB, HashMap<String, String>);
 
 impl Storage for DummyStorage {
     type ErrorType = ();
@@ -19,6 +20,19 @@ impl Storage for DummyStorage {
         self.0 = db.clone();
         Ok(())
     }
+
+    #[instrument(skip(self))]
+    fn read_extra(&self, key: &str) -> Result<Option<String>, Self::ErrorType> {
+        trace!("Reading extra data from DummyDB");
+        Ok(self.1.get(key).cloned())
+    }
+
+    #[instrument(skip(self, contents))]
+    fn write_extra(&mut self, key: &str, contents: &str) -> Result<(), Self::ErrorType> {
+        trace!("Writing extra data to DummyDB");
+        self.1.insert(key.to_string(), contents.to_string());
+        Ok(())
+    }
 }
 
 ///Trait for [`Storage`] that implements methods that take `dyn` trait objects referenced `self`s.
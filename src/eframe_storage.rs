@@ -1,6 +1,9 @@
-use crate::{game::AnkiDB, storage::Storage as JStorage};
+use crate::{
+    game::AnkiDB,
+    migration::{from_versioned_str, to_versioned_string},
+    storage::Storage as JStorage,
+};
 use eframe::Storage as EStorage;
-use serde_json::{from_str, to_string};
 
 impl JStorage for &dyn EStorage {
     type ErrorType = serde_json::Error;
@@ -8,7 +11,9 @@ impl JStorage for &dyn EStorage {
     #[instrument(skip(self))]
     fn read_db(&self) -> Result<AnkiDB, Self::ErrorType> {
         trace!("Reading &dyn EStorage");
-        from_str(&self.get_string("db").unwrap_or_else(|| "[]".into()))
+        Ok(from_versioned_str(
+            &self.get_string("db").unwrap_or_else(|| "[]".into()),
+        )?)
     }
 
     #[instrument(skip(self))]
@@ -16,6 +21,12 @@ impl JStorage for &dyn EStorage {
         error!("Cannot write to an &dyn EStorage due to mutability.");
         Ok(())
     }
+
+    #[instrument(skip(self))]
+    fn read_extra(&self, key: &str) -> Result<Option<String>, Self::ErrorType> {
+        trace!("Reading extra data from &dyn EStorage");
+        Ok(self.get_string(key))
+    }
 }
 
 impl JStorage for &mut dyn EStorage {
@@ -24,7 +35,7 @@ impl JStorage for &mut dyn EStorage {
     #[instrument(skip(self))]
     fn read_db(&self) -> Result<AnkiDB, Self::ErrorType> {
         trace!("Reading &mut dyn EStorage");
-        Ok(from_str(
+        Ok(from_versioned_str(
             &self.get_string("db").unwrap_or_else(|| "[]".into()),
         )?)
     }
@@ -32,6 +43,19 @@ impl JStorage for &mut dyn EStorage {
     #[instrument(skip(self, db))]
     fn write_db(&mut self, db: &AnkiDB) -> Result<(), Self::ErrorType> {
         trace!("Writing to &mut dyn EStorage");
-        Ok(self.set_string("db", to_string(db)?))
+        Ok(self.set_string("db", to_versioned_string(db)?))
+    }
+
+    #[instrument(skip(self))]
+    fn read_extra(&self, key: &str) -> Result<Option<String>, Self::ErrorType> {
+        trace!("Reading extra data from &mut dyn EStorage");
+        Ok(self.get_string(key))
+    }
+
+    #[instrument(skip(self, contents))]
+    fn write_extra(&mut self, key: &str, contents: &str) -> Result<(), Self::ErrorType> {
+        trace!("Writing extra data to &mut dyn EStorage");
+        self.set_string(key, contents.to_string());
+        Ok(())
     }
 }
@@ -1,10 +1,27 @@
-use eframe::{egui, egui::Context, Frame, Storage as EStorage};
+use eframe::{
+    egui,
+    egui::{
+        plot::{Bar, BarChart, Legend, Plot},
+        Context,
+    },
+    Frame, Storage as EStorage,
+};
+#[cfg(feature = "encryption")]
+use janki::encrypted_storage::{EncryptedStorage, EncryptionHeader, EncryptionKey, KeyParams};
 use janki::{
+    answer::AnswerType,
+    backup::{BackupInfo, BackupManager},
     csv::{read_in, write_out},
     dummy_storage::{DummyStorage, DynStorage},
-    game::{default_sag, AnkiGame, GiveFacts},
+    exam::{Exam, ExamReport},
+    game::{default_sag, AddFactsError, AnkiGame, GiveFacts},
     item::Fact,
-    storage::Storage as JStorage,
+    matching::{MatchingBoard, MatchingResult},
+    profile::{Achievement, Profile},
+    session::{Session, SessionFilter, SessionMode, SessionSummary},
+    sprint::{HighScores, Sprint, SprintResult, SprintState},
+    stats::Stats,
+    storage::{read_extra_json, write_extra_json, Storage as JStorage},
 };
 use std::{fs::File, time::Duration};
 use tracing::Level;
@@ -17,11 +34,44 @@ pub enum JankiState {
     },
     Tested {
         fact: Fact,
-        was_correct: bool,
+        was_correct: Result<bool, String>,
+    },
+    InSession {
+        session: Session,
+        current_text: String,
+        last_result: Option<(Fact, bool)>,
+    },
+    SessionOver(SessionSummary),
+    InExam {
+        exam: Exam,
+        current_text: String,
+    },
+    ExamOver {
+        report: ExamReport,
+        file_name: String,
+    },
+    Matching {
+        board: MatchingBoard,
+        selected_term: Option<usize>,
+        record_results: bool,
+    },
+    MatchingOver(MatchingResult),
+    InSprint {
+        sprint: Sprint,
+        current_text: String,
+        last_correct: Option<bool>,
+    },
+    SprintOver {
+        result: SprintResult,
+        position: Option<usize>,
+        deck: String,
     },
     AddingNew {
         term: String,
         def: String,
+        answer_type: AnswerType,
+        tolerance: String,
+        error: Option<String>,
     },
     Viewing {
         show_defs: bool,
@@ -30,13 +80,36 @@ pub enum JankiState {
     Csv {
         file_name: String,
         overwrite_existing: bool,
+        invalid_rows: Vec<String>,
     },
+    Profile,
+    Stats(Stats),
+    Backups(Vec<BackupInfo>),
+    #[cfg(feature = "encryption")]
+    Encryption {
+        passphrase: String,
+        confirm: String,
+        message: Option<String>,
+    },
+}
+
+///An encrypted collection which is waiting for its passphrase
+#[cfg(feature = "encryption")]
+pub struct Locked {
+    header: EncryptionHeader,
+    passphrase: String,
+    error: Option<String>,
 }
 
 pub struct JankiApp {
     app: AnkiGame<DummyStorage, GiveFacts>,
     has_done_initial_read: bool,
     state: JankiState,
+    high_scores: HighScores,
+    #[cfg(feature = "encryption")]
+    key: Option<EncryptionKey>,
+    #[cfg(feature = "encryption")]
+    locked: Option<Locked>,
 }
 
 impl JankiApp {
@@ -48,12 +121,91 @@ impl JankiApp {
                 show_only_eligible: true,
             },
             has_done_initial_read: false,
+            high_scores: HighScores::default(),
+            #[cfg(feature = "encryption")]
+            key: None,
+            #[cfg(feature = "encryption")]
+            locked: None,
+        }
+    }
+}
+
+impl JankiApp {
+    ///Gets every backup, newest first - or none if backups are turned off or can't be read
+    fn list_backups(&self) -> Vec<BackupInfo> {
+        self.app
+            .backups()
+            .map(|b| {
+                b.list_backups().unwrap_or_else(|e| {
+                    error!("Unable to list backups: {e}");
+                    vec![]
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    ///Reads the collection and high scores from `storage`
+    fn read_all<S: JStorage>(&mut self, storage: &S) {
+        self.app
+            .read_custom(storage)
+            .expect("Failure to read from EGUI storage");
+        self.high_scores = read_extra_json(storage, HighScores::KEY).unwrap_or_else(|e| {
+            error!("Failure to read high scores from EGUI storage: {e}");
+            HighScores::default()
+        });
+    }
+
+    ///Writes the collection and high scores to `storage`
+    fn write_all<S: JStorage>(&mut self, storage: &mut S) {
+        self.app
+            .write_custom(storage)
+            .expect("Failure to write to EGUI storage");
+        write_extra_json(storage, HighScores::KEY, &self.high_scores)
+            .expect("Failure to write high scores to EGUI storage");
+    }
+
+    ///Shows the passphrase prompt for an encrypted collection, and unlocks it once the right one is entered
+    #[cfg(feature = "encryption")]
+    fn show_locked(&mut self, ctx: &Context) {
+        let Some(locked) = &mut self.locked else {
+            return;
+        };
+
+        let mut unlocked = None;
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.label("This collection is encrypted - enter the passphrase to unlock it");
+            ui.add(egui::TextEdit::singleline(&mut locked.passphrase).password(true));
+            if ui.button("Unlock").clicked() {
+                match EncryptionKey::unlock(&locked.header, &locked.passphrase) {
+                    Ok(key) => unlocked = Some(key),
+                    Err(e) => {
+                        warn!("Unable to unlock collection: {e}");
+                        locked.passphrase.clear();
+                        locked.error = Some(e.to_string());
+                    }
+                }
+            }
+            if let Some(error) = &locked.error {
+                ui.label(format!("Unable to unlock: {error}"));
+            }
+        });
+
+        if unlocked.is_some() {
+            //the next save does the initial read, now that it can be decrypted
+            self.key = unlocked;
+            self.locked = None;
         }
     }
 }
 
 impl eframe::App for JankiApp {
     fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
+        #[cfg(feature = "encryption")]
+        if self.locked.is_some() {
+            self.show_locked(ctx);
+            return;
+        }
+
         if self.has_done_initial_read {
             egui::SidePanel::left("left_side").show(ctx, |ui| {
                 if ui.button("New Test").clicked() {
@@ -64,20 +216,67 @@ impl eframe::App for JankiApp {
                             was_eligible,
                         };
                     }
+                } else if ui.button("Start Session").clicked() {
+                    self.state = JankiState::InSession {
+                        session: Session::new(&self.app, &self.app.config().session_options()),
+                        current_text: String::default(),
+                        last_result: None,
+                    };
+                } else if ui.button("Cram Session").clicked() {
+                    self.state = JankiState::InSession {
+                        session: Session::new(&self.app, &self.app.config().cram_options()),
+                        current_text: String::default(),
+                        last_result: None,
+                    };
+                } else if ui.button("Start Exam").clicked() {
+                    self.state = JankiState::InExam {
+                        exam: Exam::new(&self.app, &self.app.config().exam_options()),
+                        current_text: String::default(),
+                    };
+                } else if ui.button("Matching Game").clicked() {
+                    self.state = JankiState::Matching {
+                        board: MatchingBoard::new(&self.app, 6, &SessionFilter::default()),
+                        selected_term: None,
+                        record_results: false,
+                    };
+                } else if ui.button("Sprint").clicked() {
+                    self.state = JankiState::InSprint {
+                        sprint: Sprint::new(&self.app, &self.app.config().sprint_options()),
+                        current_text: String::default(),
+                        last_correct: None,
+                    };
                 } else if ui.button("Add More").clicked() {
                     self.state = JankiState::AddingNew {
                         term: String::default(),
                         def: String::default(),
+                        answer_type: AnswerType::Text,
+                        tolerance: String::default(),
+                        error: None,
                     }
                 } else if ui.button("View").clicked() {
                     self.state = JankiState::Viewing {
                         show_defs: false,
                         show_only_eligible: true,
                     }
+                } else if ui.button("Profile").clicked() {
+                    self.state = JankiState::Profile;
+                } else if ui.button("Stats").clicked() {
+                    self.state = JankiState::Stats(self.app.stats());
+                } else if ui.button("Backups").clicked() {
+                    self.state = JankiState::Backups(self.list_backups());
                 } else if ui.button("CSV Utilities").clicked() {
                     self.state = JankiState::Csv {
                         file_name: "./data.csv".into(),
                         overwrite_existing: false,
+                        invalid_rows: vec![],
+                    };
+                }
+                #[cfg(feature = "encryption")]
+                if ui.button("Encryption").clicked() {
+                    self.state = JankiState::Encryption {
+                        passphrase: String::default(),
+                        confirm: String::default(),
+                        message: None,
                     };
                 }
 
@@ -93,10 +292,43 @@ impl eframe::App for JankiApp {
                     ui.separator();
                 }
 
-                ui.label(format!(
-                    "Only {} Facts remaining this session!",
-                    self.app.get_eligible_no(),
-                ));
+                if let JankiState::InSession { session, .. } = &self.state {
+                    let progress = session.progress(&self.app);
+                    ui.label(format!(
+                        "{} done, {} remaining - {} correct, {} wrong",
+                        progress.done, progress.remaining, progress.correct, progress.incorrect
+                    ));
+                } else if let JankiState::InSprint { sprint, .. } = &self.state {
+                    ui.label(format!(
+                        "Score: {} - {}x combo",
+                        sprint.score(),
+                        sprint.multiplier()
+                    ));
+                    ui.label(format!(
+                        "{}s left",
+                        sprint.time_remaining(&self.app).num_seconds()
+                    ));
+                    ctx.request_repaint();
+                } else if let JankiState::Matching { board, .. } = &self.state {
+                    ui.label(format!(
+                        "{} mistakes, {}s",
+                        board.mistakes(),
+                        board.elapsed(&self.app).num_seconds()
+                    ));
+                    ctx.request_repaint();
+                } else if let JankiState::InExam { exam, .. } = &self.state {
+                    let (question, total) = exam.position();
+                    ui.label(format!("Question {question} of {total}"));
+                    if let Some(remaining) = exam.time_remaining(&self.app) {
+                        ui.label(format!("{}s left", remaining.num_seconds()));
+                        ctx.request_repaint();
+                    }
+                } else {
+                    ui.label(format!(
+                        "Only {} Facts remaining this session!",
+                        self.app.get_eligible_no(),
+                    ));
+                }
             });
 
             egui::CentralPanel::default().show(ctx, |ui| {
@@ -123,8 +355,14 @@ impl eframe::App for JankiApp {
                         ui.separator();
 
                         if ui.button("Submit!").clicked() {
-                            let was_correct = current_text.trim() == current_fact.definition;
-                            self.app.finish_current_fact(Some(was_correct));
+                            //if the answer can't be checked, it isn't recorded - like in sessions and the CLI
+                            let was_correct = current_fact.check(current_text).map_err(|e| {
+                                error!("Error checking answer: {e}");
+                                e.to_string()
+                            });
+                            if let Err(e) = self.app.finish_current_fact(was_correct.clone().ok()) {
+                                error!("Unable to record answer: {e:?}");
+                            }
 
                             self.state = JankiState::Tested {
                                 fact: current_fact.clone(),
@@ -132,14 +370,328 @@ impl eframe::App for JankiApp {
                             };
                         }
                     }
-                    JankiState::Tested { fact, was_correct } => {
-                        if *was_correct {
+                    JankiState::Tested { fact, was_correct } => match was_correct {
+                        Ok(true) => {
                             ui.label("Correct!");
-                        } else {
+                        }
+                        Ok(false) => {
                             ui.label(format!("Wrong - it should've been {:?}", fact.definition));
                         }
+                        Err(e) => {
+                            ui.label(format!(
+                                "Unable to check answer, so it wasn't recorded: {e}"
+                            ));
+                        }
+                    },
+                    JankiState::InSession {
+                        session,
+                        current_text,
+                        last_result,
+                    } => {
+                        if session.mode() == SessionMode::Cram {
+                            ui.label("Cramming - this won't change when facts are due");
+                        } else {
+                            ui.label("Session");
+                        }
+                        ui.separator();
+
+                        if let Some((fact, was_correct)) = last_result {
+                            if *was_correct {
+                                ui.label("Correct!");
+                            } else {
+                                ui.label(format!(
+                                    "Wrong - {:?} should've been {:?}",
+                                    fact.term, fact.definition
+                                ));
+                            }
+                            ui.separator();
+                        }
+
+                        let mut finished = session.is_finished(&self.app);
+                        if let Some(current_fact) = session.current_fact(&self.app) {
+                            ui.label(format!("The term is: {}", current_fact.term));
+
+                            ui.horizontal(|ui| {
+                                ui.label("Please enter the definition: ");
+                                ui.text_edit_singleline(current_text);
+                            });
+
+                            ui.separator();
+
+                            if ui.button("Submit!").clicked() {
+                                match session.answer(&mut self.app, current_text) {
+                                    Ok(was_correct) => {
+                                        *last_result = Some((current_fact, was_correct));
+                                        current_text.clear();
+                                    }
+                                    Err(e) => error!("Error checking answer: {e}"),
+                                }
+                                finished = session.is_finished(&self.app);
+                            }
+                            if ui.button("Stop Session").clicked() {
+                                finished = true;
+                            }
+                        }
+
+                        if finished {
+                            self.state = JankiState::SessionOver(session.summary(&self.app));
+                        }
+                    }
+                    JankiState::SessionOver(summary) => {
+                        ui.label("Session Over!");
+                        ui.separator();
+
+                        ui.label(format!(
+                            "{}/{} correct ({:.0}%) over {} items in {}s",
+                            summary.correct,
+                            summary.reviews,
+                            summary.accuracy * 100.0,
+                            summary.items,
+                            summary.duration.num_seconds()
+                        ));
+
+                        if !summary.missed.is_empty() {
+                            ui.separator();
+                            ui.label("Missed facts:");
+                            egui::ScrollArea::vertical().show(ui, |ui| {
+                                for (fact, times) in &summary.missed {
+                                    ui.label(format!(
+                                        "{} - {} (missed {times} time(s))",
+                                        fact.term, fact.definition
+                                    ));
+                                }
+                            });
+                        }
+                    }
+                    JankiState::InExam { exam, current_text } => {
+                        ui.label("Exam");
+                        ui.separator();
+
+                        if let Some(current_fact) = exam.current_fact(&self.app) {
+                            ui.label(format!("The term is: {}", current_fact.term));
+
+                            ui.horizontal(|ui| {
+                                ui.label("Please enter the definition: ");
+                                ui.text_edit_singleline(current_text);
+                            });
+
+                            ui.separator();
+
+                            if ui.button("Next").clicked() {
+                                exam.answer(&self.app, current_text);
+                                current_text.clear();
+                            }
+                        }
+
+                        if exam.is_finished(&self.app) || ui.button("Finish Exam").clicked() {
+                            let exam = exam.clone();
+                            self.state = JankiState::ExamOver {
+                                report: exam.finish(&mut self.app),
+                                file_name: "./exam_report.json".into(),
+                            };
+                        }
+                    }
+                    JankiState::ExamOver { report, file_name } => {
+                        ui.label("Exam Over!");
+                        ui.separator();
+
+                        ui.label(format!(
+                            "{}/{} correct ({:.0}%) in {}s",
+                            report.correct,
+                            report.results.len(),
+                            report.percentage,
+                            report.time_taken_ms / 1000
+                        ));
+                        if report.timed_out {
+                            ui.label("Out of time!");
+                        }
+
+                        ui.separator();
+                        egui::ScrollArea::vertical().show(ui, |ui| {
+                            for r in &report.results {
+                                ui.label(format!(
+                                    "{} {} - you said {:?}, the answer is {:?}",
+                                    match (&r.check_error, r.correct) {
+                                        (Some(e), _) => format!("Couldn't check ({e})"),
+                                        (None, true) => "Correct".into(),
+                                        (None, false) => "Wrong".into(),
+                                    },
+                                    r.term,
+                                    r.answer.as_deref().unwrap_or_default(),
+                                    r.definition
+                                ));
+                            }
+                        });
+
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.label("Export to (.json or .csv)");
+                            ui.text_edit_singleline(file_name);
+                        });
+                        if ui.button("Export report").clicked() {
+                            match File::create(&file_name) {
+                                Ok(file) => {
+                                    let result = if file_name.ends_with(".csv") {
+                                        report.write_csv(file)
+                                    } else {
+                                        serde_json::to_writer_pretty(file, report)
+                                            .map_err(Into::into)
+                                    };
+                                    if let Err(e) = result {
+                                        error!("Error exporting report: {e}");
+                                    }
+                                }
+                                Err(e) => error!("Error creating file: {e}"),
+                            }
+                        }
                     }
-                    JankiState::AddingNew { term, def } => {
+                    JankiState::Matching {
+                        board,
+                        selected_term,
+                        record_results,
+                    } => {
+                        ui.label("Match each term with its definition");
+                        ui.checkbox(record_results, "Count towards each fact's history");
+                        ui.separator();
+
+                        ui.columns(2, |columns| {
+                            for (index, term) in board.terms().into_iter().enumerate() {
+                                let enabled = !board.is_term_matched(index);
+                                let selected = *selected_term == Some(index);
+                                if columns[0]
+                                    .add_enabled(
+                                        enabled,
+                                        egui::SelectableLabel::new(selected, term),
+                                    )
+                                    .clicked()
+                                {
+                                    *selected_term = Some(index);
+                                }
+                            }
+
+                            let mut chosen_definition = None;
+                            for (index, definition) in board.definitions().into_iter().enumerate() {
+                                let enabled = !board.is_definition_matched(index);
+                                if columns[1]
+                                    .add_enabled(enabled, egui::Button::new(definition))
+                                    .clicked()
+                                {
+                                    chosen_definition = Some(index);
+                                }
+                            }
+
+                            if let (Some(term), Some(definition)) =
+                                (*selected_term, chosen_definition)
+                            {
+                                board.try_match(&self.app, term, definition);
+                                *selected_term = None;
+                            }
+                        });
+
+                        ui.separator();
+                        if board.is_finished() || ui.button("Give Up").clicked() {
+                            let board = board.clone();
+                            let record_results = *record_results;
+                            self.state = JankiState::MatchingOver(
+                                board.finish(&mut self.app, record_results),
+                            );
+                        }
+                    }
+                    JankiState::MatchingOver(result) => {
+                        ui.label("Matching Over!");
+                        ui.separator();
+                        ui.label(format!(
+                            "Matched {}/{} pairs with {} mistakes in {}s",
+                            result.matched,
+                            result.pairs,
+                            result.mistakes,
+                            result.time_taken.num_seconds()
+                        ));
+                    }
+                    JankiState::InSprint {
+                        sprint,
+                        current_text,
+                        last_correct,
+                    } => {
+                        ui.label("Sprint - answer as many as you can!");
+                        ui.separator();
+
+                        match sprint.state(&self.app) {
+                            SprintState::Ready => {
+                                if ui.button("Go!").clicked() {
+                                    sprint.start(&self.app);
+                                }
+                            }
+                            SprintState::Running => {
+                                match last_correct {
+                                    Some(true) => {
+                                        ui.label(format!("Correct! {} in a row", sprint.combo()));
+                                    }
+                                    Some(false) => {
+                                        ui.label("Wrong - combo lost");
+                                    }
+                                    None => {}
+                                }
+
+                                if let Some(current_fact) = sprint.current_fact(&self.app) {
+                                    ui.label(format!("The term is: {}", current_fact.term));
+                                }
+
+                                let response = ui.text_edit_singleline(current_text);
+                                if response.lost_focus() && ui.input().key_pressed(egui::Key::Enter)
+                                {
+                                    match sprint.answer(&mut self.app, current_text) {
+                                        Ok(correct) => *last_correct = Some(correct),
+                                        Err(e) => error!("Error checking answer: {e}"),
+                                    }
+                                    current_text.clear();
+                                }
+                                response.request_focus();
+                            }
+                            SprintState::Finished => {
+                                let result = sprint.result(&self.app);
+                                self.state = JankiState::SprintOver {
+                                    result,
+                                    position: self.high_scores.submit(sprint.deck(), result),
+                                    deck: sprint.deck().to_string(),
+                                };
+                            }
+                        }
+                    }
+                    JankiState::SprintOver {
+                        result,
+                        position,
+                        deck,
+                    } => {
+                        ui.label("Sprint Over!");
+                        ui.label(format!(
+                            "Scored {} with {} correct, {} wrong and a best combo of {}",
+                            result.score, result.correct, result.incorrect, result.best_combo
+                        ));
+                        if let Some(position) = position {
+                            ui.label(format!("New high score - number {}!", *position + 1));
+                        }
+
+                        ui.separator();
+                        ui.label(format!("High Scores for {deck}"));
+                        for (index, score) in self.high_scores.get(deck).iter().enumerate() {
+                            ui.label(format!(
+                                "{}. {} ({} correct, best combo {}) - {}",
+                                index + 1,
+                                score.score,
+                                score.correct,
+                                score.best_combo,
+                                score.at.format("%Y-%m-%d %H:%M")
+                            ));
+                        }
+                    }
+                    JankiState::AddingNew {
+                        term,
+                        def,
+                        answer_type,
+                        tolerance,
+                        error,
+                    } => {
                         ui.label("Add New Stuff");
                         ui.separator();
 
@@ -151,13 +703,291 @@ impl eframe::App for JankiApp {
                             ui.label("Enter a definition: ");
                             ui.text_edit_singleline(def);
                         });
+                        ui.horizontal(|ui| {
+                            ui.radio_value(answer_type, AnswerType::Text, "Text");
+                            ui.radio_value(
+                                answer_type,
+                                AnswerType::Numeric(self.app.config().answers.numeric),
+                                "Numeric (with tolerance and units)",
+                            );
+                            ui.radio_value(answer_type, AnswerType::Pattern, "Pattern");
+                        });
+                        if matches!(answer_type, AnswerType::Numeric(_)) {
+                            ui.horizontal(|ui| {
+                                ui.label(
+                                    "Tolerance, like 0.5 or 2% (leave empty for the default): ",
+                                );
+                                ui.text_edit_singleline(tolerance);
+                            });
+                        }
 
                         if ui.button("Submit").clicked() {
-                            self.app
-                                .add_fact(Fact::new(term.to_string(), def.to_string()));
-                            term.clear();
-                            def.clear();
+                            let answer_type = match *answer_type {
+                                AnswerType::Numeric(options) => {
+                                    options.with_tolerance(tolerance).map(AnswerType::Numeric)
+                                }
+                                answer_type => Ok(answer_type),
+                            };
+                            match answer_type
+                                .map_err(AddFactsError::from)
+                                .and_then(|answer_type| {
+                                    self.app.add_fact(
+                                        Fact::new(term.to_string(), def.to_string())
+                                            .with_answer_type(answer_type),
+                                    )
+                                }) {
+                                Ok(()) => {
+                                    term.clear();
+                                    def.clear();
+                                    *error = None;
+                                }
+                                Err(e) => {
+                                    error!("Error adding fact: {e}");
+                                    *error = Some(e.to_string());
+                                }
+                            }
                         }
+
+                        if let Some(error) = error {
+                            ui.label(format!("Unable to add fact: {error}"));
+                        }
+                    }
+                    JankiState::Profile => {
+                        let profile = self.app.profile();
+                        let now = chrono::Utc::now();
+
+                        ui.label(format!("Level {}", profile.level()));
+                        ui.add(
+                            egui::ProgressBar::new(profile.level_progress() as f32)
+                                .show_percentage(),
+                        );
+                        ui.label(format!(
+                            "{} XP - {} more for the next level",
+                            profile.xp,
+                            Profile::xp_for_level(profile.level() + 1) - profile.xp
+                        ));
+                        ui.label(format!(
+                            "{} answers, {} correct",
+                            profile.reviews, profile.correct
+                        ));
+                        ui.label(format!(
+                            "Current streak: {} days, longest: {} days",
+                            profile.current_streak(now),
+                            profile.longest_streak
+                        ));
+
+                        ui.separator();
+                        ui.label("Achievements:");
+
+                        egui::ScrollArea::vertical().show(ui, |ui| {
+                            for a in Achievement::ALL {
+                                ui.horizontal(|ui| {
+                                    match profile.achievements.get(&a) {
+                                        Some(at) => ui.label(format!(
+                                            "✔ {} - {} (unlocked {})",
+                                            a.name(),
+                                            a.description(),
+                                            at.format("%Y-%m-%d")
+                                        )),
+                                        None => ui.label(format!(
+                                            "🔒 {} - {}",
+                                            a.name(),
+                                            a.description()
+                                        )),
+                                    };
+                                });
+                            }
+                        });
+                    }
+                    JankiState::Backups(backups) => {
+                        if self.app.backups().is_none() {
+                            ui.label("Backups are turned off");
+                        }
+                        ui.label(format!("{} backups", backups.len()));
+                        ui.separator();
+
+                        let mut restored = false;
+                        egui::ScrollArea::vertical().show(ui, |ui| {
+                            for backup in backups.iter() {
+                                ui.horizontal(|ui| {
+                                    ui.label(
+                                        backup.created.format("%Y-%m-%d %H:%M:%S").to_string(),
+                                    );
+                                    if ui.button("Restore").clicked() {
+                                        match self.app.restore_backup(backup) {
+                                            Ok(()) => restored = true,
+                                            Err(e) => error!("Unable to restore backup: {e}"),
+                                        }
+                                    }
+                                });
+                            }
+                        });
+
+                        if restored {
+                            self.state = JankiState::Viewing {
+                                show_defs: false,
+                                show_only_eligible: true,
+                            };
+                        }
+                    }
+                    #[cfg(feature = "encryption")]
+                    JankiState::Encryption {
+                        passphrase,
+                        confirm,
+                        message,
+                    } => {
+                        if self.key.is_some() {
+                            ui.label("This collection is encrypted with a passphrase");
+                            ui.label("Backups are turned off, as they aren't encrypted");
+                        } else {
+                            ui.label("This collection isn't encrypted");
+                        }
+                        ui.separator();
+
+                        ui.horizontal(|ui| {
+                            ui.label("New passphrase: ");
+                            ui.add(egui::TextEdit::singleline(passphrase).password(true));
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Confirm passphrase: ");
+                            ui.add(egui::TextEdit::singleline(confirm).password(true));
+                        });
+
+                        if ui.button("Set passphrase").clicked() {
+                            if passphrase.is_empty() {
+                                *message = Some("The passphrase can't be empty".into());
+                            } else if passphrase != confirm {
+                                *message = Some("The passphrases don't match".into());
+                            } else {
+                                match EncryptionKey::new(passphrase, KeyParams::default()) {
+                                    Ok(key) => {
+                                        info!("Encrypting collection with a new passphrase");
+                                        self.key = Some(key);
+                                        //backups go through the game's `DummyStorage` rather than the encrypted one, so they would be left unencrypted
+                                        self.app.set_backups(None);
+                                        *message = Some("Passphrase set".into());
+                                    }
+                                    Err(e) => {
+                                        error!("Unable to make key: {e}");
+                                        *message = Some(e.to_string());
+                                    }
+                                }
+                            }
+                            passphrase.clear();
+                            confirm.clear();
+                        }
+
+                        if let Some(message) = message {
+                            ui.label(message.as_str());
+                        }
+                    }
+                    JankiState::Stats(stats) => {
+                        ui.horizontal(|ui| {
+                            ui.label(format!(
+                                "{} items - {} new, {} learning, {} young, {} mature",
+                                stats.total,
+                                stats.states.new,
+                                stats.states.learning,
+                                stats.states.young,
+                                stats.states.mature
+                            ));
+                            if ui.button("Refresh").clicked() {
+                                *stats = self.app.stats();
+                            }
+                        });
+                        ui.label(format!("Average streak: {:.2}", stats.average_streak));
+
+                        ui.separator();
+
+                        egui::ScrollArea::vertical().show(ui, |ui| {
+                            ui.label("Reviews per day:");
+                            let correct: Vec<Bar> = stats
+                                .reviews_per_day
+                                .iter()
+                                .enumerate()
+                                .map(|(i, day)| {
+                                    Bar::new(i as f64, day.correct as f64)
+                                        .name(day.day.format("%a %d %b"))
+                                })
+                                .collect();
+                            let wrong: Vec<Bar> = stats
+                                .reviews_per_day
+                                .iter()
+                                .enumerate()
+                                .map(|(i, day)| {
+                                    Bar::new(i as f64, (day.reviews - day.correct) as f64)
+                                        .name(day.day.format("%a %d %b"))
+                                })
+                                .collect();
+                            let correct = BarChart::new(correct).name("Correct");
+                            let wrong = BarChart::new(wrong).name("Wrong").stack_on(&[&correct]);
+                            Plot::new("reviews_per_day")
+                                .height(150.0)
+                                .legend(Legend::default())
+                                .allow_zoom(false)
+                                .allow_drag(false)
+                                .show(ui, |plot_ui| {
+                                    plot_ui.bar_chart(correct);
+                                    plot_ui.bar_chart(wrong);
+                                });
+
+                            ui.label("Retention (%):");
+                            let retention: Vec<Bar> = [
+                                ("Last 7 days", stats.retention_week),
+                                ("Last 30 days", stats.retention_month),
+                                ("All time", stats.retention_all),
+                            ]
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, (name, retention))| {
+                                Bar::new(i as f64, retention.rate().unwrap_or(0.0) * 100.0)
+                                    .name(format!("{name} - {} reviews", retention.reviews))
+                            })
+                            .collect();
+                            Plot::new("retention")
+                                .height(150.0)
+                                .include_y(100.0)
+                                .allow_zoom(false)
+                                .allow_drag(false)
+                                .show(ui, |plot_ui| {
+                                    plot_ui.bar_chart(BarChart::new(retention).name("Retention"));
+                                });
+
+                            ui.label("Due in the next few days:");
+                            let forecast: Vec<Bar> = stats
+                                .forecast
+                                .iter()
+                                .enumerate()
+                                .map(|(i, due)| Bar::new(i as f64, *due as f64))
+                                .collect();
+                            Plot::new("forecast")
+                                .height(150.0)
+                                .allow_zoom(false)
+                                .allow_drag(false)
+                                .show(ui, |plot_ui| {
+                                    plot_ui.bar_chart(BarChart::new(forecast).name("Due"));
+                                });
+
+                            ui.label("Streaks:");
+                            let streaks: Vec<Bar> = stats
+                                .streaks
+                                .iter()
+                                .enumerate()
+                                .map(|(streak, count)| Bar::new(streak as f64, *count as f64))
+                                .collect();
+                            Plot::new("streaks")
+                                .height(150.0)
+                                .allow_zoom(false)
+                                .allow_drag(false)
+                                .show(ui, |plot_ui| {
+                                    plot_ui.bar_chart(BarChart::new(streaks).name("Items"));
+                                });
+
+                            ui.label("Hardest items:");
+                            for (fact, difficulty) in &stats.hardest {
+                                ui.label(format!("{} - difficulty {:.2}", fact.term, difficulty));
+                            }
+                        });
                     }
                     JankiState::Viewing {
                         show_defs,
@@ -186,7 +1016,9 @@ impl eframe::App for JankiApp {
                                             }
 
                                             if ui.button("Delete fact").clicked() {
-                                                self.app.delete_at_index(index);
+                                                if let Err(e) = self.app.delete_at_index(index) {
+                                                    error!("Unable to delete fact: {e:?}");
+                                                }
                                             }
                                         });
                                     },
@@ -199,6 +1031,7 @@ impl eframe::App for JankiApp {
                     JankiState::Csv {
                         file_name,
                         overwrite_existing,
+                        invalid_rows,
                     } => {
                         ui.label("CSV Utilities");
                         ui.horizontal(|ui| {
@@ -225,7 +1058,7 @@ impl eframe::App for JankiApp {
                                             //TOOD: communicate this to user
                                         }
                                         Ok(csv_conts) => {
-                                            facts_to_write.extend(csv_conts.into_iter());
+                                            facts_to_write.extend(csv_conts.facts.into_iter());
                                         }
                                     },
                                     Err(e) => error!("Error reading csv file: {e}"),
@@ -254,15 +1087,34 @@ impl eframe::App for JankiApp {
                                         //TOOD: communicate this to user
                                     }
                                     Ok(csv_conts) => {
+                                        *invalid_rows = csv_conts
+                                            .invalid
+                                            .iter()
+                                            .map(|row| {
+                                                format!(
+                                                    "Line {} ({:?}) was read in as text: {}",
+                                                    row.line, row.row, row.error
+                                                )
+                                            })
+                                            .collect();
                                         if *overwrite_existing {
                                             self.app.clear();
                                         }
-                                        self.app.add_facts(csv_conts);
+                                        if let Err(e) = self.app.add_facts(csv_conts.facts) {
+                                            error!("Error adding facts from CSV file: {e}");
+                                        }
                                     }
                                 },
                                 Err(e) => error!("Error reading in CSV file: {e}"),
                             }
                         }
+
+                        if !invalid_rows.is_empty() {
+                            ui.separator();
+                            for row in invalid_rows.iter() {
+                                ui.label(row);
+                            }
+                        }
                     }
                 }
             });
@@ -274,19 +1126,54 @@ impl eframe::App for JankiApp {
         }
     }
 
-    fn save(&mut self, mut storage: &mut dyn EStorage) {
-        if !matches!(self.state, JankiState::Testing { .. }) {
-            if self.has_done_initial_read {
-                self.app
-                    .write_custom(&mut storage as &mut dyn JStorage<ErrorType = serde_json::Error>)
-                    .expect("Failure to write to EGUI storage");
-            } else {
-                self.has_done_initial_read = true;
-                trace!("Doing initial read");
-                self.app
-                    .read_custom(&storage as &dyn JStorage<ErrorType = serde_json::Error>)
-                    .expect("Failure to read from EGUI storage");
+    fn save(&mut self, storage: &mut dyn EStorage) {
+        #[cfg(feature = "encryption")]
+        if self.locked.is_some() {
+            return;
+        }
+        if matches!(self.state, JankiState::Testing { .. }) {
+            return;
+        }
+
+        if self.has_done_initial_read {
+            #[cfg(feature = "encryption")]
+            if let Some(key) = self.key.clone() {
+                self.write_all(&mut EncryptedStorage::with_key(storage, key));
+                return;
             }
+            self.write_all(&mut &mut *storage);
+        } else {
+            trace!("Doing initial read");
+            let storage: &dyn EStorage = storage;
+            #[cfg(feature = "encryption")]
+            {
+                if self.key.is_none() {
+                    match EncryptedStorage::read_header(&storage) {
+                        Ok(Some(header)) => {
+                            info!("Collection is encrypted, asking for the passphrase");
+                            self.locked = Some(Locked {
+                                header,
+                                passphrase: String::default(),
+                                error: None,
+                            });
+                            return;
+                        }
+                        Ok(None) => {}
+                        Err(e) => error!("Unable to read encryption header: {e}"),
+                    }
+                }
+                if let Some(key) = self.key.clone() {
+                    self.has_done_initial_read = true;
+                    self.read_all(&EncryptedStorage::with_key(storage, key));
+                    return;
+                }
+            }
+
+            self.has_done_initial_read = true;
+            self.read_all(&storage);
+            //eframe doesn't say where it keeps the database, so backups are relative to the current directory
+            self.app
+                .set_backups(Some(BackupManager::new(self.app.config().backup.clone())));
         }
     }
 
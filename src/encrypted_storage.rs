@@ -0,0 +1,582 @@
+use crate::{
+    config::JankiConfig,
+    game::AnkiDB,
+    migration::{from_versioned_str, to_versioned_string, MigrationError},
+    optimiser::OptimisedGaps,
+    profile::Profile,
+    sprint::HighScores,
+    storage::Storage,
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::{convert::Infallible, fmt::Debug};
+use thiserror::Error;
+
+///The length of the salt used to derive keys, in bytes
+const SALT_LEN: usize = 16;
+///The length of the nonce stored with every encrypted value, in bytes
+const NONCE_LEN: usize = 24;
+///What is encrypted into [`EncryptionHeader::check`], to tell whether or not a passphrase is right
+const CHECK: &[u8] = b"janki";
+///Every key that janki stores extra data under - these are encrypted when an unencrypted collection is first written to
+const KNOWN_EXTRAS: [&str; 5] = [
+    Profile::KEY,
+    JankiConfig::KEY,
+    JankiConfig::JSON_KEY,
+    HighScores::KEY,
+    OptimisedGaps::KEY,
+];
+
+///Settings for deriving a key from a passphrase with Argon2id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyParams {
+    ///The memory to use, in KiB
+    pub m_cost: u32,
+    ///The number of passes
+    pub t_cost: u32,
+    ///The number of lanes
+    pub p_cost: u32,
+}
+
+impl Default for KeyParams {
+    fn default() -> Self {
+        Self {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+///Everything needed to derive the key again from the passphrase - this isn't secret, and is stored next to the encrypted database using [`EncryptionHeader::KEY`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptionHeader {
+    ///The salt, as base64
+    pub salt: String,
+    ///The settings for Argon2id
+    pub params: KeyParams,
+    ///[`CHECK`], encrypted with the key - if this can't be decrypted, the passphrase is wrong
+    pub check: String,
+}
+
+impl EncryptionHeader {
+    ///The key used to store the header with [`Storage::write_extra`]
+    pub const KEY: &'static str = "encryption.json";
+}
+
+///Error for [`EncryptedStorage`] and [`EncryptionKey`], using `thiserror`
+#[derive(Error, Debug)]
+pub enum EncryptedStorageError<E = Infallible> {
+    ///Error from the inner [`Storage`]
+    #[error("storage error: {0:?}")]
+    StorageError(E),
+
+    ///The passphrase doesn't match the one the database was encrypted with
+    #[error("wrong passphrase")]
+    WrongPassphrase,
+
+    ///Something that was stored can't be decrypted - the passphrase was right, so it has been changed or damaged
+    #[error("unable to decrypt {0:?} - it may have been tampered with")]
+    Corrupt(String),
+
+    ///The inner storage didn't keep the encrypted database, so the unencrypted one was left alone - it needs to store extra data with [`Storage::write_extra`], which does nothing by default
+    #[error(
+        "the inner storage didn't keep the encrypted database - it needs to support extra data"
+    )]
+    ExtrasUnsupported,
+
+    ///Error deriving the key - comes from [`argon2::Error`]
+    #[error("key derivation error: {0}")]
+    KeyError(argon2::Error),
+
+    ///Error with `serde_json` - comes from [`serde_json::Error`]
+    #[error("serde_json error: {0}")]
+    SJError(#[from] serde_json::Error),
+
+    ///Error reading or upgrading the database - comes from [`MigrationError`]
+    #[error("error reading database: {0}")]
+    MigrationError(#[from] MigrationError),
+}
+
+impl EncryptedStorageError {
+    ///Turns an error which can't come from a [`Storage`] into one for any [`Storage`]
+    fn widen<E>(self) -> EncryptedStorageError<E> {
+        match self {
+            Self::StorageError(e) => match e {},
+            Self::WrongPassphrase => EncryptedStorageError::WrongPassphrase,
+            Self::Corrupt(what) => EncryptedStorageError::Corrupt(what),
+            Self::ExtrasUnsupported => EncryptedStorageError::ExtrasUnsupported,
+            Self::KeyError(e) => EncryptedStorageError::KeyError(e),
+            Self::SJError(e) => EncryptedStorageError::SJError(e),
+            Self::MigrationError(e) => EncryptedStorageError::MigrationError(e),
+        }
+    }
+}
+
+///A key derived from a passphrase - deriving one is deliberately slow, so this should be kept rather than derived for every read or write
+#[derive(Clone)]
+pub struct EncryptionKey {
+    ///The cipher, made from the key
+    cipher: XChaCha20Poly1305,
+    ///The header for the key, so that it can be derived again
+    header: EncryptionHeader,
+}
+
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptionKey")
+            .field("header", &self.header)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EncryptionKey {
+    ///Derives a new key from a passphrase, with a new random salt
+    pub fn new(passphrase: &str, params: KeyParams) -> Result<Self, EncryptedStorageError> {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+
+        let cipher = derive(passphrase, &salt, params)?;
+        let mut key = Self {
+            cipher,
+            header: EncryptionHeader {
+                salt: STANDARD.encode(salt),
+                params,
+                check: String::new(),
+            },
+        };
+        key.header.check = key.encrypt(CHECK)?;
+        Ok(key)
+    }
+
+    ///Derives the key for an existing header, and checks that the passphrase is right
+    pub fn unlock(
+        header: &EncryptionHeader,
+        passphrase: &str,
+    ) -> Result<Self, EncryptedStorageError> {
+        let salt = STANDARD
+            .decode(&header.salt)
+            .map_err(|_| EncryptedStorageError::Corrupt("salt".into()))?;
+        let key = Self {
+            cipher: derive(passphrase, &salt, header.params)?,
+            header: header.clone(),
+        };
+
+        match key.decrypt(&header.check, "check") {
+            Ok(check) if check == CHECK => Ok(key),
+            _ => Err(EncryptedStorageError::WrongPassphrase),
+        }
+    }
+
+    ///Gets the header, to store next to the encrypted data
+    #[must_use]
+    pub const fn header(&self) -> &EncryptionHeader {
+        &self.header
+    }
+
+    ///Encrypts some data with a new random nonce, and returns the nonce and the ciphertext together as base64
+    fn encrypt(&self, plaintext: &[u8]) -> Result<String, EncryptedStorageError> {
+        let mut nonce = [0; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut out = nonce.to_vec();
+        out.extend(
+            self.cipher
+                .encrypt(XNonce::from_slice(&nonce), plaintext)
+                .map_err(|_| EncryptedStorageError::Corrupt("plaintext".into()))?,
+        );
+        Ok(STANDARD.encode(out))
+    }
+
+    ///Decrypts something from [`EncryptionKey::encrypt`] - `what` is used in the error if it can't be decrypted
+    fn decrypt(&self, encrypted: &str, what: &str) -> Result<Vec<u8>, EncryptedStorageError> {
+        let corrupt = || EncryptedStorageError::Corrupt(what.into());
+        let bytes = STANDARD.decode(encrypted.trim()).map_err(|_| corrupt())?;
+        if bytes.len() < NONCE_LEN {
+            return Err(corrupt());
+        }
+
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| corrupt())
+    }
+
+    ///Decrypts something from [`EncryptionKey::encrypt`] as a [`String`]
+    fn decrypt_string(&self, encrypted: &str, what: &str) -> Result<String, EncryptedStorageError> {
+        String::from_utf8(self.decrypt(encrypted, what)?)
+            .map_err(|_| EncryptedStorageError::Corrupt(what.into()))
+    }
+}
+
+///Derives a key from a passphrase and salt with Argon2id, and makes a cipher from it
+fn derive(
+    passphrase: &str,
+    salt: &[u8],
+    params: KeyParams,
+) -> Result<XChaCha20Poly1305, EncryptedStorageError> {
+    let params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32))
+        .map_err(EncryptedStorageError::KeyError)?;
+    let mut key = [0; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(EncryptedStorageError::KeyError)?;
+    Ok(XChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+///A wrapper around another [`Storage`] which encrypts everything with a key derived from a passphrase (with Argon2id and XChaCha20-Poly1305).
+///
+///The encrypted database and extra data are all stored with [`Storage::write_extra`] on the inner storage, along with the [`EncryptionHeader`]. An empty database is written with [`Storage::write_db`], so that no unencrypted copy is left behind.
+///
+///If the inner storage isn't encrypted yet, its unencrypted database and extra data are read as they are, and encrypted on the next write
+#[derive(Debug)]
+pub struct EncryptedStorage<S: Storage> {
+    ///The storage that the encrypted data is kept in
+    inner: S,
+    ///The key
+    key: EncryptionKey,
+    ///Whether or not the header for [`EncryptedStorage::key`] has been written yet
+    header_written: bool,
+}
+
+impl<S: Storage> EncryptedStorage<S> {
+    ///The key used to store the encrypted database with [`Storage::write_extra`] on the inner storage
+    pub const DB_KEY: &'static str = "db.enc";
+
+    ///Opens an encrypted storage with a passphrase - if the inner storage is already encrypted, the passphrase is checked, and if not, a new key is made with the default [`KeyParams`] and used from the next write.
+    ///
+    ///Returns [`EncryptedStorageError::WrongPassphrase`] if the passphrase is wrong
+    pub fn open(inner: S, passphrase: &str) -> Result<Self, EncryptedStorageError<S::ErrorType>> {
+        let key = Self::read_header(&inner)?
+            .map_or_else(
+                || EncryptionKey::new(passphrase, KeyParams::default()),
+                |header| EncryptionKey::unlock(&header, passphrase),
+            )
+            .map_err(EncryptedStorageError::widen)?;
+
+        Ok(Self::with_key(inner, key))
+    }
+
+    ///Uses a key which has already been derived or unlocked.
+    ///
+    ///If the inner storage was encrypted with a different key, its header is replaced on the next write - so everything in it should be written again
+    pub const fn with_key(inner: S, key: EncryptionKey) -> Self {
+        Self {
+            inner,
+            key,
+            header_written: false,
+        }
+    }
+
+    ///Reads the [`EncryptionHeader`] from a storage, or [`Option::None`] if it isn't encrypted - use this to check whether or not to ask for a passphrase
+    pub fn read_header(
+        inner: &S,
+    ) -> Result<Option<EncryptionHeader>, EncryptedStorageError<S::ErrorType>> {
+        inner
+            .read_extra(EncryptionHeader::KEY)
+            .map_err(EncryptedStorageError::StorageError)?
+            .map(|header| serde_json::from_str(&header))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    ///Gets the key
+    pub const fn key(&self) -> &EncryptionKey {
+        &self.key
+    }
+
+    ///Gets the inner storage back
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Storage> Storage for EncryptedStorage<S> {
+    type ErrorType = EncryptedStorageError<S::ErrorType>;
+
+    #[instrument(skip(self))]
+    fn read_db(&self) -> Result<AnkiDB, Self::ErrorType> {
+        trace!("Reading from EncryptedStorage");
+        if let Some(contents) = self.read_extra(Self::DB_KEY)? {
+            Ok(from_versioned_str(&contents)?)
+        } else {
+            info!("No encrypted database found, reading the unencrypted one");
+            self.inner
+                .read_db()
+                .map_err(EncryptedStorageError::StorageError)
+        }
+    }
+
+    #[instrument(skip(self, db))]
+    fn write_db(&mut self, db: &AnkiDB) -> Result<(), Self::ErrorType> {
+        trace!("Writing to EncryptedStorage");
+        let contents = to_versioned_string(db)?;
+        self.write_extra(Self::DB_KEY, &contents)?;
+
+        //only get rid of the unencrypted database once the encrypted one is definitely stored
+        if self.read_extra(Self::DB_KEY)?.as_deref() != Some(contents.as_str()) {
+            error!("Inner storage didn't keep the encrypted database, leaving it unencrypted");
+            return Err(EncryptedStorageError::ExtrasUnsupported);
+        }
+        self.inner
+            .write_db(&vec![])
+            .map_err(EncryptedStorageError::StorageError)
+    }
+
+    fn exit_application(&mut self) {
+        self.inner.exit_application();
+    }
+
+    #[instrument(skip(self))]
+    fn read_extra(&self, key: &str) -> Result<Option<String>, Self::ErrorType> {
+        trace!("Reading extra data from EncryptedStorage");
+        let Some(contents) = self
+            .inner
+            .read_extra(key)
+            .map_err(EncryptedStorageError::StorageError)?
+        else {
+            return Ok(None);
+        };
+
+        match self.key.decrypt_string(&contents, key) {
+            Ok(decrypted) => Ok(Some(decrypted)),
+            Err(_) if Self::read_header(&self.inner)?.is_none() => {
+                info!(?key, "Reading unencrypted extra data");
+                Ok(Some(contents))
+            }
+            Err(e) => Err(e.widen()),
+        }
+    }
+
+    #[instrument(skip(self, contents))]
+    fn write_extra(&mut self, key: &str, contents: &str) -> Result<(), Self::ErrorType> {
+        trace!("Writing extra data to EncryptedStorage");
+        if !self.header_written {
+            let was_encrypted = Self::read_header(&self.inner)?.is_some();
+            self.inner
+                .write_extra(
+                    EncryptionHeader::KEY,
+                    &serde_json::to_string(self.key.header())?,
+                )
+                .map_err(EncryptedStorageError::StorageError)?;
+            self.header_written = true;
+
+            //anything left unencrypted couldn't be read once there is a header
+            if !was_encrypted {
+                for extra in KNOWN_EXTRAS.into_iter().filter(|extra| *extra != key) {
+                    if let Some(contents) = self
+                        .inner
+                        .read_extra(extra)
+                        .map_err(EncryptedStorageError::StorageError)?
+                    {
+                        info!(?extra, "Encrypting existing extra data");
+                        self.write_extra(extra, &contents)?;
+                    }
+                }
+            }
+        }
+
+        let encrypted = self
+            .key
+            .encrypt(contents.as_bytes())
+            .map_err(EncryptedStorageError::widen)?;
+        self.inner
+            .write_extra(key, &encrypted)
+            .map_err(EncryptedStorageError::StorageError)
+    }
+
+    fn seal_backup(&self, contents: String) -> Result<String, Self::ErrorType> {
+        self.key
+            .encrypt(contents.as_bytes())
+            .map_err(EncryptedStorageError::widen)
+    }
+
+    fn open_backup(&self, contents: String) -> Result<String, Self::ErrorType> {
+        self.key
+            .decrypt_string(&contents, "backup")
+            .map_err(EncryptedStorageError::widen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        backup::{BackupManager, BackupOptions},
+        config::JankiConfig,
+        dummy_storage::DummyStorage,
+        encrypted_storage::{
+            EncryptedStorage, EncryptedStorageError, EncryptionHeader, EncryptionKey, KeyParams,
+        },
+        game::AnkiDB,
+        item::Item,
+        storage::Storage,
+        test_utils::f,
+    };
+    use chrono::{TimeZone, Utc};
+    use std::convert::Infallible;
+
+    ///Cheap settings, so the tests are quick
+    const PARAMS: KeyParams = KeyParams {
+        m_cost: 8,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    #[test]
+    fn encryption_test() {
+        let key = EncryptionKey::new("hunter2", PARAMS).unwrap();
+        let mut storage = EncryptedStorage::with_key(DummyStorage::default(), key);
+        let db = vec![Item::new(f("secret term", "secret definition"))];
+        storage.write_db(&db).unwrap();
+        JankiConfig::default().save(&mut storage).unwrap();
+
+        //nothing is stored in plain text
+        let inner = storage.into_inner();
+        assert!(inner.read_db().unwrap().is_empty());
+        let encrypted = inner
+            .read_extra(EncryptedStorage::<DummyStorage>::DB_KEY)
+            .unwrap()
+            .unwrap();
+        assert!(!encrypted.contains("secret"));
+        assert!(!inner
+            .read_extra(JankiConfig::KEY)
+            .unwrap()
+            .unwrap()
+            .contains("gaps"));
+
+        assert!(matches!(
+            EncryptedStorage::open(inner, "hunter3"),
+            Err(EncryptedStorageError::WrongPassphrase)
+        ));
+    }
+
+    #[test]
+    fn existing_collection_test() {
+        let mut inner = DummyStorage::default();
+        let db = vec![Item::new(f("a", "b"))];
+        inner.write_db(&db).unwrap();
+        JankiConfig::default().save(&mut inner).unwrap();
+
+        //the unencrypted collection is read, and encrypted on the next write
+        let key = EncryptionKey::new("hunter2", PARAMS).unwrap();
+        let mut storage = EncryptedStorage::with_key(inner, key);
+        assert_eq!(storage.read_db().unwrap(), db);
+        assert_eq!(JankiConfig::load(&storage).unwrap(), JankiConfig::default());
+        storage.write_db(&db).unwrap();
+
+        let inner = storage.into_inner();
+        assert!(inner.read_db().unwrap().is_empty());
+        assert!(!inner
+            .read_extra(JankiConfig::KEY)
+            .unwrap()
+            .unwrap()
+            .contains("gaps"));
+        let storage = EncryptedStorage::open(inner, "hunter2").unwrap();
+        assert_eq!(storage.read_db().unwrap(), db);
+        assert_eq!(JankiConfig::load(&storage).unwrap(), JankiConfig::default());
+    }
+
+    ///A storage which keeps the default [`Storage::write_extra`], which does nothing
+    #[derive(Debug, Default)]
+    struct NoExtrasStorage(AnkiDB);
+
+    impl Storage for NoExtrasStorage {
+        type ErrorType = Infallible;
+
+        fn read_db(&self) -> Result<AnkiDB, Self::ErrorType> {
+            Ok(self.0.clone())
+        }
+
+        fn write_db(&mut self, db: &AnkiDB) -> Result<(), Self::ErrorType> {
+            self.0 = db.clone();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn no_extras_test() {
+        let db = vec![Item::new(f("a", "b"))];
+        let key = EncryptionKey::new("hunter2", PARAMS).unwrap();
+        let mut storage = EncryptedStorage::with_key(NoExtrasStorage(db.clone()), key);
+
+        assert_eq!(storage.read_db().unwrap(), db);
+        assert!(matches!(
+            storage.write_db(&vec![]),
+            Err(EncryptedStorageError::ExtrasUnsupported)
+        ));
+        //the collection is still there
+        assert_eq!(storage.into_inner().0, db);
+    }
+
+    #[test]
+    fn backup_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut backups = BackupManager::new(BackupOptions {
+            dir: dir.path().to_str().unwrap().into(),
+            ..BackupOptions::default()
+        });
+        let db = vec![Item::new(f("secret", "answer"))];
+        let key = EncryptionKey::new("hunter2", PARAMS).unwrap();
+        let storage = EncryptedStorage::with_key(DummyStorage::default(), key);
+
+        let backup = backups
+            .backup(
+                &storage,
+                &db,
+                Utc.with_ymd_and_hms(2022, 7, 1, 12, 0, 0).unwrap(),
+            )
+            .unwrap();
+        assert!(!std::fs::read_to_string(&backup.path)
+            .unwrap()
+            .contains("secret"));
+        assert_eq!(BackupManager::read_backup(&storage, &backup).unwrap(), db);
+
+        let other = EncryptedStorage::with_key(
+            DummyStorage::default(),
+            EncryptionKey::new("hunter3", PARAMS).unwrap(),
+        );
+        assert!(BackupManager::read_backup(&other, &backup).is_err());
+    }
+
+    #[test]
+    fn reopen_test() {
+        let key = EncryptionKey::new("hunter2", PARAMS).unwrap();
+        let mut storage = EncryptedStorage::with_key(DummyStorage::default(), key);
+        let db = vec![Item::new(f("a", "b"))];
+        storage.write_db(&db).unwrap();
+
+        let mut inner = storage.into_inner();
+        let header = EncryptedStorage::read_header(&inner).unwrap().unwrap();
+        assert_eq!(header.params, PARAMS);
+        let storage = EncryptedStorage::open(inner, "hunter2").unwrap();
+        assert_eq!(storage.read_db().unwrap(), db);
+        assert_eq!(JankiConfig::load(&storage).unwrap(), JankiConfig::default());
+
+        //changing the ciphertext is caught
+        inner = storage.into_inner();
+        let mut encrypted = inner
+            .read_extra(EncryptedStorage::<DummyStorage>::DB_KEY)
+            .unwrap()
+            .unwrap();
+        encrypted.replace_range(40..44, "AAAA");
+        inner
+            .write_extra(EncryptedStorage::<DummyStorage>::DB_KEY, &encrypted)
+            .unwrap();
+        let storage =
+            EncryptedStorage::with_key(inner, EncryptionKey::unlock(&header, "hunter2").unwrap());
+        assert!(matches!(
+            storage.read_db(),
+            Err(EncryptedStorageError::Corrupt(_))
+        ));
+        assert!(DummyStorage::default()
+            .read_extra(EncryptionHeader::KEY)
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,498 @@
+use crate::{
+    game::{AnkiCardReturnType, AnkiGame},
+    item::Fact,
+    session::{index_of, SessionFilter},
+    storage::Storage,
+};
+use chrono::{DateTime, Duration, Utc};
+use rand::{seq::SliceRandom, thread_rng};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+///Options for creating an [`Exam`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExamOptions {
+    ///How many items to ask - if there are fewer matching items, all of them are asked
+    pub questions: usize,
+    ///Which items can be asked - [`SessionFilter::only_eligible`] is respected
+    pub filter: SessionFilter,
+    ///How long the user has for the whole exam - [`None`] for no limit
+    pub time_limit: Option<Duration>,
+    ///Whether or not the results should be added to each item's history when the exam is finished, changing when it is next eligible
+    pub record_results: bool,
+}
+
+impl Default for ExamOptions {
+    fn default() -> Self {
+        Self {
+            questions: 10,
+            filter: SessionFilter::default(),
+            time_limit: Some(Duration::minutes(5)),
+            record_results: false,
+        }
+    }
+}
+
+///An exam - a fixed number of randomly chosen items, with no feedback until the end when an [`ExamReport`] is made.
+///
+///The exam keeps track of items by their [`Fact`] rather than their index, so if an item is deleted from the game while an exam is running, it just gets skipped. The time is taken from the game's [`crate::clock::Clock`].
+#[derive(Debug, Clone)]
+pub struct Exam {
+    ///The fact for each question
+    questions: Vec<Fact>,
+    ///The answers given so far, and how long each one took
+    answers: Vec<(String, Duration)>,
+    ///When the exam started
+    started: DateTime<Utc>,
+    ///When the last answer was given, or the exam started
+    last_answer: DateTime<Utc>,
+    ///How long the user has for the whole exam
+    time_limit: Option<Duration>,
+    ///Whether or not the results should be added to the history
+    record_results: bool,
+}
+
+///The result of one question in an [`Exam`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExamResult {
+    ///The term that was asked
+    pub term: String,
+    ///The correct definition
+    pub definition: String,
+    ///The answer the user gave - [`None`] if they ran out of time
+    pub answer: Option<String>,
+    ///Whether or not the answer was correct
+    pub correct: bool,
+    ///Why the answer couldn't be checked, if it couldn't be - eg. if the definition isn't a valid pattern. These count as incorrect, but are never recorded in the item's history
+    #[serde(default)]
+    pub check_error: Option<String>,
+    ///How long the user took to answer, in milliseconds
+    pub time_taken_ms: i64,
+}
+
+///A scored report for a finished [`Exam`], which can be exported using [`Self::to_json`] or [`Self::write_csv`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExamReport {
+    ///When the exam started
+    pub started: DateTime<Utc>,
+    ///The result for each question, in the order they were asked
+    pub results: Vec<ExamResult>,
+    ///The number of correct answers
+    pub correct: usize,
+    ///The percentage of questions answered correctly, from `0.0` to `100.0`
+    pub percentage: f64,
+    ///How long the whole exam took, in milliseconds
+    pub time_taken_ms: i64,
+    ///Whether or not the exam ended before every question was answered - eg. if the time limit ran out
+    pub timed_out: bool,
+}
+
+impl Exam {
+    ///Creates a new exam, randomly choosing items from an [`AnkiGame`] using the [`ExamOptions`]
+    #[instrument(skip(game))]
+    pub fn new<S: Storage, T: AnkiCardReturnType>(
+        game: &AnkiGame<S, T>,
+        options: &ExamOptions,
+    ) -> Self {
+        let mut indices = options.filter.indices(game, true);
+        indices.shuffle(&mut thread_rng());
+        indices.truncate(options.questions);
+        info!("Starting exam with {} questions", indices.len());
+
+        let now = game.now();
+        Self {
+            questions: indices
+                .into_iter()
+                .map(|index| game.v[index].fact.clone())
+                .collect(),
+            answers: vec![],
+            started: now,
+            last_answer: now,
+            time_limit: options.time_limit,
+            record_results: options.record_results,
+        }
+    }
+
+    ///Gets the fact that is currently being asked, or [`None`] if the exam is finished
+    #[must_use]
+    pub fn current_fact<S: Storage, T: AnkiCardReturnType>(
+        &self,
+        game: &AnkiGame<S, T>,
+    ) -> Option<&Fact> {
+        if self.is_finished(game) {
+            None
+        } else {
+            self.next_question(game)
+        }
+    }
+
+    ///Gets the next question which hasn't been answered, skipping any facts that have been deleted from the game
+    fn next_question<S: Storage, T: AnkiCardReturnType>(
+        &self,
+        game: &AnkiGame<S, T>,
+    ) -> Option<&Fact> {
+        self.questions[self.answers.len()..]
+            .iter()
+            .find(|fact| index_of(game, fact).is_some())
+    }
+
+    ///Removes any questions which haven't been answered and have been deleted from the game
+    fn remove_deleted<S: Storage, T: AnkiCardReturnType>(&mut self, game: &AnkiGame<S, T>) {
+        let answered = self.answers.len();
+        let mut position = 0;
+        self.questions.retain(|fact| {
+            position += 1;
+            position <= answered || index_of(game, fact).is_some()
+        });
+    }
+
+    ///Gives an answer for the current fact, and moves on to the next one.
+    ///
+    ///Answers aren't checked until the exam is finished, and answers given after the time limit are ignored.
+    #[instrument(skip(self, game))]
+    pub fn answer<S: Storage, T: AnkiCardReturnType>(
+        &mut self,
+        game: &AnkiGame<S, T>,
+        answer: &str,
+    ) {
+        if self.is_finished(game) {
+            warn!("Answering a finished exam");
+            return;
+        }
+
+        //so that the answer lines up with the question that was shown
+        self.remove_deleted(game);
+
+        let now = game.now();
+        self.answers
+            .push((answer.trim().to_string(), now - self.last_answer));
+        self.last_answer = now;
+    }
+
+    ///Gets the question number that is being asked, starting from 1, and the total number of questions
+    #[must_use]
+    pub fn position(&self) -> (usize, usize) {
+        (
+            (self.answers.len() + 1).min(self.questions.len()),
+            self.questions.len(),
+        )
+    }
+
+    ///Gets when the time limit runs out, or [`None`] if there is no time limit
+    #[must_use]
+    pub fn deadline(&self) -> Option<DateTime<Utc>> {
+        self.time_limit.map(|limit| self.started + limit)
+    }
+
+    ///Gets how long is left before the time limit runs out, or [`None`] if there is no time limit
+    #[must_use]
+    pub fn time_remaining<S: Storage, T: AnkiCardReturnType>(
+        &self,
+        game: &AnkiGame<S, T>,
+    ) -> Option<Duration> {
+        self.deadline()
+            .map(|deadline| (deadline - game.now()).max(Duration::zero()))
+    }
+
+    ///Whether or not every question has been answered, or the time limit has run out
+    #[must_use]
+    pub fn is_finished<S: Storage, T: AnkiCardReturnType>(&self, game: &AnkiGame<S, T>) -> bool {
+        self.next_question(game).is_none() || self.time_remaining(game) == Some(Duration::zero())
+    }
+
+    ///Finishes the exam, checks all of the answers and makes an [`ExamReport`].
+    ///
+    ///This can be called before the exam is finished, in which case unanswered questions are marked as incorrect. Questions that were deleted from the game before being answered are left out.
+    ///If [`ExamOptions::record_results`] was set, the results are added to the history of each item - apart from answers which couldn't be checked, see [`ExamResult::check_error`].
+    #[instrument(skip(self, game))]
+    pub fn finish<S: Storage, T: AnkiCardReturnType>(
+        mut self,
+        game: &mut AnkiGame<S, T>,
+    ) -> ExamReport {
+        self.remove_deleted(game);
+        let now = game.now();
+        let all_answered = self.answers.len() >= self.questions.len();
+        let deadline = self.deadline().filter(|deadline| now >= *deadline);
+        let timed_out = !all_answered && deadline.is_some();
+        let finished = if all_answered {
+            self.last_answer
+        } else {
+            //stopped early, or ran out of time
+            deadline.unwrap_or(now)
+        };
+
+        let mut answers = self.answers.into_iter();
+        let results: Vec<ExamResult> = self
+            .questions
+            .into_iter()
+            .map(|fact| {
+                let (answer, time_taken) = answers.next().unzip();
+                let checked = answer
+                    .as_ref()
+                    .map_or(Ok(false), |answer| fact.check(answer));
+                let check_error = checked.as_ref().err().map(|e| {
+                    warn!("Unable to check answer, so it won't be recorded: {e}");
+                    e.to_string()
+                });
+                let correct = checked.unwrap_or(false);
+
+                if self.record_results && check_error.is_none() {
+                    if let Some(index) = index_of(game, &fact) {
+                        if let Err(e) = game.record_answer(index, correct) {
+                            error!("Unable to record answer: {e:?}");
+                        }
+                    } else {
+                        warn!("Exam item no longer exists, so the answer wasn't recorded");
+                    }
+                }
+
+                ExamResult {
+                    term: fact.term,
+                    definition: fact.definition,
+                    answer,
+                    correct,
+                    check_error,
+                    time_taken_ms: time_taken.map_or(0, |t| t.num_milliseconds()),
+                }
+            })
+            .collect();
+
+        let correct = results.iter().filter(|r| r.correct).count();
+        #[allow(clippy::cast_precision_loss)]
+        let percentage = if results.is_empty() {
+            0.0
+        } else {
+            correct as f64 / results.len() as f64 * 100.0
+        };
+
+        ExamReport {
+            started: self.started,
+            results,
+            correct,
+            percentage,
+            time_taken_ms: (finished - self.started).num_milliseconds(),
+            timed_out,
+        }
+    }
+}
+
+impl ExamReport {
+    ///Exports the report as pretty-printed JSON
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    ///Exports the results as CSV with a header row - one row per question
+    #[instrument(skip(self, writer))]
+    pub fn write_csv(&self, mut writer: impl Write) -> Result<(), std::io::Error> {
+        writeln!(writer, "term,definition,answer,correct,time_taken_ms")?;
+        for r in &self.results {
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                escape_csv(&r.term),
+                escape_csv(&r.definition),
+                escape_csv(r.answer.as_deref().unwrap_or_default()),
+                r.correct,
+                r.time_taken_ms
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+///Quotes a CSV field if it contains commas, quotes or line breaks
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        answer::AnswerType,
+        clock::MockClock,
+        dummy_storage::DummyStorage,
+        exam::{Exam, ExamOptions, ExamReport},
+        game::{default_sag, AnkiGame, GiveFacts},
+        item::{Fact, Item},
+        test_utils::{f, string_wrapper::StringWrapper},
+    };
+    use chrono::Duration;
+
+    fn game() -> AnkiGame<DummyStorage, GiveFacts> {
+        let mut game = AnkiGame::new(DummyStorage::default(), default_sag()).unwrap();
+        game.add_facts(vec![f("a", "1"), f("b", "2"), f("c", "3")])
+            .unwrap();
+        game
+    }
+
+    #[test]
+    fn exam_test() {
+        let mut game = game();
+        let mut exam = Exam::new(
+            &game,
+            &ExamOptions {
+                questions: 2,
+                time_limit: None,
+                ..ExamOptions::default()
+            },
+        );
+        assert_eq!(exam.position(), (1, 2));
+        assert_eq!(exam.time_remaining(&game), None);
+
+        let first = exam.current_fact(&game).unwrap().clone();
+        exam.answer(&game, &first.definition);
+        assert_eq!(exam.position(), (2, 2));
+        exam.answer(&game, "wrong");
+        assert!(exam.is_finished(&game));
+        assert!(exam.current_fact(&game).is_none());
+
+        let report = exam.finish(&mut game);
+        assert_eq!(report.results.len(), 2);
+        assert_eq!(report.results[0].term, first.term);
+        assert!(report.results[0].correct);
+        assert!(!report.results[1].correct);
+        assert_eq!(report.results[1].answer.as_deref(), Some("wrong"));
+        assert_eq!(report.correct, 1);
+        assert!((report.percentage - 50.0).abs() < f64::EPSILON);
+        assert!(!report.timed_out);
+
+        assert!(game.v.iter().all(|item| item.history.is_empty()));
+    }
+
+    #[test]
+    fn exam_timeout_test() {
+        let clock = MockClock::default();
+        let mut game = game().with_clock(clock.clone());
+        let options = ExamOptions {
+            time_limit: Some(Duration::minutes(1)),
+            record_results: true,
+            ..ExamOptions::default()
+        };
+
+        //stopping early isn't timing out
+        let exam = Exam::new(&game, &options);
+        clock.advance(Duration::seconds(10));
+        assert_eq!(exam.time_remaining(&game), Some(Duration::seconds(50)));
+        let report = exam.finish(&mut game);
+        assert!(!report.timed_out);
+        assert_eq!(report.time_taken_ms, 10_000);
+
+        let mut exam = Exam::new(&game, &options);
+        clock.advance(Duration::minutes(2));
+        assert!(exam.is_finished(&game));
+        exam.answer(&game, "ignored");
+
+        let report = exam.finish(&mut game);
+        assert!(report.timed_out);
+        assert_eq!(report.time_taken_ms, 60_000);
+        assert_eq!(report.correct, 0);
+        assert!(report.results.iter().all(|r| r.answer.is_none()));
+        assert!(game.v.iter().all(|item| item.history == vec![false, false]));
+    }
+
+    #[test]
+    fn export_test() {
+        let report = ExamReport {
+            started: chrono::Utc::now(),
+            results: vec![
+                crate::exam::ExamResult {
+                    term: "a, b".into(),
+                    definition: "say \"hi\"".into(),
+                    answer: None,
+                    correct: false,
+                    check_error: None,
+                    time_taken_ms: 5,
+                },
+                crate::exam::ExamResult {
+                    term: "line\r".into(),
+                    definition: "b".into(),
+                    answer: None,
+                    correct: false,
+                    check_error: None,
+                    time_taken_ms: 5,
+                },
+            ],
+            correct: 0,
+            percentage: 0.0,
+            time_taken_ms: 5,
+            timed_out: true,
+        };
+
+        let mut csv = StringWrapper::default();
+        report.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            csv.to_inner(),
+            "term,definition,answer,correct,time_taken_ms\n\"a, b\",\"say \"\"hi\"\"\",,false,5\n\"line\r\",b,,false,5\n"
+        );
+
+        let json = report.to_json().unwrap();
+        assert_eq!(serde_json::from_str::<ExamReport>(&json).unwrap(), report);
+    }
+
+    #[test]
+    fn unchecked_test() {
+        let mut game = game();
+        //like from a hand-edited database - this can't be added normally
+        game.v = vec![Item::new(
+            f("bad", "(b").with_answer_type(AnswerType::Pattern),
+        )];
+        let mut exam = Exam::new(
+            &game,
+            &ExamOptions {
+                record_results: true,
+                ..ExamOptions::default()
+            },
+        );
+        exam.answer(&game, "(b");
+
+        let report = exam.finish(&mut game);
+        assert!(!report.results[0].correct);
+        assert!(report.results[0].check_error.is_some());
+        assert!(game.v[0].history.is_empty());
+    }
+
+    #[test]
+    fn deleted_item_test() {
+        let mut game = game();
+        let mut exam = Exam::new(
+            &game,
+            &ExamOptions {
+                time_limit: None,
+                record_results: true,
+                ..ExamOptions::default()
+            },
+        );
+        let position = |game: &AnkiGame<DummyStorage, GiveFacts>, fact: &Fact| {
+            game.v.iter().position(|item| &item.fact == fact).unwrap()
+        };
+
+        let first = exam.current_fact(&game).unwrap().clone();
+        exam.answer(&game, &first.definition);
+
+        //the next question is deleted before it is answered, so it is skipped
+        let second = exam.current_fact(&game).unwrap().clone();
+        game.delete_at_index(position(&game, &second)).unwrap();
+        let third = exam.current_fact(&game).unwrap().clone();
+        assert_ne!(third, second);
+        exam.answer(&game, &third.definition);
+        assert!(exam.is_finished(&game));
+
+        //and one that was answered is deleted before the end, so it isn't recorded against another item
+        game.delete_at_index(position(&game, &first)).unwrap();
+
+        let report = exam.finish(&mut game);
+        let terms: Vec<&str> = report.results.iter().map(|r| r.term.as_str()).collect();
+        assert_eq!(terms, [first.term.as_str(), third.term.as_str()]);
+        assert_eq!(report.correct, 2);
+        assert!(!report.timed_out);
+        assert_eq!(game.v.len(), 1);
+        assert_eq!(game.v[0].fact, third);
+        assert_eq!(game.v[0].history, vec![true]);
+    }
+}
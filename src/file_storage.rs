@@ -1,10 +1,48 @@
-use crate::{game::AnkiDB, storage::Storage};
-use serde_json::{from_str, to_string};
+use crate::{
+    compression::{Compression, CompressionError},
+    format::{Format, FormatError},
+    game::AnkiDB,
+    migration::MigrationError,
+    storage::Storage,
+};
+use std::{
+    cell::Cell,
+    fs::File,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
 use thiserror::Error;
 
-///A struct implementing [`Storage`] for [`std::fs::File`] that uses [`std::fs::read_to_string`] and [`std::fs::write`]
+///A struct implementing [`Storage`] for [`std::fs::File`]s.
+///
+///Everything is written to a temporary file next to the real one, synced to disk and then renamed over it - so if the app is killed mid-write, either the old or the new version is left, never half of one. If a temporary file is left over when reading, it is used if it is complete, and thrown away if not.
+///
+///If the database doesn't exist, it is read as empty and created on the first write. If it exists but can't be read, the error is returned and writing over it is refused until [`NamedFileStorage::force_overwrite`] is called - so the real data can't be replaced with an empty collection by accident.
+///
+///The database is stored in the [`Format`] for its file extension, so `"./janki_db.toml"` is stored as TOML - this can be changed with [`NamedFileStorage::with_format`].
+///
+///The database can be compressed with [`NamedFileStorage::with_compression`] - whether or not it was compressed is worked out when reading, so this can be switched on or off for an existing database. Extra data is never compressed.
+///
+///In a transaction, changes are kept in memory and the file is only written once, when it is committed.
+///
+///Before v0.4 this was a tuple struct holding just the path - code that used `.0` should use [`NamedFileStorage::path`] instead.
 #[derive(Debug, Clone)]
-pub struct NamedFileStorage(pub String);
+pub struct NamedFileStorage {
+    ///The path to the database
+    pub path: String,
+    ///Whether or not to keep the previous version of the database as a `.bak` file next to it on every write
+    pub backup: bool,
+    ///The format the database is stored in
+    pub format: Format,
+    ///How to compress the database when writing it
+    pub compression: Compression,
+    ///Whether or not the last read failed for a file that exists - if so, writes are refused
+    unreadable: Cell<bool>,
+    ///Whether or not there is a transaction going on
+    in_transaction: bool,
+    ///The database with the changes made in the current transaction, which is written when it is committed
+    staged: Option<AnkiDB>,
+}
 
 ///Struct using `thiserror` that contains all errors for [`NamedFileStorage`]
 #[derive(Error, Debug)]
@@ -16,27 +54,538 @@ pub enum NamedFileStorageError {
     ///Error with `serde_json` - comes from [`serde_json::Error`]
     #[error("serde_json error: {0}")]
     SJError(#[from] serde_json::Error),
+
+    ///Error reading or upgrading the database - comes from [`MigrationError`]
+    #[error("error reading database: {0}")]
+    MigrationError(#[from] MigrationError),
+
+    ///Error reading or writing the database in its [`Format`] - comes from [`FormatError`]
+    #[error("format error: {0}")]
+    FormatError(FormatError),
+
+    ///Error compressing or decompressing the database - comes from [`CompressionError`]
+    #[error("compression error: {0}")]
+    CompressionError(#[from] CompressionError),
+
+    ///The database couldn't be read, so it wasn't written over - use [`NamedFileStorage::force_overwrite`] to write anyway
+    #[error("refusing to overwrite {0:?}, as it couldn't be read")]
+    RefusedOverwrite(PathBuf),
+}
+
+impl From<FormatError> for NamedFileStorageError {
+    fn from(e: FormatError) -> Self {
+        match e {
+            FormatError::MigrationError(e) => Self::MigrationError(e),
+            e => Self::FormatError(e),
+        }
+    }
 }
 
 impl<S: Into<String>> From<S> for NamedFileStorage {
     fn from(s: S) -> Self {
-        Self(s.into())
+        let path = s.into();
+        Self {
+            format: Format::from_path(&path),
+            path,
+            backup: false,
+            compression: Compression::None,
+            unreadable: Cell::new(false),
+            in_transaction: false,
+            staged: None,
+        }
     }
 }
 
+impl NamedFileStorage {
+    ///Gets the path to the database
+    #[must_use]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    ///Sets whether or not to keep the previous version of the database as a `.bak` file
+    #[must_use]
+    pub const fn with_backup(mut self, backup: bool) -> Self {
+        self.backup = backup;
+        self
+    }
+
+    ///Sets the format to store the database in, instead of the one for its file extension
+    #[must_use]
+    pub const fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    ///Sets how to compress the database when writing it
+    #[must_use]
+    pub const fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    ///Lets the database be written over even though the last read failed - eg. after the user has chosen to start again
+    pub fn force_overwrite(&mut self) {
+        warn!("Allowing an unreadable database to be overwritten");
+        self.unreadable.set(false);
+    }
+
+    ///Checks whether or not the last read failed for a database that exists - if so, [`Storage::write_db`] will return [`NamedFileStorageError::RefusedOverwrite`]
+    #[must_use]
+    pub const fn is_unreadable(&self) -> bool {
+        self.unreadable.get()
+    }
+
+    ///Makes the same change to the database staged in a transaction that was made to `db` - or stages all of `db`, if nothing is staged yet or they don't match up
+    fn stage(&mut self, db: &AnkiDB, change: impl FnOnce(&mut AnkiDB)) {
+        match &mut self.staged {
+            Some(staged) => {
+                change(staged);
+                if staged.len() != db.len() {
+                    warn!("Staged database doesn't match, staging all of it");
+                    staged.clone_from(db);
+                }
+            }
+            None => self.staged = Some(db.clone()),
+        }
+    }
+
+    ///Gets the path that extra data for a key is stored at - next to the database, so `"high_scores.json"` for `"./janki_db.json"` is stored at `"./janki_db.high_scores.json"`
+    #[must_use]
+    pub fn extra_path(&self, key: &str) -> PathBuf {
+        PathBuf::from(&self.path).with_extension(key)
+    }
+
+    ///Gets the path that the previous version of the database is kept at, if [`NamedFileStorage::backup`] is on - `"./janki_db.json.bak"` for `"./janki_db.json"`
+    #[must_use]
+    pub fn backup_path(&self) -> PathBuf {
+        PathBuf::from(format!("{}.bak", self.path))
+    }
+
+    ///Gets the path of the temporary file used while writing the database
+    #[must_use]
+    pub fn temp_path(&self) -> PathBuf {
+        temp_path(Path::new(&self.path))
+    }
+
+    ///Checks whether or not a temporary file was left over from a write that didn't finish - [`Storage::read_db`] deals with these automatically
+    #[must_use]
+    pub fn has_leftover_temp(&self) -> bool {
+        self.temp_path().exists()
+    }
+
+    ///Deals with a temporary file left over from a write that didn't finish.
+    ///
+    ///If it can be read, the write got as far as syncing it, so it is newer than the database and is renamed over it. If not, the app was killed while writing it, so the database is still the latest version and it is removed
+    fn recover_temp(&self) -> Result<(), NamedFileStorageError> {
+        let temp = self.temp_path();
+        let contents = match std::fs::read(&temp) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let complete = Compression::decompress_to_string(&contents)
+            .is_ok_and(|contents| self.format.deserialise(&contents).is_ok());
+        if complete {
+            warn!("Found a complete temporary file from an unfinished write, using it");
+            std::fs::rename(&temp, &self.path)?;
+        } else {
+            warn!("Found a partial temporary file from an unfinished write, removing it");
+            std::fs::remove_file(&temp)?;
+        }
+        Ok(())
+    }
+}
+
+///Gets the path of the temporary file used to write a file atomically - `"./janki_db.json.tmp"` for `"./janki_db.json"`
+fn temp_path(path: &Path) -> PathBuf {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    PathBuf::from(temp)
+}
+
+///Writes a file atomically - the contents go to a temporary file which is synced to disk and then renamed over the file, so the file is always either the old or the new version.
+///
+///If `backup` is given, the old version is copied there first
+pub(crate) fn write_atomic(
+    path: &Path,
+    contents: &[u8],
+    backup: Option<&Path>,
+) -> std::io::Result<()> {
+    let temp = temp_path(path);
+    let mut file = File::create(&temp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    if let Some(backup) = backup {
+        match std::fs::copy(path, backup) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    std::fs::rename(&temp, path)?;
+
+    //the rename is only on disk once the directory is synced
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        File::open(parent)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
 impl Storage for NamedFileStorage {
     type ErrorType = NamedFileStorageError;
 
     #[instrument]
     fn read_db(&self) -> Result<AnkiDB, Self::ErrorType> {
         trace!("Reading from FileStorage");
-        let contents = std::fs::read_to_string(&self.0).unwrap_or_else(|_e| "[]".into());
-        Ok(from_str(&contents)?)
+        let read = || -> Result<AnkiDB, Self::ErrorType> {
+            self.recover_temp()?;
+            match std::fs::read(&self.path) {
+                Ok(contents) => Ok(self
+                    .format
+                    .deserialise(&Compression::decompress_to_string(&contents)?)?),
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    info!("No database found, starting a new one");
+                    Ok(vec![])
+                }
+                Err(e) => Err(e.into()),
+            }
+        };
+
+        let db = read();
+        if let Err(e) = &db {
+            error!("Unable to read database, refusing to write over it: {e}");
+        }
+        self.unreadable.set(db.is_err());
+        db
     }
 
     #[instrument]
     fn write_db(&mut self, db: &AnkiDB) -> Result<(), Self::ErrorType> {
         trace!("Writing to FileStorage");
-        Ok(std::fs::write(&self.0, to_string(db)?)?)
+        if self.in_transaction {
+            self.staged = Some(db.clone());
+            return Ok(());
+        }
+        if self.unreadable.get() {
+            return Err(NamedFileStorageError::RefusedOverwrite(PathBuf::from(
+                &self.path,
+            )));
+        }
+
+        let backup = self.backup.then(|| self.backup_path());
+        let contents = self
+            .compression
+            .compress(self.format.serialise(db)?.as_bytes())?;
+        Ok(write_atomic(
+            Path::new(&self.path),
+            &contents,
+            backup.as_deref(),
+        )?)
+    }
+
+    #[instrument(skip(db))]
+    fn upsert_item(&mut self, db: &AnkiDB, index: usize) -> Result<(), Self::ErrorType> {
+        if !self.in_transaction {
+            return self.write_db(db);
+        }
+        self.stage(db, |staged| match staged.get_mut(index) {
+            Some(item) => item.clone_from(&db[index]),
+            None => staged.push(db[index].clone()),
+        });
+        Ok(())
+    }
+
+    #[instrument(skip(db))]
+    fn delete_item(&mut self, db: &AnkiDB, index: usize) -> Result<(), Self::ErrorType> {
+        if !self.in_transaction {
+            return self.write_db(db);
+        }
+        self.stage(db, |staged| {
+            if index < staged.len() {
+                staged.remove(index);
+            }
+        });
+        Ok(())
+    }
+
+    #[instrument(skip(db))]
+    fn append_review(&mut self, db: &AnkiDB, index: usize) -> Result<(), Self::ErrorType> {
+        self.upsert_item(db, index)
+    }
+
+    #[instrument]
+    fn begin_transaction(&mut self) -> Result<(), Self::ErrorType> {
+        trace!("Beginning transaction");
+        self.in_transaction = true;
+        Ok(())
+    }
+
+    #[instrument]
+    fn commit_transaction(&mut self) -> Result<(), Self::ErrorType> {
+        trace!("Committing transaction");
+        self.in_transaction = false;
+        self.staged.take().map_or(Ok(()), |db| self.write_db(&db))
+    }
+
+    #[instrument]
+    fn rollback_transaction(&mut self) -> Result<(), Self::ErrorType> {
+        trace!("Rolling back transaction");
+        //nothing has been written yet
+        self.in_transaction = false;
+        self.staged = None;
+        Ok(())
+    }
+
+    #[instrument]
+    fn read_extra(&self, key: &str) -> Result<Option<String>, Self::ErrorType> {
+        trace!("Reading extra data from FileStorage");
+        match std::fs::read_to_string(self.extra_path(key)) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[instrument(skip(contents))]
+    fn write_extra(&mut self, key: &str, contents: &str) -> Result<(), Self::ErrorType> {
+        trace!("Writing extra data to FileStorage");
+        Ok(write_atomic(
+            &self.extra_path(key),
+            contents.as_bytes(),
+            None,
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        file_storage::{NamedFileStorage, NamedFileStorageError},
+        format::Format,
+        game::{default_sag, AddFactsError, AnkiGame, GiveFacts},
+        item::Item,
+        migration::to_versioned_string,
+        storage::Storage,
+        test_utils::f,
+    };
+
+    #[test]
+    fn atomic_write_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.json");
+        let mut storage =
+            NamedFileStorage::from(path.to_str().unwrap().to_string()).with_backup(true);
+        assert_eq!(storage.path(), path.to_str().unwrap());
+
+        let first = vec![Item::new(f("a", "b"))];
+        let second = vec![Item::new(f("a", "b")), Item::new(f("c", "d"))];
+        storage.write_db(&first).unwrap();
+        assert!(!storage.backup_path().exists());
+        storage.write_db(&second).unwrap();
+        assert!(!storage.has_leftover_temp());
+        assert_eq!(storage.read_db().unwrap(), second);
+
+        let backup = NamedFileStorage::from(storage.backup_path().to_str().unwrap());
+        assert_eq!(backup.read_db().unwrap(), first);
+
+        storage.write_extra("profile.json", "{}").unwrap();
+        assert_eq!(
+            storage.read_extra("profile.json").unwrap().as_deref(),
+            Some("{}")
+        );
+    }
+
+    #[test]
+    fn leftover_temp_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.json");
+        let mut storage = NamedFileStorage::from(path.to_str().unwrap());
+        let db = vec![Item::new(f("a", "b"))];
+        storage.write_db(&db).unwrap();
+
+        //killed while writing the temporary file - the database is still fine
+        std::fs::write(storage.temp_path(), "[{\"fact\":").unwrap();
+        assert!(storage.has_leftover_temp());
+        assert_eq!(storage.read_db().unwrap(), db);
+        assert!(!storage.has_leftover_temp());
+
+        //killed after syncing the temporary file, but before renaming it - it is the newest version
+        let newer = vec![Item::new(f("c", "d"))];
+        std::fs::write(storage.temp_path(), to_versioned_string(&newer).unwrap()).unwrap();
+        assert_eq!(storage.read_db().unwrap(), newer);
+        assert!(!storage.has_leftover_temp());
+    }
+
+    #[test]
+    fn unreadable_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.json");
+        let mut storage = NamedFileStorage::from(path.to_str().unwrap());
+
+        //a missing database is empty, and can be written
+        assert!(storage.read_db().unwrap().is_empty());
+        assert!(!storage.is_unreadable());
+
+        //one that can't be parsed isn't written over until forced
+        std::fs::write(&path, "not json").unwrap();
+        assert!(matches!(
+            storage.read_db(),
+            Err(NamedFileStorageError::MigrationError(_))
+        ));
+        let db = vec![Item::new(f("a", "b"))];
+        assert!(matches!(
+            storage.write_db(&db),
+            Err(NamedFileStorageError::RefusedOverwrite(_))
+        ));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not json");
+        storage.force_overwrite();
+        storage.write_db(&db).unwrap();
+        assert_eq!(storage.read_db().unwrap(), db);
+
+        //neither is one that isn't a file
+        let dir_storage = NamedFileStorage::from(dir.path().to_str().unwrap());
+        assert!(matches!(
+            dir_storage.read_db(),
+            Err(NamedFileStorageError::ReadFileError(_))
+        ));
+        assert!(dir_storage.is_unreadable());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compression_test() {
+        use crate::compression::Compression;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.json");
+        let mut storage = NamedFileStorage::from(path.to_str().unwrap());
+        let db = vec![Item::new(f("a", "b")), Item::new(f("c", "d"))];
+        storage.write_db(&db).unwrap();
+        let plain_len = std::fs::metadata(&path).unwrap().len();
+
+        //an existing database can be switched to compressed and back
+        for compression in [Compression::Gzip, Compression::Zstd, Compression::None] {
+            storage = storage.with_compression(compression);
+            assert_eq!(storage.read_db().unwrap(), db);
+            storage.write_db(&db).unwrap();
+            assert_eq!(
+                Compression::detect(&std::fs::read(&path).unwrap()),
+                compression
+            );
+            assert_eq!(
+                NamedFileStorage::from(path.to_str().unwrap())
+                    .read_db()
+                    .unwrap(),
+                db
+            );
+        }
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), plain_len);
+    }
+
+    #[test]
+    fn format_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.toml");
+        let mut storage = NamedFileStorage::from(path.to_str().unwrap());
+        assert_eq!(storage.format, Format::Toml);
+
+        let db = vec![Item::new(f("a", "b")), Item::new(f("c", "d"))];
+        storage.write_db(&db).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(written.starts_with("version = 1\n"));
+        assert_eq!(storage.read_db().unwrap(), db);
+
+        //hand edits are picked up
+        std::fs::write(&path, written.replace("'d'", "'e'")).unwrap();
+        assert_eq!(storage.read_db().unwrap()[1].fact.definition, "e");
+    }
+
+    #[test]
+    fn transaction_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.json");
+        let mut storage = NamedFileStorage::from(path.to_str().unwrap());
+
+        //nothing is written until the transaction is committed
+        let mut db = vec![Item::new(f("a", "b"))];
+        storage.begin_transaction().unwrap();
+        storage.upsert_item(&db, 0).unwrap();
+        db.push(Item::new(f("c", "d")));
+        storage.upsert_item(&db, 1).unwrap();
+        db.remove(0);
+        storage.delete_item(&db, 0).unwrap();
+        assert!(!path.exists());
+        storage.commit_transaction().unwrap();
+        assert_eq!(storage.read_db().unwrap(), db);
+
+        //and rolling back writes nothing
+        storage.begin_transaction().unwrap();
+        storage.upsert_item(&vec![], 0).unwrap();
+        storage.rollback_transaction().unwrap();
+        assert_eq!(storage.read_db().unwrap(), db);
+    }
+
+    #[test]
+    fn add_facts_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.json");
+        let storage = NamedFileStorage::from(path.to_str().unwrap());
+        let mut game: AnkiGame<_, GiveFacts> = AnkiGame::new_params(vec![], storage, default_sag());
+
+        game.add_facts(vec![f("a", "b"), f("c", "d")]).unwrap();
+        let stored = NamedFileStorage::from(path.to_str().unwrap())
+            .read_db()
+            .unwrap();
+        assert_eq!(stored.len(), 2);
+
+        //if the facts can't be written, none of them are added
+        std::fs::write(&path, "not json").unwrap();
+        let storage = NamedFileStorage::from(path.to_str().unwrap());
+        assert!(storage.read_db().is_err());
+        let mut game: AnkiGame<_, GiveFacts> = AnkiGame::new_params(stored, storage, default_sag());
+        assert!(matches!(
+            game.add_facts(vec![f("e", "f")]),
+            Err(AddFactsError::StorageError(
+                NamedFileStorageError::RefusedOverwrite(_)
+            ))
+        ));
+        assert_eq!(game.get_all_facts(), vec![f("a", "b"), f("c", "d")]);
+    }
+
+    #[test]
+    fn storage_errors_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.json");
+        std::fs::write(&path, "not json").unwrap();
+
+        //nothing can be written, so every change is undone and the error returned
+        let storage = NamedFileStorage::from(path.to_str().unwrap());
+        assert!(storage.read_db().is_err());
+        let mut game: AnkiGame<_, GiveFacts> =
+            AnkiGame::new_params(vec![Item::new(f("a", "b"))], storage, default_sag());
+        assert!(matches!(
+            game.add_fact(f("c", "d")),
+            Err(AddFactsError::StorageError(
+                NamedFileStorageError::RefusedOverwrite(_)
+            ))
+        ));
+        assert!(game.delete_at_index(0).is_err());
+        assert!(game.record_answer(0, true).is_err());
+        assert!(game.record_cram_answer(0, true).is_err());
+
+        assert_eq!(game.v, vec![Item::new(f("a", "b"))]);
+        assert_eq!(game.profile().reviews, 0);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not json");
     }
 }
@@ -0,0 +1,275 @@
+use crate::{
+    game::AnkiDB,
+    migration::{
+        from_versioned_str, from_versioned_value, Envelope, MigrationError, CURRENT_VERSION,
+    },
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::path::Path;
+use thiserror::Error;
+
+///A format that the database can be stored in. Everything is pretty-printed, with fields in the same order every time, so they can be edited by hand and diffs stay small.
+///
+///[`Format::Ron`] and [`Format::Yaml`] need the `formats` feature
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Format {
+    ///JSON
+    #[default]
+    Json,
+    ///Rusty Object Notation
+    Ron,
+    ///YAML
+    Yaml,
+    ///TOML - this can't hold a list on its own, so databases from before the versioned format can't be TOML
+    Toml,
+}
+
+///Error for reading and writing databases in a [`Format`], using `thiserror`
+#[derive(Error, Debug)]
+pub enum FormatError {
+    ///Error reading or upgrading the database - comes from [`MigrationError`]
+    #[error("error reading database: {0}")]
+    MigrationError(#[from] MigrationError),
+
+    ///Error with `serde_json` - comes from [`serde_json::Error`]
+    #[error("serde_json error: {0}")]
+    SJError(#[from] serde_json::Error),
+
+    ///Error parsing TOML - comes from [`toml::de::Error`]
+    #[error("error parsing TOML: {0}")]
+    TomlDeError(#[from] toml::de::Error),
+
+    ///Error writing TOML - comes from [`toml::ser::Error`]
+    #[error("error writing TOML: {0}")]
+    TomlSerError(#[from] toml::ser::Error),
+
+    ///Error parsing RON - comes from [`ron::error::SpannedError`]
+    #[cfg(feature = "formats")]
+    #[error("error parsing RON: {0}")]
+    RonDeError(#[from] ron::error::SpannedError),
+
+    ///Error writing RON - comes from [`ron::Error`]
+    #[cfg(feature = "formats")]
+    #[error("error writing RON: {0}")]
+    RonSerError(#[from] ron::Error),
+
+    ///Error with YAML - comes from [`serde_yaml::Error`]
+    #[cfg(feature = "formats")]
+    #[error("YAML error: {0}")]
+    YamlError(#[from] serde_yaml::Error),
+
+    ///The format needs the `formats` feature
+    #[error("{0:?} needs the `formats` feature")]
+    Unsupported(Format),
+}
+
+///Just the version from an [`Envelope`], to check it before reading the rest
+#[derive(Deserialize)]
+struct Version {
+    ///The version of the format
+    version: u32,
+}
+
+impl Format {
+    ///Works out the format from a file extension - `.ron`, `.yaml` or `.yml`, `.toml`, and JSON for anything else
+    #[must_use]
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        let extension = path
+            .as_ref()
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase);
+        match extension.as_deref() {
+            Some("ron") => Self::Ron,
+            Some("yaml" | "yml") => Self::Yaml,
+            Some("toml") => Self::Toml,
+            _ => Self::Json,
+        }
+    }
+
+    ///Writes the database in the current version of the format
+    ///
+    /// # Errors
+    /// - [`FormatError::Unsupported`] if the `formats` feature is needed and is off
+    /// - An error from the serialiser
+    pub fn serialise(self, db: &AnkiDB) -> Result<String, FormatError> {
+        let envelope = Envelope {
+            version: CURRENT_VERSION,
+            items: db,
+        };
+
+        match self {
+            Self::Json => Ok(serde_json::to_string_pretty(&envelope)?),
+            //`toml` can't write enums with data like `AnswerType::Numeric`, so it goes through JSON first - which also puts plain values before tables, as TOML needs
+            Self::Toml => {
+                let mut value = serde_json::to_value(&envelope)?;
+                remove_nulls(&mut value);
+                Ok(toml::to_string_pretty(&toml::Value::try_from(value)?)?)
+            }
+            #[cfg(feature = "formats")]
+            Self::Ron => Ok(ron::ser::to_string_pretty(
+                &envelope,
+                ron::ser::PrettyConfig::default(),
+            )?),
+            #[cfg(feature = "formats")]
+            Self::Yaml => Ok(serde_yaml::to_string(&envelope)?),
+            #[cfg(not(feature = "formats"))]
+            _ => Err(FormatError::Unsupported(self)),
+        }
+    }
+
+    ///Reads a database, upgrading it from older versions with [`from_versioned_value`] if needed - including the list of items from before the versioned format
+    ///
+    /// # Errors
+    /// - [`FormatError::Unsupported`] if the `formats` feature is needed and is off
+    /// - [`FormatError::MigrationError`] if it is from a newer version, or can't be upgraded
+    /// - An error from the parser
+    pub fn deserialise(self, s: &str) -> Result<AnkiDB, FormatError> {
+        if self == Self::Json {
+            return Ok(from_versioned_str(s)?);
+        }
+
+        match self.parse(s) {
+            Ok(Version { version }) if version == CURRENT_VERSION => {
+                let envelope: Envelope<AnkiDB> = self.parse(s)?;
+                Ok(envelope.items)
+            }
+            //older versions go through the same migrations as JSON
+            _ => Ok(from_versioned_value(self.parse(s)?)?),
+        }
+    }
+
+    ///Parses anything from this format
+    fn parse<T: DeserializeOwned>(self, s: &str) -> Result<T, FormatError> {
+        match self {
+            Self::Json => Ok(serde_json::from_str(s)?),
+            Self::Toml => Ok(serde_json::from_value(toml::from_str(s)?)?),
+            #[cfg(feature = "formats")]
+            Self::Ron => Ok(ron::from_str(s)?),
+            #[cfg(feature = "formats")]
+            Self::Yaml => Ok(serde_yaml::from_str(s)?),
+            #[cfg(not(feature = "formats"))]
+            _ => Err(FormatError::Unsupported(self)),
+        }
+    }
+}
+
+///Removes every `null` from objects, as TOML can't store them - they are read back as [`Option::None`] when missing anyway
+fn remove_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.retain(|_, v| !v.is_null());
+            map.values_mut().for_each(remove_nulls);
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(remove_nulls),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        answer::{AnswerType, NumericOptions},
+        format::{Format, FormatError},
+        item::{Fact, Item},
+        migration::MigrationError,
+        test_utils::f,
+    };
+    use chrono::{TimeZone, Utc};
+
+    ///Every format that can be used with the features that are on
+    fn formats() -> Vec<Format> {
+        let mut formats = vec![Format::Json, Format::Toml];
+        if cfg!(feature = "formats") {
+            formats.extend([Format::Ron, Format::Yaml]);
+        }
+        formats
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let mut reviewed = Item::new(
+            Fact::new("pi", "3.14")
+                .with_answer_type(AnswerType::Numeric(NumericOptions::default())),
+        );
+        reviewed.record(true, Utc.with_ymd_and_hms(2022, 7, 1, 12, 0, 0).unwrap());
+        let db = vec![Item::new(f("hello", "world")), reviewed];
+
+        for format in formats() {
+            let written = format.serialise(&db).unwrap();
+            assert_eq!(format.deserialise(&written).unwrap(), db, "{format:?}");
+            //the same database is always written the same way
+            assert_eq!(format.serialise(&db).unwrap(), written, "{format:?}");
+            assert!(
+                written.lines().count() > db.len() * 4,
+                "{format:?} isn't pretty-printed"
+            );
+        }
+    }
+
+    #[test]
+    fn pretty_json_test() {
+        let db = vec![Item::new(f("hello", "world"))];
+        let written = Format::Json.serialise(&db).unwrap();
+        let lines: Vec<_> = written.lines().collect();
+
+        //the version always comes first, and the fields of each item are in the order they are declared
+        assert_eq!(lines[..3], ["{", r#"  "version": 1,"#, r#"  "items": ["#]);
+        let fields: Vec<_> = lines
+            .iter()
+            .filter_map(|l| l.trim().strip_prefix('"')?.split('"').next())
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                "version",
+                "items",
+                "fact",
+                "term",
+                "definition",
+                "answer_type",
+                "last_tested",
+                "history",
+                "reviews",
+                "due"
+            ]
+        );
+    }
+
+    #[test]
+    fn v0_test() {
+        let v0 = include_str!("../fixtures/db_v0.json");
+        let expected = Format::Json.deserialise(v0).unwrap();
+        assert_eq!(expected.len(), 2);
+
+        #[cfg(feature = "formats")]
+        {
+            let value: serde_json::Value = serde_json::from_str(v0).unwrap();
+            let yaml = serde_yaml::to_string(&value).unwrap();
+            assert_eq!(Format::Yaml.deserialise(&yaml).unwrap(), expected);
+            let ron = ron::to_string(&value).unwrap();
+            assert_eq!(Format::Ron.deserialise(&ron).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn from_path_test() {
+        assert_eq!(Format::from_path("./janki_db.json"), Format::Json);
+        assert_eq!(Format::from_path("./janki_db.RON"), Format::Ron);
+        assert_eq!(Format::from_path("db.yml"), Format::Yaml);
+        assert_eq!(Format::from_path("db.yaml"), Format::Yaml);
+        assert_eq!(Format::from_path("db.toml"), Format::Toml);
+        assert_eq!(Format::from_path("janki_db"), Format::Json);
+
+        assert!(matches!(
+            Format::Toml.deserialise("version = 99\nitems = []"),
+            Err(FormatError::MigrationError(MigrationError::TooNew(99)))
+        ));
+        if cfg!(not(feature = "formats")) {
+            assert!(matches!(
+                Format::Ron.serialise(&vec![]),
+                Err(FormatError::Unsupported(Format::Ron))
+            ));
+        }
+    }
+}
@@ -1,11 +1,20 @@
 use crate::{
+    answer::AnswerError,
+    backup::{BackupError, BackupInfo, BackupManager},
+    clock::{Clock, SystemClock},
+    config::{ConfigError, HumanDuration, JankiConfig},
     dummy_storage::{DummyStorage, DynStorage},
     item::{Fact, Item, ItemGuard},
-    storage::Storage,
+    optimiser::{optimise, OptimisedGaps, OptimiserOptions},
+    profile::Profile,
+    stats::Stats,
+    storage::{read_extra_json, transaction, write_extra_json, ExtraDataError, Storage},
 };
-use chrono::{Duration, Utc};
-use rand::{thread_rng, Rng};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use rand::{rngs::StdRng, seq::SliceRandom, thread_rng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, marker::PhantomData};
+use thiserror::Error;
 use tracing::Level;
 
 ///Alias used to determine how long the space is between repetitions based on the current streak
@@ -42,14 +51,223 @@ pub fn default_sag() -> SeeAgainGaps {
     hm
 }
 
+///The longest gap that can be extrapolated, in seconds - about 100 years, to stop [`Duration`] from overflowing
+const MAX_EXTRAPOLATED_SECS: f64 = 100.0 * 365.0 * 86_400.0;
+
+///What to do for streaks longer than any in the [`SeeAgainGaps`]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Extrapolation {
+    ///The item is eligible straight away - this means the best-known items are asked the most, so it's only here for the old behaviour
+    Immediate,
+    ///Use the gap for the longest streak
+    LastGap,
+    ///Multiply the gap for the longest streak by `factor` for each extra correct answer
+    Multiplicative {
+        ///What to multiply the gap by for each extra correct answer
+        factor: f64,
+    },
+    ///Like [`Extrapolation::Multiplicative`], but never longer than `max`
+    Capped {
+        ///What to multiply the gap by for each extra correct answer
+        factor: f64,
+        ///The longest gap allowed
+        max: HumanDuration,
+    },
+}
+
+impl Default for Extrapolation {
+    fn default() -> Self {
+        Self::Capped {
+            factor: 2.0,
+            max: HumanDuration(Duration::days(365)),
+        }
+    }
+}
+
+impl Extrapolation {
+    ///Gets the gap for a streak - from the [`SeeAgainGaps`] if it's in there, or extrapolated if it's longer than every streak in there.
+    ///
+    ///Returns [`Option::None`] if the item should be eligible straight away - eg. for a streak of 0, or any other streak that is missing but shorter than the longest one
+    #[must_use]
+    pub fn gap(&self, sag: &SeeAgainGaps, streak: u32) -> Option<Duration> {
+        if let Some(gap) = sag.get(&streak) {
+            return Some(*gap);
+        }
+
+        let (last_streak, last_gap) = sag.iter().max_by_key(|(s, _)| **s)?;
+        if streak < *last_streak {
+            return None;
+        }
+
+        #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+        let scale = |factor: f64| {
+            let steps = i32::try_from(streak - last_streak).unwrap_or(i32::MAX);
+            let secs = (last_gap.num_seconds() as f64 * factor.powi(steps))
+                .clamp(0.0, MAX_EXTRAPOLATED_SECS);
+            Duration::seconds(secs as i64)
+        };
+
+        match self {
+            Self::Immediate => None,
+            Self::LastGap => Some(*last_gap),
+            Self::Multiplicative { factor } => Some(scale(*factor)),
+            Self::Capped { factor, max } => Some(scale(*factor).min(max.0)),
+        }
+    }
+}
+
+///How due dates are spread out, so that items answered at the same time don't all come due at the same time
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fuzz {
+    ///How far a due date can move, as a fraction of the gap - this is always at least a day, but never more than half of the gap
+    pub fraction: f64,
+    ///Gaps shorter than this aren't fuzzed
+    pub min_gap: HumanDuration,
+    ///Whether or not to pick the day with the fewest items already due, rather than a random time
+    pub load_balance: bool,
+}
+
+impl Default for Fuzz {
+    fn default() -> Self {
+        Self {
+            fraction: 0.1,
+            min_gap: HumanDuration(Duration::days(1)),
+            load_balance: true,
+        }
+    }
+}
+
+impl Fuzz {
+    ///Turns it off, so that items are due exactly one gap after they're answered
+    #[must_use]
+    pub fn none() -> Self {
+        Self {
+            fraction: 0.0,
+            ..Self::default()
+        }
+    }
+
+    ///Gets a due date for an item answered at `now` with a given gap.
+    ///
+    ///`others` are the due dates of every other item, used for load balancing - they get counted into a per-day histogram once, rather than rescanned for every day
+    pub fn due(
+        &self,
+        now: DateTime<Utc>,
+        gap: Duration,
+        rng: &mut impl Rng,
+        others: impl Iterator<Item = DateTime<Utc>>,
+    ) -> DateTime<Utc> {
+        let due = now + gap;
+        if self.fraction <= 0.0 || gap < self.min_gap.0 {
+            return due;
+        }
+
+        #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+        let range = ((gap.num_seconds() as f64 * self.fraction) as i64)
+            .max(86_400)
+            .min(gap.num_seconds() / 2);
+        let days = range / 86_400;
+
+        if !self.load_balance || days == 0 {
+            return due + Duration::seconds(rng.gen_range(-range..=range));
+        }
+
+        let range =
+            (due - Duration::days(days)).date_naive()..=(due + Duration::days(days)).date_naive();
+        let mut histogram: HashMap<NaiveDate, usize> = HashMap::new();
+        for day in others.map(|d| d.date_naive()).filter(|d| range.contains(d)) {
+            *histogram.entry(day).or_default() += 1;
+        }
+
+        let loads: Vec<(i64, usize)> = (-days..=days)
+            .map(|offset| {
+                let day = (due + Duration::days(offset)).date_naive();
+                (offset, histogram.get(&day).copied().unwrap_or_default())
+            })
+            .collect();
+        let least = loads
+            .iter()
+            .map(|(_, load)| *load)
+            .min()
+            .unwrap_or_default();
+        let quietest: Vec<i64> = loads
+            .into_iter()
+            .filter(|(_, load)| *load == least)
+            .map(|(offset, _)| offset)
+            .collect();
+        let offset = quietest.choose(rng).copied().unwrap_or_default();
+        trace!(?offset, ?least, "Load balanced due date");
+
+        due + Duration::days(offset)
+    }
+}
+
+///The parts of an [`AnkiGame`] needed to work out when an item is next due
+#[derive(Debug)]
+pub(crate) struct Scheduler<'a> {
+    ///The gaps for each streak
+    pub(crate) sag: &'a SeeAgainGaps,
+    ///What to do for streaks past the end of the gaps
+    pub(crate) extrapolation: Extrapolation,
+    ///How to spread out due dates
+    pub(crate) fuzz: Fuzz,
+    ///The random number generator for the fuzz
+    pub(crate) rng: &'a mut StdRng,
+}
+
+impl Scheduler<'_> {
+    ///Works out when the item at `index` is next due, after being answered at `now`.
+    ///
+    ///Returns [`Option::None`] if there's no gap, so the item is eligible straight away
+    fn due(&mut self, items: &[Item], index: usize, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let gap = self
+            .extrapolation
+            .gap(self.sag, items[index].get_streak())?;
+        let others = items
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .filter_map(|(_, item)| item.due);
+        Some(self.fuzz.due(now, gap, self.rng, others))
+    }
+}
+
+///Error for adding facts with [`AnkiGame::add_facts`], using `thiserror`
+#[derive(Error, Debug)]
+pub enum AddFactsError<E> {
+    ///One of the facts has an invalid definition - comes from [`AnswerError`]
+    #[error("invalid fact: {0}")]
+    InvalidFact(#[from] AnswerError),
+
+    ///Error from the [`Storage`] - the facts were rolled back
+    #[error("storage error: {0:?}")]
+    StorageError(E),
+}
+
 ///Struct used to manage the game - this should be used in the client
 pub struct AnkiGame<S: Storage, T: AnkiCardReturnType> {
     ///Vector to store the items
-    v: AnkiDB,
+    pub(crate) v: AnkiDB,
     ///Storage for the AnkiDB
     pub(crate) storage: S,
     ///Timer for spaced repetition
-    sag: SeeAgainGaps,
+    pub(crate) sag: SeeAgainGaps,
+    ///What to do for streaks longer than any in the [`SeeAgainGaps`]
+    pub(crate) extrapolation: Extrapolation,
+    ///How due dates are spread out
+    pub(crate) fuzz: Fuzz,
+    ///The random number generator used for scheduling - this can be seeded with [`AnkiGame::with_seed`]
+    pub(crate) rng: StdRng,
+    ///The user's XP, streaks and achievements
+    pub(crate) profile: Profile,
+    ///The settings for the collection
+    pub(crate) config: JankiConfig,
+    ///The [`Clock`] used for scheduling - this is a [`SystemClock`] unless set with [`AnkiGame::with_clock`]
+    pub(crate) clock: Box<dyn Clock + Send>,
+    ///Makes backups every so often, if set with [`AnkiGame::with_backups`]
+    backups: Option<BackupManager>,
     ///Stores the index of the card being tested if [`AnkiCardReturnType`] == [`GiveFacts`]
     current: Option<(usize, bool)>,
     ///Stores whether or not an [`ItemGuard`] is present if [`AnkiCardReturnType`] == [`GiveItemGuards`]
@@ -62,41 +280,197 @@ pub struct AnkiGame<S: Storage, T: AnkiCardReturnType> {
 impl<S: Storage, T: AnkiCardReturnType> AnkiGame<S, T> {
     ///Constructor function - sets all fields to arguments, and uses the [`Storage`] to read the database.
     ///
-    ///Can return [`Result::Err`] if there is an error reading the database
+    ///Can return [`Result::Err`] if there is an error reading the database. The [`Profile`] is also read, and a new one is started if it can't be parsed.
     pub fn new(storage: S, sag: SeeAgainGaps) -> Result<Self, S::ErrorType> {
+        let profile = match read_extra_json(&storage, Profile::KEY) {
+            Ok(profile) => profile,
+            Err(ExtraDataError::StorageError(e)) => return Err(e),
+            Err(ExtraDataError::SJError(e)) => {
+                error!("Unable to parse profile, starting a new one: {e}");
+                Profile::default()
+            }
+        };
+
         Ok(Self {
             v: storage.read_db()?,
             storage,
             sag,
+            extrapolation: Extrapolation::default(),
+            fuzz: Fuzz::default(),
+            rng: StdRng::from_entropy(),
+            profile,
+            config: JankiConfig::default(),
+            clock: Box::new(SystemClock),
+            backups: None,
             current: None,
             ig_present: false,
             _pd: PhantomData,
         })
     }
 
-    ///Constructor from parameters
+    ///Constructor which reads the [`JankiConfig`] stored with the database, and uses its gaps - see [`JankiConfig::load`]
+    pub fn load(storage: S) -> Result<Self, ConfigError<S::ErrorType>> {
+        let config = JankiConfig::load(&storage)?;
+        let mut game = Self::new(storage, config.sag()).map_err(ConfigError::StorageError)?;
+        game.extrapolation = config.extrapolation;
+        game.fuzz = config.fuzz;
+        game.config = config;
+        Ok(game)
+    }
+
+    ///Constructor from parameters - uses a new [`Profile`]
     pub fn new_params(v: AnkiDB, storage: S, sag: SeeAgainGaps) -> Self {
         Self {
             v,
             storage,
             sag,
+            extrapolation: Extrapolation::default(),
+            fuzz: Fuzz::default(),
+            rng: StdRng::from_entropy(),
+            profile: Profile::default(),
+            config: JankiConfig::default(),
+            clock: Box::new(SystemClock),
+            backups: None,
             current: None,
             ig_present: false,
             _pd: PhantomData,
         }
     }
 
+    ///Sets the [`Clock`] used for scheduling - useful for tests and simulations, with a [`crate::clock::MockClock`]
+    #[must_use]
+    pub fn with_clock(mut self, clock: impl Clock + Send + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    ///Gets the [`SeeAgainGaps`] used for scheduling
+    #[must_use]
+    pub const fn sag(&self) -> &SeeAgainGaps {
+        &self.sag
+    }
+
+    ///Sets the [`SeeAgainGaps`] used for scheduling - eg. to ones from [`crate::optimiser::optimise`]. Items which already have a due date keep it until they are next answered
+    pub fn set_sag(&mut self, sag: SeeAgainGaps) {
+        self.sag = sag;
+    }
+
+    ///Fits new [`SeeAgainGaps`] to the user's answers so far - see [`crate::optimiser::optimise`]. Use [`OptimisedGaps::to_sag`] and [`AnkiGame::set_sag`] to start using them
+    #[must_use]
+    pub fn optimise_sag(&self, options: &OptimiserOptions) -> OptimisedGaps {
+        optimise(&self.v, &self.sag, options)
+    }
+
+    ///Gets the [`Extrapolation`] used for streaks longer than any in the [`SeeAgainGaps`]
+    #[must_use]
+    pub const fn extrapolation(&self) -> Extrapolation {
+        self.extrapolation
+    }
+
+    ///Sets the [`Extrapolation`] used for streaks longer than any in the [`SeeAgainGaps`]
+    pub const fn set_extrapolation(&mut self, extrapolation: Extrapolation) {
+        self.extrapolation = extrapolation;
+    }
+
+    ///Gets the [`Fuzz`] used to spread out due dates
+    #[must_use]
+    pub const fn fuzz(&self) -> Fuzz {
+        self.fuzz
+    }
+
+    ///Sets the [`Fuzz`] used to spread out due dates - this only affects items answered from now on
+    pub const fn set_fuzz(&mut self, fuzz: Fuzz) {
+        self.fuzz = fuzz;
+    }
+
+    ///Gets the indices of all of the eligible items, as of the game's [`Clock`]
+    pub(crate) fn eligible_indices(&self) -> Vec<usize> {
+        get_eligible_at(&self.v, &self.sag, self.extrapolation, self.now())
+    }
+
+    ///Gets the [`JankiConfig`] for the collection
+    #[must_use]
+    pub const fn config(&self) -> &JankiConfig {
+        &self.config
+    }
+
+    ///Sets the [`JankiConfig`] for the collection, including the [`SeeAgainGaps`], and stores it with the database
+    pub fn set_config(&mut self, config: JankiConfig) -> Result<(), ConfigError<S::ErrorType>> {
+        config.save(&mut self.storage)?;
+        self.sag = config.sag();
+        self.extrapolation = config.extrapolation;
+        self.fuzz = config.fuzz;
+        self.config = config;
+        Ok(())
+    }
+
+    ///Seeds the random number generator used for scheduling, so that due dates are the same every time - useful for tests and simulations
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    ///Makes a backup straight away, and then every so often as questions are answered - see [`BackupManager::backup_if_due`]
+    #[must_use]
+    pub fn with_backups(mut self, backups: BackupManager) -> Self {
+        self.set_backups(Some(backups));
+        self
+    }
+
+    ///Sets or removes the [`BackupManager`] - like [`AnkiGame::with_backups`], this makes a backup straight away
+    pub fn set_backups(&mut self, backups: Option<BackupManager>) {
+        self.backups = backups;
+        self.backup_if_due();
+    }
+
+    ///Gets the [`BackupManager`], if backups were turned on with [`AnkiGame::with_backups`] - eg. to use [`BackupManager::list_backups`]
+    #[must_use]
+    pub const fn backups(&self) -> Option<&BackupManager> {
+        self.backups.as_ref()
+    }
+
+    ///Restores a backup, replacing every item in memory and in the [`Storage`]. If backups are turned on, the current items are backed up first
+    pub fn restore_backup(&mut self, backup: &BackupInfo) -> Result<(), BackupError<S::ErrorType>> {
+        let now = self.now();
+        self.v = if let Some(backups) = &mut self.backups {
+            backups.restore_backup(backup, &mut self.storage, now)?
+        } else {
+            let db = BackupManager::read_backup(&self.storage, backup)?;
+            self.storage
+                .write_db(&db)
+                .map_err(BackupError::StorageError)?;
+            db
+        };
+        self.current = None;
+        Ok(())
+    }
+
+    ///Gets the current time from the game's [`Clock`]
+    #[must_use]
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
     ///Adds a new item to the [`AnkiDB`] using [`Into::into`] - which sets the streak to 0, and the last tested to [`Option::None`]
-    pub fn add_fact(&mut self, f: Fact) {
+    ///
+    ///Returns [`AddFactsError::InvalidFact`] without adding the fact if the definition isn't valid for its [`crate::answer::AnswerType`], and [`AddFactsError::StorageError`] if it can't be written - in which case it isn't added
+    pub fn add_fact(&mut self, f: Fact) -> Result<(), AddFactsError<S::ErrorType>> {
         trace!("New fact - {f:?}");
+        f.validate()?;
         self.v.push(f.into());
-        self.storage.write_db(&self.v).unwrap();
+        if let Err(e) = self.storage.upsert_item(&self.v, self.v.len() - 1) {
+            error!("Unable to add fact, rolling back: {e:?}");
+            self.v.pop();
+            return Err(AddFactsError::StorageError(e));
+        }
+        Ok(())
     }
 
     ///Gets all the current eligible facts - the ordering is **not** related to anything
     #[must_use]
     pub fn get_eligible(&self) -> Vec<Fact> {
-        let indices = get_eligible(&self.v, &self.sag);
+        let indices = self.eligible_indices();
         indices
             .into_iter()
             .map(|index| &self.v[index].fact)
@@ -107,7 +481,19 @@ impl<S: Storage, T: AnkiCardReturnType> AnkiGame<S, T> {
     ///Get the number of facts in the eligible list
     #[must_use]
     pub fn get_eligible_no(&self) -> usize {
-        get_eligible(&self.v, &self.sag).len()
+        self.eligible_indices().len()
+    }
+
+    ///Gets the user's [`Profile`] - their XP, level, streaks and achievements
+    #[must_use]
+    pub const fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    ///Works out a [`Stats`] report for all of the items, as of now
+    #[must_use]
+    pub fn stats(&self) -> Stats {
+        Stats::new(&self.v, &self.sag, self.extrapolation, self.now())
     }
 
     ///Gets **all** of the current facts
@@ -116,22 +502,50 @@ impl<S: Storage, T: AnkiCardReturnType> AnkiGame<S, T> {
         self.v.clone().into_iter().map(Into::into).collect()
     }
 
-    ///Deletes a fact at a given index
+    ///Deletes a fact at a given index, and removes it from the [`Storage`]
+    ///
+    ///If the [`Storage`] can't be written to, the fact is put back and the error is returned
     #[instrument(skip(self))]
-    pub fn delete_at_index(&mut self, index: usize) {
+    pub fn delete_at_index(&mut self, index: usize) -> Result<(), S::ErrorType> {
         if self.v.len() > index {
-            self.v.remove(index);
+            let item = self.v.remove(index);
+            if let Err(e) = self.storage.delete_item(&self.v, index) {
+                error!("Unable to delete fact, rolling back: {e:?}");
+                self.v.insert(index, item);
+                return Err(e);
+            }
         }
+        Ok(())
     }
 
-    ///Adds a list of facts to the database
-    pub fn add_facts(&mut self, v: Vec<Fact>) {
+    ///Adds a list of facts to the database, in one [`Storage`] transaction
+    ///
+    ///Returns [`AddFactsError::InvalidFact`] without adding any facts if any of the definitions are invalid, and [`AddFactsError::StorageError`] if they can't be written - in which case none of them are added
+    pub fn add_facts(&mut self, v: Vec<Fact>) -> Result<(), AddFactsError<S::ErrorType>> {
+        for f in &v {
+            f.validate()?;
+        }
+
         let current_facts = self.get_all_facts();
-        for f in v {
-            if !current_facts.contains(&f) {
-                self.add_fact(f);
+        let old_len = self.v.len();
+        let db = &mut self.v;
+        let written = transaction(&mut self.storage, |storage| {
+            for f in v {
+                if !current_facts.contains(&f) {
+                    trace!("New fact - {f:?}");
+                    db.push(f.into());
+                    storage.upsert_item(db, db.len() - 1)?;
+                }
             }
+            Ok(())
+        });
+
+        if let Err(e) = written {
+            error!("Unable to add facts, rolling back: {e:?}");
+            self.v.truncate(old_len);
+            return Err(AddFactsError::StorageError(e));
         }
+        Ok(())
     }
 
     ///Writes to the database - useful if the function is called externally, like in eframe
@@ -139,11 +553,67 @@ impl<S: Storage, T: AnkiCardReturnType> AnkiGame<S, T> {
         self.storage.write_db(&self.v)
     }
 
+    ///Records an answer for the item at a given index, and writes the database - see [`record_and_write`] for what happens if it can't be written
+    pub(crate) fn record_answer(
+        &mut self,
+        index: usize,
+        correct: bool,
+    ) -> Result<(), S::ErrorType> {
+        let now = self.now();
+        let written = record_and_write(
+            &mut self.v,
+            &mut self.profile,
+            &mut self.storage,
+            index,
+            correct,
+            now,
+            Some(Scheduler {
+                sag: &self.sag,
+                extrapolation: self.extrapolation,
+                fuzz: self.fuzz,
+                rng: &mut self.rng,
+            }),
+        );
+        self.backup_if_due();
+        written
+    }
+
+    ///Records an answer given while cramming for the item at a given index, without changing the scheduling, and writes the database
+    pub(crate) fn record_cram_answer(
+        &mut self,
+        index: usize,
+        correct: bool,
+    ) -> Result<(), S::ErrorType> {
+        let now = self.now();
+        let written = record_and_write(
+            &mut self.v,
+            &mut self.profile,
+            &mut self.storage,
+            index,
+            correct,
+            now,
+            None,
+        );
+        self.backup_if_due();
+        written
+    }
+
+    ///Makes a backup if one is due - errors are only logged, as they shouldn't stop the user answering questions
+    fn backup_if_due(&mut self) {
+        let now = self.now();
+        if let Some(backups) = &mut self.backups {
+            if let Err(e) = backups.backup_if_due(&self.storage, &self.v, self.profile.reviews, now)
+            {
+                error!("Unable to make backup: {e}");
+            }
+        }
+    }
+
     ///Gets an index for use in a [`get_new_card`] or [`get_fact`]
     ///
     ///Returns the index to use and a bool for whether the item was taken from the eligible list
     fn get_an_index(&self) -> Option<(usize, bool)> {
-        let eligible = get_eligible(&self.v, &self.sag);
+        let eligible = self.eligible_indices();
 
         if eligible.is_empty() {
             if self.v.is_empty() {
@@ -165,11 +635,26 @@ impl<S: Storage> AnkiGame<S, GiveItemGuards> {
         if self.ig_present {
             return None;
         }
+        //answers from the last ItemGuard have been recorded by now
+        self.backup_if_due();
 
         if let Some((index, was_e)) = self.get_an_index() {
             self.ig_present = true;
             Some((
-                ItemGuard::new(&mut self.v, index, &mut self.storage, &mut self.ig_present),
+                ItemGuard::new(
+                    &mut self.v,
+                    index,
+                    &mut self.storage,
+                    &mut self.profile,
+                    Scheduler {
+                        sag: &self.sag,
+                        extrapolation: self.extrapolation,
+                        fuzz: self.fuzz,
+                        rng: &mut self.rng,
+                    },
+                    &*self.clock,
+                    &mut self.ig_present,
+                ),
                 was_e,
             ))
         } else {
@@ -179,7 +664,15 @@ impl<S: Storage> AnkiGame<S, GiveItemGuards> {
 
     ///Sets the current [`AnkiCardReturnType`] to be [`GiveFacts`] over [`GiveItemGuards`]
     pub fn to_give_facts(self) -> AnkiGame<S, GiveFacts> {
-        AnkiGame::new_params(self.v, self.storage, self.sag)
+        let mut game = AnkiGame::new_params(self.v, self.storage, self.sag);
+        game.extrapolation = self.extrapolation;
+        game.fuzz = self.fuzz;
+        game.rng = self.rng;
+        game.profile = self.profile;
+        game.config = self.config;
+        game.clock = self.clock;
+        game.backups = self.backups;
+        game
     }
 
     ///Function to clean everything up for exit
@@ -228,43 +721,49 @@ impl<S: Storage> AnkiGame<S, GiveFacts> {
     }
 
     ///Signifies that the client is done with the fact.
+    ///
+    ///Returns an error if the answer couldn't be written to the [`Storage`] - the fact is finished either way
     #[instrument(skip(self))]
-    pub fn finish_current_fact(&mut self, correct: Option<bool>) {
+    pub fn finish_current_fact(&mut self, correct: Option<bool>) -> Result<(), S::ErrorType> {
         trace!("Finishing current fact");
 
-        if let Some((cu, _)) = self.current {
+        let current = self.current.take();
+        if let Some((cu, _)) = current {
             if let Some(correct) = correct {
                 event!(Level::INFO, cu, correct, "Finishing current fact");
-
-                self.v[cu].history.push(correct);
-                self.v[cu].last_tested = Some(Utc::now());
-                self.storage
-                    .write_db(&self.v)
-                    .expect("unable to write to db");
-            } else {
-                event!(Level::WARN, cu, "Correct not marked");
+                return self.record_answer(cu, correct);
             }
+            event!(Level::WARN, cu, "Correct not marked");
         }
-
-        self.current = None;
+        Ok(())
     }
 
     ///Sets the current [`AnkiCardReturnType`] to be [`GiveItemGuards`] over [`GiveFacts`]
     pub fn to_give_item_guards(self) -> AnkiGame<S, GiveItemGuards> {
-        AnkiGame::new_params(self.v, self.storage, self.sag)
+        let mut game = AnkiGame::new_params(self.v, self.storage, self.sag);
+        game.extrapolation = self.extrapolation;
+        game.fuzz = self.fuzz;
+        game.rng = self.rng;
+        game.profile = self.profile;
+        game.config = self.config;
+        game.clock = self.clock;
+        game.backups = self.backups;
+        game
     }
 
     ///Function to clean everything up for exit
     #[instrument(skip(self))]
     pub fn exit(&mut self) {
         trace!("Calling exit");
-        self.finish_current_fact(None);
+        //nothing is recorded without an answer, so this can't fail
+        let _ = self.finish_current_fact(None);
         self.storage.exit_application();
     }
 
     ///Clears **all** items from the [`AnkiDB`]
     pub fn clear(&mut self) {
-        self.finish_current_fact(None);
+        //nothing is recorded without an answer, so this can't fail
+        let _ = self.finish_current_fact(None);
         self.v.clear();
     }
 }
@@ -272,11 +771,43 @@ impl<S: Storage> AnkiGame<S, GiveFacts> {
 impl<E: std::fmt::Debug, T: AnkiCardReturnType> DynStorage<E> for AnkiGame<DummyStorage, T> {
     fn read_custom(&mut self, s: &dyn Storage<ErrorType = E>) -> Result<(), E> {
         self.v = s.read_db()?;
+        self.profile = match read_extra_json(s, Profile::KEY) {
+            Ok(profile) => profile,
+            Err(ExtraDataError::StorageError(e)) => return Err(e),
+            Err(ExtraDataError::SJError(e)) => {
+                error!("Unable to parse profile, starting a new one: {e}");
+                Profile::default()
+            }
+        };
+        self.config = match JankiConfig::load(s) {
+            Ok(config) => config,
+            Err(ConfigError::StorageError(e)) => return Err(e),
+            Err(e) => {
+                error!("Unable to parse config, using the default: {e}");
+                JankiConfig::default()
+            }
+        };
+        self.sag = self.config.sag();
+        self.extrapolation = self.config.extrapolation;
+        self.fuzz = self.config.fuzz;
         Ok(())
     }
 
     fn write_custom(&mut self, s: &mut dyn Storage<ErrorType = E>) -> Result<(), E> {
-        s.write_db(&self.v)
+        s.write_db(&self.v)?;
+        match write_extra_json(s, Profile::KEY, &self.profile) {
+            Ok(()) => {}
+            Err(ExtraDataError::StorageError(e)) => return Err(e),
+            Err(ExtraDataError::SJError(e)) => error!("Unable to serialise profile: {e}"),
+        }
+        match self.config.save(s) {
+            Ok(()) => Ok(()),
+            Err(ConfigError::StorageError(e)) => Err(e),
+            Err(e) => {
+                error!("Unable to serialise config: {e}");
+                Ok(())
+            }
+        }
     }
 
     fn exit_custom(&mut self, s: &mut dyn Storage<ErrorType = E>) {
@@ -284,23 +815,256 @@ impl<E: std::fmt::Debug, T: AnkiCardReturnType> DynStorage<E> for AnkiGame<Dummy
     }
 }
 
-///A function to get all of the indexes that need to be tested from a list using a [`SeeAgainGaps`]
+///Records an answer for the item at a given index - updating the item, the [`Profile`] and any achievements, and then writes the answer and profile to the [`Storage`] in one transaction.
+///
+///The [`Scheduler`] is used to work out when the item is next due. If it is [`Option::None`], the answer is only logged with [`Item::record_cram`], so the scheduling isn't changed.
+///
+///If the [`Storage`] can't be written to, the item and profile are put back to how they were and the error is returned
+pub(crate) fn record_and_write<S: Storage>(
+    v: &mut AnkiDB,
+    profile: &mut Profile,
+    storage: &mut S,
+    index: usize,
+    correct: bool,
+    now: DateTime<Utc>,
+    scheduler: Option<Scheduler>,
+) -> Result<(), S::ErrorType> {
+    if index >= v.len() {
+        warn!("Recording an answer for an item that doesn't exist");
+        return Ok(());
+    }
+    let (old_item, old_profile) = (v[index].clone(), profile.clone());
+
+    profile.record_review(&v[index], correct, now);
+    if let Some(mut scheduler) = scheduler {
+        v[index].record(correct, now);
+        v[index].due = scheduler.due(v, index, now);
+    } else {
+        v[index].record_cram(correct, now);
+    }
+    profile.unlock_achievements(v, now);
+
+    let written = transaction(storage, |storage| {
+        storage.append_review(v, index)?;
+        match serde_json::to_string(profile) {
+            Ok(contents) => storage.write_extra(Profile::KEY, &contents),
+            Err(e) => {
+                error!("Unable to serialise profile: {e}");
+                Ok(())
+            }
+        }
+    });
+
+    if let Err(e) = written {
+        error!("Unable to record answer, rolling back: {e:?}");
+        v[index] = old_item;
+        *profile = old_profile;
+        return Err(e);
+    }
+    Ok(())
+}
+
+///A function to get all of the indexes that need to be tested from a list using a [`SeeAgainGaps`], and the default [`Extrapolation`] for longer streaks
 #[must_use]
 pub fn get_eligible(items: &[Item], sag: &SeeAgainGaps) -> Vec<usize> {
+    get_eligible_at(items, sag, Extrapolation::default(), Utc::now())
+}
+
+///Like [`get_eligible`], but with a given [`Extrapolation`] and for a given time rather than now.
+///
+///Items with a stored due date (from a [`Fuzz`]) are eligible once it has passed - otherwise the gap for the streak is used
+#[must_use]
+pub fn get_eligible_at(
+    items: &[Item],
+    sag: &SeeAgainGaps,
+    extrapolation: Extrapolation,
+    now: DateTime<Utc>,
+) -> Vec<usize> {
     items
         .iter()
         .enumerate()
         .filter_map(|(index, item)| {
-            item.time_since_last_test()
+            if let Some(due) = item.due {
+                return (now >= due).then_some(index);
+            }
+
+            item.time_since_last_test_at(now)
                 .map_or(Some(index), |last_seen| {
-                    sag.get(&item.get_streak()).map_or(Some(index), |distance| {
-                        if &last_seen > distance {
-                            Some(index)
-                        } else {
-                            None
-                        }
-                    })
+                    extrapolation
+                        .gap(sag, item.get_streak())
+                        .map_or(Some(index), |distance| {
+                            if last_seen > distance {
+                                Some(index)
+                            } else {
+                                None
+                            }
+                        })
                 })
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        clock::MockClock,
+        config::HumanDuration,
+        dummy_storage::DummyStorage,
+        game::{
+            default_sag, get_eligible_at, AnkiGame, Extrapolation, Fuzz, GiveFacts, GiveItemGuards,
+        },
+        item::Item,
+        test_utils::f,
+    };
+    use chrono::{Duration, TimeZone, Utc};
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::collections::{BTreeMap, HashMap};
+
+    #[test]
+    fn extrapolation_test() {
+        let sag = default_sag();
+        let capped = Extrapolation::Capped {
+            factor: 2.0,
+            max: HumanDuration(Duration::minutes(15)),
+        };
+        let doubling = Extrapolation::Multiplicative { factor: 2.0 };
+
+        for ex in [
+            Extrapolation::Immediate,
+            Extrapolation::LastGap,
+            doubling,
+            capped,
+        ] {
+            assert_eq!(ex.gap(&sag, 0), None);
+            assert_eq!(ex.gap(&sag, 3), Some(Duration::seconds(90)));
+        }
+
+        assert_eq!(Extrapolation::Immediate.gap(&sag, 11), None);
+        assert_eq!(
+            Extrapolation::LastGap.gap(&sag, 25),
+            Some(Duration::seconds(300))
+        );
+        assert_eq!(doubling.gap(&sag, 12), Some(Duration::seconds(1200)));
+        assert_eq!(capped.gap(&sag, 11), Some(Duration::seconds(600)));
+        assert_eq!(capped.gap(&sag, 1000), Some(Duration::minutes(15)));
+        //really long streaks shouldn't overflow
+        assert!(doubling.gap(&sag, u32::MAX).unwrap() > Duration::days(365 * 99));
+
+        //missing streaks shorter than the longest one are still eligible straight away
+        let sparse = HashMap::from([(1, Duration::minutes(1)), (5, Duration::hours(1))]);
+        assert_eq!(doubling.gap(&sparse, 3), None);
+        assert_eq!(doubling.gap(&sparse, 6), Some(Duration::hours(2)));
+    }
+
+    #[test]
+    fn long_streak_test() {
+        let now = Utc::now();
+        let mut item = Item::new(f("a", "b"));
+        item.history = vec![true; 20];
+        item.last_tested = Some(now - Duration::minutes(10));
+        assert_eq!(item.get_streak(), 20);
+
+        let items = vec![item];
+        let sag = default_sag();
+        let eligible = |ex, now| get_eligible_at(&items, &sag, ex, now);
+
+        assert_eq!(eligible(Extrapolation::Immediate, now), vec![0]);
+        assert_eq!(eligible(Extrapolation::LastGap, now), vec![0]);
+        assert!(eligible(Extrapolation::LastGap, now - Duration::minutes(6)).is_empty());
+        assert!(eligible(Extrapolation::default(), now).is_empty());
+        assert!(eligible(Extrapolation::Multiplicative { factor: 1.5 }, now).is_empty());
+        assert_eq!(
+            eligible(Extrapolation::default(), now + Duration::days(366)),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn fuzz_test() {
+        let now = Utc.with_ymd_and_hms(2022, 7, 1, 12, 0, 0).unwrap();
+        let fuzz = Fuzz::default();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        //short gaps aren't fuzzed
+        let short = Duration::hours(1);
+        assert_eq!(
+            fuzz.due(now, short, &mut rng, std::iter::empty()),
+            now + short
+        );
+        assert_eq!(
+            Fuzz::none().due(now, Duration::days(30), &mut rng, std::iter::empty()),
+            now + Duration::days(30)
+        );
+
+        //load balancing picks the quietest day, and never moves more than the range
+        let gap = Duration::days(10);
+        let busy = vec![now + gap; 5];
+        let due = fuzz.due(now, gap, &mut rng, busy.into_iter());
+        assert_ne!(due, now + gap);
+        assert!((due - (now + gap)).num_days().abs() == 1);
+
+        let random = Fuzz {
+            load_balance: false,
+            ..Fuzz::default()
+        };
+        for _ in 0..100 {
+            let due = random.due(now, gap, &mut rng, std::iter::empty());
+            assert!((due - (now + gap)).num_seconds().abs() <= 86_400);
+        }
+    }
+
+    #[test]
+    fn spread_test() {
+        let now = Utc.with_ymd_and_hms(2022, 7, 1, 12, 0, 0).unwrap();
+        let sag = HashMap::from([(1, Duration::days(10))]);
+        let answer_all = |seed| {
+            let mut game: AnkiGame<DummyStorage, GiveFacts> =
+                AnkiGame::new_params(vec![], DummyStorage::default(), sag.clone())
+                    .with_clock(MockClock::new(now))
+                    .with_seed(seed);
+            game.v = (0..500)
+                .map(|i| Item::new(f(i.to_string(), "def")))
+                .collect();
+            for i in 0..500 {
+                game.record_answer(i, true).unwrap();
+            }
+            game.v
+        };
+
+        let dues = |items: &[Item]| items.iter().map(|i| i.due).collect::<Vec<_>>();
+        let items = answer_all(0);
+        assert_eq!(dues(&items), dues(&answer_all(0)));
+        assert_ne!(dues(&items), dues(&answer_all(1)));
+
+        let mut per_day: BTreeMap<_, usize> = BTreeMap::new();
+        for item in &items {
+            *per_day.entry(item.due.unwrap().date_naive()).or_default() += 1;
+        }
+        assert_eq!(per_day.len(), 3);
+        assert!(
+            per_day.values().all(|n| (166..=167).contains(n)),
+            "{per_day:?}"
+        );
+
+        let eligible = |at| get_eligible_at(&items, &sag, Extrapolation::default(), at).len();
+        assert_eq!(eligible(now + Duration::days(9) - Duration::seconds(1)), 0);
+        assert_eq!(eligible(now + Duration::days(11)), 500);
+    }
+
+    #[test]
+    fn send_test() {
+        fn assert_send<T: Send>() {}
+        assert_send::<AnkiGame<DummyStorage, GiveFacts>>();
+        assert_send::<AnkiGame<DummyStorage, GiveItemGuards>>();
+
+        //a mock clock can be moved to another thread with the game, and still be controlled from this one
+        let now = Utc.with_ymd_and_hms(2022, 7, 1, 12, 0, 0).unwrap();
+        let clock = MockClock::new(now);
+        let game = AnkiGame::<_, GiveFacts>::new(DummyStorage::default(), default_sag())
+            .unwrap()
+            .with_clock(clock.clone());
+        clock.advance(Duration::days(1));
+        let later = std::thread::spawn(move || game.now()).join().unwrap();
+        assert_eq!(later, now + Duration::days(1));
+    }
+}
@@ -1,4 +1,10 @@
-use crate::{game::AnkiDB, storage::Storage};
+use crate::{
+    answer::{AnswerError, AnswerType},
+    clock::Clock,
+    game::{record_and_write, AnkiDB, Extrapolation, Scheduler, SeeAgainGaps},
+    profile::Profile,
+    storage::Storage,
+};
 use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use std::{fmt::Display, ops::Deref};
@@ -14,16 +20,39 @@ pub struct Fact {
     pub term: String,
     ///The definition of the fact - the test taker gives this.
     pub definition: String,
+    ///How answers are checked against the definition - defaults to [`AnswerType::Text`]
+    #[serde(default)]
+    pub answer_type: AnswerType,
 }
 
 impl Fact {
-    ///Fact constructor using [`Into`]
+    ///Fact constructor using [`Into`] - uses [`AnswerType::Text`]
     pub fn new(term: impl Into<String>, definition: impl Into<String>) -> Self {
         Self {
             term: term.into(),
             definition: definition.into(),
+            answer_type: AnswerType::default(),
         }
     }
+
+    ///Sets the [`AnswerType`] used to check answers to this fact
+    #[must_use]
+    pub const fn with_answer_type(mut self, answer_type: AnswerType) -> Self {
+        self.answer_type = answer_type;
+        self
+    }
+
+    ///Checks whether or not an answer is correct for this fact, using the [`AnswerType`]
+    ///
+    ///Can return an [`AnswerError`] if the definition isn't valid for the [`AnswerType`]
+    pub fn check(&self, answer: &str) -> Result<bool, AnswerError> {
+        self.answer_type.check(&self.definition, answer)
+    }
+
+    ///Checks that the definition is valid for the [`AnswerType`] - eg. that a pattern is a valid regular expression
+    pub fn validate(&self) -> Result<(), AnswerError> {
+        self.answer_type.validate(&self.definition)
+    }
 }
 
 impl Display for Fact {
@@ -32,10 +61,21 @@ impl Display for Fact {
     }
 }
 
+///A single answer to an [`Item`], and when it was given
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Review {
+    ///When the answer was given
+    pub at: DateTime<Utc>,
+    ///Whether or not the answer was correct
+    pub correct: bool,
+    ///Whether or not the answer was given while cramming - if so, it didn't affect the scheduling
+    pub cram: bool,
+}
+
 ///An Item - contains a fact, as well as stats about the user's history with that fact.
 ///
 ///Often accessed in the client via an [`ItemGuard`]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Item {
     ///The fact that is the focus of the item
     pub fact: Fact,
@@ -51,6 +91,16 @@ pub struct Item {
     ///
     ///Clients should never directly access this, as this is set via an [`ItemGuard`] or otherwise
     pub(crate) history: Vec<bool>,
+    ///Every answer given for this fact, including ones given while cramming which aren't in the `history`.
+    ///
+    ///Clients should never directly access this, as this is set via an [`ItemGuard`] or otherwise
+    #[serde(default)]
+    pub(crate) reviews: Vec<Review>,
+    ///When the item is next due, if it was scheduled with a [`crate::game::Fuzz`]. If this is [`Option::None`], the gap for the current streak is used instead.
+    ///
+    ///Clients should never directly access this, as this is set via an [`ItemGuard`] or otherwise
+    #[serde(default)]
+    pub(crate) due: Option<DateTime<Utc>>,
 }
 
 impl From<Fact> for Item {
@@ -72,6 +122,8 @@ impl Item {
             fact,
             last_tested: None,
             history: vec![],
+            reviews: vec![],
+            due: None,
         }
     }
 
@@ -87,42 +139,78 @@ impl Item {
             fact,
             last_tested: Some(last_tested),
             history,
+            reviews: vec![],
+            due: None,
         }
     }
 
     ///Gets the user's streak for that fact - AKA the number of times in a row that they have answered correctly, with a correction factor to not make the user start from beginning on every mistake.
     #[must_use]
     pub fn get_streak(&self) -> u32 {
-        let min = if self.history.contains(&true) && self.true_streak() > 0 {
-            1
-        } else {
-            0
-        };
-
-        let mut count = 0;
-        for b in &self.history {
-            if *b {
-                count += 1;
-            } else {
-                count /= 2;
-            }
-        }
+        streak_of(&self.history)
+    }
+
+    ///Records an answer given at `now` - pushes it to the `history` and `reviews`, and sets `last_tested`
+    pub(crate) fn record(&mut self, correct: bool, now: DateTime<Utc>) {
+        self.history.push(correct);
+        self.last_tested = Some(now);
+        self.reviews.push(Review {
+            at: now,
+            correct,
+            cram: false,
+        });
+    }
+
+    ///Records an answer given while cramming - this only pushes to the `reviews`, so the scheduling isn't affected
+    pub(crate) fn record_cram(&mut self, correct: bool, now: DateTime<Utc>) {
+        self.reviews.push(Review {
+            at: now,
+            correct,
+            cram: true,
+        });
+    }
+
+    ///Gets every answer given for this fact, oldest first - including ones given while cramming
+    #[must_use]
+    pub fn reviews(&self) -> &[Review] {
+        &self.reviews
+    }
 
-        count.min(min)
+    ///Gets how difficult the user finds this fact, from `0.0` to `1.0` - the fraction of answers in the `history` that were wrong.
+    ///
+    ///This starts with one imaginary right and one imaginary wrong answer, so new facts are `0.5`
+    #[must_use]
+    pub fn difficulty(&self) -> f64 {
+        let wrong = self.history.iter().filter(|b| !**b).count();
+
+        #[allow(clippy::cast_precision_loss)]
+        let difficulty = (wrong + 1) as f64 / (self.history.len() + 2) as f64;
+        difficulty
     }
 
     ///Gets the user's streak - the number of times they have correctly answered in a row
     pub(crate) fn true_streak(&self) -> u32 {
-        let mut count = 0;
-        for b in &self.history {
-            if *b {
-                count += 1;
-            } else {
-                count = 0;
-            }
+        true_streak_of(&self.history)
+    }
+
+    ///Gets when this fact is next due to be tested, using the gap for the current streak.
+    ///
+    ///Returns [`Option::None`] if the user has never been tested on it. If there's a stored due date, that is used, and if there's no gap for the streak, it is due straight away, like in [`crate::game::get_eligible`]
+    #[must_use]
+    pub fn next_due(
+        &self,
+        sag: &SeeAgainGaps,
+        extrapolation: Extrapolation,
+    ) -> Option<DateTime<Utc>> {
+        if self.due.is_some() {
+            return self.due;
         }
 
-        count
+        self.last_tested.map(|last_tested| {
+            extrapolation
+                .gap(sag, self.get_streak())
+                .map_or(last_tested, |gap| last_tested + gap)
+        })
     }
 
     ///Gets the time since the user was last tested on this fact.
@@ -131,8 +219,15 @@ impl Item {
     #[must_use]
     #[instrument(skip(self))]
     pub fn time_since_last_test(&self) -> Option<Duration> {
+        self.time_since_last_test_at(Utc::now())
+    }
+
+    ///Like [`Item::time_since_last_test`], but for a given time rather than now
+    #[must_use]
+    #[instrument(skip(self))]
+    pub fn time_since_last_test_at(&self, now: DateTime<Utc>) -> Option<Duration> {
         if let Some(last_tested) = self.last_tested {
-            let diff = Utc::now() - last_tested;
+            let diff = now - last_tested;
             let zero = Duration::zero();
 
             if diff > zero {
@@ -145,6 +240,42 @@ impl Item {
     }
 }
 
+///Gets the streak used for scheduling from a history of answers - see [`Item::get_streak`].
+///
+///Every right answer adds one to the streak, and every wrong answer halves it. If the last answer was right, the streak is at least 1
+pub(crate) fn streak_of(history: &[bool]) -> u32 {
+    let min = if history.contains(&true) && true_streak_of(history) > 0 {
+        1
+    } else {
+        0
+    };
+
+    let mut count = 0;
+    for b in history {
+        if *b {
+            count += 1;
+        } else {
+            count /= 2;
+        }
+    }
+
+    count.max(min)
+}
+
+///Gets the number of correct answers in a row at the end of a history of answers
+pub(crate) fn true_streak_of(history: &[bool]) -> u32 {
+    let mut count = 0;
+    for b in history {
+        if *b {
+            count += 1;
+        } else {
+            count = 0;
+        }
+    }
+
+    count
+}
+
 ///Guard for [`Item`] for Client use.
 ///
 ///On [`Drop::drop`], the [`crate::game::AnkiGame`] is updated, and as of such only one [`ItemGuard`] can exist per [`crate::game::AnkiGame`]
@@ -156,6 +287,12 @@ pub struct ItemGuard<'a, S: Storage> {
     index: usize,
     ///A mutable reference to the [`Storage`] for the [`crate::game::AnkiGame`]
     s: &'a mut S,
+    ///A mutable reference to the [`Profile`] for the [`crate::game::AnkiGame`]
+    profile: &'a mut Profile,
+    ///The [`Scheduler`] for the [`crate::game::AnkiGame`], for when the item is next due
+    scheduler: Scheduler<'a>,
+    ///The [`Clock`] for the [`crate::game::AnkiGame`], for when the answer is recorded
+    clock: &'a dyn Clock,
     ///A mutable reference to a bool to set to false on [`std::drop::Drop`] to allow the [`AnkiGame`] to get a new [`ItemGuard`]
     present: &'a mut bool,
 
@@ -168,15 +305,32 @@ pub struct ItemGuard<'a, S: Storage> {
 impl<'a, S: Storage> Drop for ItemGuard<'a, S> {
     ///On drop, assuming the question was answered (AKA `self.was_successful.is_some()`), the following happens:
     ///
-    /// - the `history` and `last_tested` of the underlying item are updated.
-    /// - the database is written using [`Storage::write_db`]
+    /// - the `history` and `last_tested` of the underlying item are updated, as well as the [`Profile`].
+    /// - the new answer is written using [`Storage::append_review`]
+    ///
+    ///Whether or not it was answered, the [`crate::game::AnkiGame`] can then give out a new [`ItemGuard`]
     fn drop(&mut self) {
+        *self.present = false;
+
         if let Some(ws) = self.was_succesful {
-            if let Some(el) = self.v.get_mut(self.index) {
-                el.history.push(ws);
-                el.last_tested = Some(Utc::now());
-                self.s.write_db(self.v).unwrap();
-                *self.present = false;
+            if self.index < self.v.len() {
+                //there's nowhere to return an error to from a drop, so it can only be logged - the item is left as it was
+                if let Err(e) = record_and_write(
+                    self.v,
+                    self.profile,
+                    self.s,
+                    self.index,
+                    ws,
+                    self.clock.now(),
+                    Some(Scheduler {
+                        sag: self.scheduler.sag,
+                        extrapolation: self.scheduler.extrapolation,
+                        fuzz: self.scheduler.fuzz,
+                        rng: self.scheduler.rng,
+                    }),
+                ) {
+                    error!("Unable to record answer: {e:?}");
+                }
 
                 //TODO: ability to invalidate an IG
             }
@@ -198,6 +352,9 @@ impl<'a, S: Storage> ItemGuard<'a, S> {
         v: &'a mut AnkiDB,
         index: usize,
         s: &'a mut S,
+        profile: &'a mut Profile,
+        scheduler: Scheduler<'a>,
+        clock: &'a dyn Clock,
         present: &'a mut bool,
     ) -> Self {
         Self {
@@ -205,6 +362,9 @@ impl<'a, S: Storage> ItemGuard<'a, S> {
             index,
             was_succesful: None,
             s,
+            profile,
+            scheduler,
+            clock,
             present,
         }
     }
@@ -212,9 +372,38 @@ impl<'a, S: Storage> ItemGuard<'a, S> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{item::Item, test_utils::f};
+    use crate::{
+        answer::{AnswerType, NumericOptions},
+        dummy_storage::DummyStorage,
+        game::{default_sag, AnkiGame, GiveItemGuards},
+        item::Item,
+        test_utils::f,
+    };
     use chrono::{Duration, Utc};
 
+    #[test]
+    fn unanswered_guard_test() {
+        let mut game: AnkiGame<DummyStorage, GiveItemGuards> =
+            AnkiGame::new_params(vec![], DummyStorage::default(), default_sag());
+        //like from a hand-edited database - this can't be added normally
+        game.v
+            .push(Item::new(f("pi", "not a number").with_answer_type(
+                AnswerType::Numeric(NumericOptions::default()),
+            )));
+
+        let (guard, _) = game.get_item_guard().unwrap();
+        drop(guard);
+
+        let (mut guard, _) = game.get_item_guard().unwrap();
+        if let Ok(correct) = guard.check("3.14") {
+            guard.was_succesful = Some(correct);
+        }
+        drop(guard);
+
+        assert!(game.get_item_guard().is_some());
+        assert!(game.v[0].history.is_empty());
+    }
+
     #[test]
     fn get_true_streak_test() {
         let mut f = Item::new(f("", ""));
@@ -234,6 +423,23 @@ mod tests {
         assert_eq!(f.true_streak(), 3);
     }
 
+    #[test]
+    fn get_streak_test() {
+        let mut f = Item::new(f("", ""));
+        f.history = vec![true, true, true];
+        assert_eq!(f.get_streak(), 3);
+        //a mistake halves the streak
+        f.history = vec![true, true, true, true, false];
+        assert_eq!(f.get_streak(), 2);
+        f.history = vec![true, true, true, true, false, true];
+        assert_eq!(f.get_streak(), 3);
+        //but a right answer always gives at least 1
+        f.history = vec![false, false, true];
+        assert_eq!(f.get_streak(), 1);
+        f.history = vec![false];
+        assert_eq!(f.get_streak(), 0);
+    }
+
     #[test]
     fn item_timing_test() {
         let mut f = Item::new(f("", ""));
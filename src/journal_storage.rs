@@ -0,0 +1,568 @@
+use crate::{file_storage::write_atomic, game::AnkiDB, item::Item, storage::Storage};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::PathBuf,
+};
+use thiserror::Error;
+
+///The default number of changes to keep in the journal before it is compacted into the snapshot
+pub const DEFAULT_COMPACT_EVERY: usize = 1000;
+
+///One change to the database, stored as a line in the journal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op")]
+pub enum JournalEntry {
+    ///An item was added to the end
+    Add {
+        ///The new item
+        item: Item,
+    },
+    ///An item was answered - this is replayed with the same logic as the [`crate::game::AnkiGame`] so only the answer needs storing
+    Review {
+        ///The index of the item
+        index: usize,
+        ///Whether or not the answer was correct
+        correct: bool,
+        ///Whether or not the answer was given while cramming
+        cram: bool,
+        ///When the answer was given
+        at: DateTime<Utc>,
+        ///When the item is next due, if it has a due date
+        due: Option<DateTime<Utc>>,
+    },
+    ///An item was changed
+    Edit {
+        ///The index of the item
+        index: usize,
+        ///The whole item, after the change
+        item: Item,
+    },
+    ///An item was deleted, and every item after it moved down one
+    Delete {
+        ///The index of the item
+        index: usize,
+    },
+}
+
+impl JournalEntry {
+    ///Applies the change to a database, or returns [`Option::None`] if the index doesn't exist
+    fn apply(self, db: &mut AnkiDB) -> Option<()> {
+        match self {
+            Self::Add { item } => db.push(item),
+            Self::Review {
+                index,
+                correct,
+                cram,
+                at,
+                due,
+            } => {
+                let item = db.get_mut(index)?;
+                if cram {
+                    item.record_cram(correct, at);
+                } else {
+                    item.record(correct, at);
+                    item.due = due;
+                }
+            }
+            Self::Edit { index, item } => *db.get_mut(index)? = item,
+            Self::Delete { index } => {
+                if index >= db.len() {
+                    return None;
+                }
+                db.remove(index);
+            }
+        }
+        Some(())
+    }
+}
+
+///A line in the journal - a [`JournalEntry`], when it was written, and its place in the journal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalLine {
+    ///Goes up by one for every line ever written, even after compaction - so the snapshot knows which lines it already has
+    pub seq: u64,
+    ///When the line was written
+    pub written: DateTime<Utc>,
+    ///The change
+    #[serde(flatten)]
+    pub entry: JournalEntry,
+}
+
+///The whole database, as of a given line in the journal
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Snapshot {
+    ///The `seq` of the last [`JournalLine`] in the snapshot
+    seq: u64,
+    ///The items
+    items: AnkiDB,
+}
+
+///A struct implementing [`Storage`] using an append-only journal next to a snapshot.
+///
+///Every change is added to the end of the journal as a line of JSON and synced to disk, so a crash can only lose the change that was being written. Reading replays the journal on top of the snapshot, and every [`JournalStorage::compact_every`] changes (or on [`Storage::write_db`]) everything is written to a new snapshot and the journal is emptied.
+///
+///Extra data is stored next to the snapshot, like with [`crate::file_storage::NamedFileStorage`]
+#[derive(Debug)]
+pub struct JournalStorage {
+    ///The path to the snapshot - the journal is next to it, with the `journal` extension
+    path: PathBuf,
+    ///How many changes to keep in the journal before compacting it - `0` never compacts automatically
+    pub compact_every: usize,
+    ///The `seq` of the last line written
+    seq: u64,
+    ///The number of lines in the journal
+    lines: usize,
+    ///The number of items, to tell additions from edits
+    len: usize,
+    ///Lines waiting to be written, if in a transaction
+    pending: Option<Vec<JournalLine>>,
+}
+
+///Struct using `thiserror` that contains all errors for [`JournalStorage`]
+#[derive(Error, Debug)]
+pub enum JournalStorageError {
+    ///Error reading or writing a file - comes from [`std::io::Error`]
+    #[error("io error: {0}")]
+    IOError(#[from] std::io::Error),
+
+    ///Error with `serde_json` - comes from [`serde_json::Error`]
+    #[error("serde_json error: {0}")]
+    SJError(#[from] serde_json::Error),
+
+    ///A line in the middle of the journal couldn't be read - only the last line can be safely skipped, as it might have been cut off by a crash
+    #[error("corrupt journal on line {line}: {error}")]
+    CorruptLine {
+        ///The line number, starting from 1
+        line: usize,
+        ///The error from parsing it
+        error: serde_json::Error,
+    },
+
+    ///A line in the journal refers to an item which doesn't exist
+    #[error("journal line {0} refers to an item which doesn't exist")]
+    MissingItem(u64),
+}
+
+impl JournalStorage {
+    ///Opens the journal and snapshot at a given path - the snapshot is at the path, and the journal is next to it.
+    ///
+    ///If the last line of the journal was cut off by a crash, it is removed so that new lines don't get added on to the end of it. Nothing is created until the first change
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, JournalStorageError> {
+        let mut s = Self {
+            path: path.into(),
+            compact_every: DEFAULT_COMPACT_EVERY,
+            seq: 0,
+            lines: 0,
+            len: 0,
+            pending: None,
+        };
+
+        s.repair_last_line()?;
+        let (snapshot, lines) = s.read_all()?;
+        s.seq = lines
+            .last()
+            .map_or(snapshot.seq, |l| l.seq.max(snapshot.seq));
+        s.lines = lines.len();
+        s.len = replay(snapshot, lines)?.len();
+        Ok(s)
+    }
+
+    ///Sets how many changes to keep in the journal before compacting it
+    #[must_use]
+    pub const fn with_compact_every(mut self, compact_every: usize) -> Self {
+        self.compact_every = compact_every;
+        self
+    }
+
+    ///Gets the path of the journal
+    #[must_use]
+    pub fn journal_path(&self) -> PathBuf {
+        self.path.with_extension("journal")
+    }
+
+    ///Gets the path that extra data for a key is stored at - next to the snapshot, like [`crate::file_storage::NamedFileStorage::extra_path`]
+    #[must_use]
+    pub fn extra_path(&self, key: &str) -> PathBuf {
+        self.path.with_extension(key)
+    }
+
+    ///Gets every change since the last compaction, oldest first - useful as an audit trail
+    pub fn journal(&self) -> Result<Vec<JournalLine>, JournalStorageError> {
+        let (snapshot, lines) = self.read_all()?;
+        Ok(lines.into_iter().filter(|l| l.seq > snapshot.seq).collect())
+    }
+
+    ///Writes everything to a new snapshot, and empties the journal
+    #[instrument(skip(self, db))]
+    pub fn compact(&mut self, db: &AnkiDB) -> Result<(), JournalStorageError> {
+        trace!("Compacting journal");
+        let snapshot = Snapshot {
+            seq: self.seq,
+            items: db.clone(),
+        };
+
+        //the new snapshot is written atomically, so there is always a whole one on disk. If it crashes before the journal is emptied, the snapshot's `seq` means the old lines are skipped
+        write_atomic(&self.path, &serde_json::to_vec(&snapshot)?, None)?;
+        File::create(self.journal_path())?.sync_all()?;
+
+        self.lines = 0;
+        self.len = db.len();
+        Ok(())
+    }
+
+    ///Makes sure that the journal ends with a newline, so the next line is written on its own.
+    ///
+    ///A last line which can be read but is missing its newline gets one, and one which can't be read was cut off, so it is removed
+    fn repair_last_line(&self) -> Result<(), JournalStorageError> {
+        let contents = match std::fs::read(self.journal_path()) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if contents.is_empty() || contents.ends_with(b"\n") {
+            return Ok(());
+        }
+
+        let start = contents
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(0, |i| i + 1);
+        let mut file = OpenOptions::new().append(true).open(self.journal_path())?;
+        if serde_json::from_slice::<JournalLine>(&contents[start..]).is_ok() {
+            trace!("Adding missing newline to the end of the journal");
+            file.write_all(b"\n")?;
+        } else {
+            warn!("Removing cut-off last line of the journal");
+            file.set_len(start as u64)?;
+        }
+        file.sync_data()?;
+        Ok(())
+    }
+
+    ///Reads the snapshot and every line in the journal
+    fn read_all(&self) -> Result<(Snapshot, Vec<JournalLine>), JournalStorageError> {
+        let snapshot = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Snapshot::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let file = match File::open(self.journal_path()) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok((snapshot, vec![])),
+            Err(e) => return Err(e.into()),
+        };
+
+        let raw: Vec<String> = BufReader::new(file).lines().collect::<Result<_, _>>()?;
+        let mut lines = Vec::with_capacity(raw.len());
+        for (i, line) in raw.iter().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(line) => lines.push(line),
+                Err(error) if i + 1 == raw.len() => {
+                    warn!("Skipping cut-off last line of the journal: {error}");
+                }
+                Err(error) => return Err(JournalStorageError::CorruptLine { line: i + 1, error }),
+            }
+        }
+
+        Ok((snapshot, lines))
+    }
+
+    ///Adds a change to the journal, or to the pending lines if in a transaction, and compacts the journal if it is long enough
+    fn append(&mut self, entry: JournalEntry, db: &AnkiDB) -> Result<(), JournalStorageError> {
+        self.seq += 1;
+        self.len = db.len();
+        let line = JournalLine {
+            seq: self.seq,
+            written: Utc::now(),
+            entry,
+        };
+
+        if let Some(pending) = &mut self.pending {
+            pending.push(line);
+            return Ok(());
+        }
+
+        self.write_lines(&[line])?;
+        if self.should_compact() {
+            self.compact(db)?;
+        }
+        Ok(())
+    }
+
+    ///Whether or not the journal is long enough to be compacted
+    const fn should_compact(&self) -> bool {
+        self.compact_every > 0 && self.lines >= self.compact_every
+    }
+
+    ///Writes lines to the end of the journal and syncs it
+    fn write_lines(&mut self, lines: &[JournalLine]) -> Result<(), JournalStorageError> {
+        let mut contents = String::new();
+        for line in lines {
+            contents += &serde_json::to_string(line)?;
+            contents.push('\n');
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.journal_path())?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_data()?;
+        self.lines += lines.len();
+        Ok(())
+    }
+}
+
+///Replays the lines of a journal on top of a snapshot, skipping any that are already in it
+fn replay(snapshot: Snapshot, lines: Vec<JournalLine>) -> Result<AnkiDB, JournalStorageError> {
+    let mut db = snapshot.items;
+    for line in lines.into_iter().filter(|l| l.seq > snapshot.seq) {
+        line.entry
+            .apply(&mut db)
+            .ok_or(JournalStorageError::MissingItem(line.seq))?;
+    }
+    Ok(db)
+}
+
+impl Storage for JournalStorage {
+    type ErrorType = JournalStorageError;
+
+    #[instrument(skip(self))]
+    fn read_db(&self) -> Result<AnkiDB, Self::ErrorType> {
+        trace!("Reading from JournalStorage");
+        let (snapshot, lines) = self.read_all()?;
+        replay(snapshot, lines)
+    }
+
+    #[instrument(skip(self, db))]
+    fn write_db(&mut self, db: &AnkiDB) -> Result<(), Self::ErrorType> {
+        trace!("Writing to JournalStorage");
+        if let Some(pending) = &mut self.pending {
+            //the whole database is being replaced, so nothing before it in the transaction matters
+            pending.clear();
+        }
+        self.compact(db)
+    }
+
+    #[instrument(skip(self, db))]
+    fn upsert_item(&mut self, db: &AnkiDB, index: usize) -> Result<(), Self::ErrorType> {
+        let item = db[index].clone();
+        let entry = if index == self.len && db.len() == self.len + 1 {
+            JournalEntry::Add { item }
+        } else {
+            JournalEntry::Edit { index, item }
+        };
+        self.append(entry, db)
+    }
+
+    #[instrument(skip(self, db))]
+    fn delete_item(&mut self, db: &AnkiDB, index: usize) -> Result<(), Self::ErrorType> {
+        self.append(JournalEntry::Delete { index }, db)
+    }
+
+    #[instrument(skip(self, db))]
+    fn append_review(&mut self, db: &AnkiDB, index: usize) -> Result<(), Self::ErrorType> {
+        let item = &db[index];
+        let Some(review) = item.reviews.last() else {
+            return self.upsert_item(db, index);
+        };
+
+        let entry = JournalEntry::Review {
+            index,
+            correct: review.correct,
+            cram: review.cram,
+            at: review.at,
+            due: item.due,
+        };
+        self.append(entry, db)
+    }
+
+    #[instrument(skip(self))]
+    fn begin_transaction(&mut self) -> Result<(), Self::ErrorType> {
+        trace!("Beginning transaction");
+        self.pending.get_or_insert_with(Vec::new);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    fn commit_transaction(&mut self) -> Result<(), Self::ErrorType> {
+        trace!("Committing transaction");
+        let Some(pending) = self.pending.take() else {
+            return Ok(());
+        };
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        //all of the lines are written in one go, so it's all or nothing - apart from a cut-off last line, which is skipped when reading
+        self.write_lines(&pending)?;
+        //the whole database is only needed for compacting, so it's only read then
+        if self.should_compact() {
+            let db = self.read_db()?;
+            self.compact(&db)?;
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    fn rollback_transaction(&mut self) -> Result<(), Self::ErrorType> {
+        trace!("Rolling back transaction");
+        self.pending = None;
+        self.len = self.read_db()?.len();
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    fn read_extra(&self, key: &str) -> Result<Option<String>, Self::ErrorType> {
+        trace!("Reading extra data from JournalStorage");
+        match std::fs::read_to_string(self.extra_path(key)) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[instrument(skip(self, contents))]
+    fn write_extra(&mut self, key: &str, contents: &str) -> Result<(), Self::ErrorType> {
+        trace!("Writing extra data to JournalStorage");
+        Ok(std::fs::write(self.extra_path(key), contents)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        game::{default_sag, AnkiGame, GiveFacts},
+        journal_storage::{JournalEntry, JournalStorage, JournalStorageError},
+        storage::Storage,
+        test_utils::f,
+    };
+    use std::io::Write;
+
+    #[test]
+    fn replay_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.json");
+        let storage = JournalStorage::open(&path).unwrap().with_compact_every(0);
+
+        let mut game: AnkiGame<JournalStorage, GiveFacts> =
+            AnkiGame::new_params(vec![], storage, default_sag());
+        game.add_facts((0..5).map(|i| f(i.to_string(), "def")).collect())
+            .unwrap();
+        game.record_answer(2, true).unwrap();
+        game.record_cram_answer(2, false).unwrap();
+        game.delete_at_index(0).unwrap();
+        game.v[0].fact.definition = "changed".into();
+        game.storage.upsert_item(&game.v, 0).unwrap();
+
+        let reopened = JournalStorage::open(&path).unwrap();
+        assert_eq!(reopened.read_db().unwrap(), game.v);
+        let ops: Vec<_> = reopened
+            .journal()
+            .unwrap()
+            .into_iter()
+            .map(|l| match l.entry {
+                JournalEntry::Add { .. } => "add",
+                JournalEntry::Review { .. } => "review",
+                JournalEntry::Edit { .. } => "edit",
+                JournalEntry::Delete { .. } => "delete",
+            })
+            .collect();
+        assert_eq!(
+            ops,
+            ["add", "add", "add", "add", "add", "review", "review", "delete", "edit"]
+        );
+
+        //a cut-off last line is skipped, but anything else is an error
+        let mut journal = std::fs::OpenOptions::new()
+            .append(true)
+            .open(reopened.journal_path())
+            .unwrap();
+        write!(journal, "{{\"seq\": 100, \"op\": \"Del").unwrap();
+        assert_eq!(reopened.read_db().unwrap(), game.v);
+        writeln!(journal).unwrap();
+        writeln!(journal, "{{}}").unwrap();
+        assert!(matches!(
+            reopened.read_db(),
+            Err(JournalStorageError::CorruptLine { line: 10, .. })
+        ));
+    }
+
+    #[test]
+    fn compaction_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.json");
+        let storage = JournalStorage::open(&path).unwrap().with_compact_every(4);
+
+        let mut game: AnkiGame<JournalStorage, GiveFacts> =
+            AnkiGame::new_params(vec![], storage, default_sag());
+        for i in 0..10 {
+            game.add_fact(f(i.to_string(), "def")).unwrap();
+        }
+        game.record_answer(3, false).unwrap();
+
+        assert_eq!(game.storage.journal().unwrap().len(), 3);
+        let reopened = JournalStorage::open(&path).unwrap();
+        assert_eq!(reopened.read_db().unwrap(), game.v);
+
+        //if compaction is interrupted before the journal is emptied, the old lines are skipped
+        let journal = std::fs::read(game.storage.journal_path()).unwrap();
+        game.storage.compact(&game.v).unwrap();
+        std::fs::write(game.storage.journal_path(), journal).unwrap();
+        assert_eq!(game.storage.read_db().unwrap(), game.v);
+        assert!(game.storage.journal().unwrap().is_empty());
+    }
+
+    #[test]
+    fn restart_after_crash_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.json");
+
+        let mut game: AnkiGame<JournalStorage, GiveFacts> =
+            AnkiGame::new_params(vec![], JournalStorage::open(&path).unwrap(), default_sag());
+        game.add_fact(f("a", "1")).unwrap();
+        let journal_path = game.storage.journal_path();
+        let whole = std::fs::read(&journal_path).unwrap();
+
+        //a crash part way through writing a line
+        let mut journal = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&journal_path)
+            .unwrap();
+        write!(journal, "{{\"seq\": 2, \"op\": \"Ad").unwrap();
+
+        let storage = JournalStorage::open(&path).unwrap();
+        assert_eq!(std::fs::read(&journal_path).unwrap(), whole);
+        let mut game: AnkiGame<JournalStorage, GiveFacts> =
+            AnkiGame::new_params(storage.read_db().unwrap(), storage, default_sag());
+        game.add_fact(f("b", "2")).unwrap();
+        game.add_fact(f("c", "3")).unwrap();
+        assert_eq!(
+            JournalStorage::open(&path).unwrap().read_db().unwrap(),
+            game.v
+        );
+
+        //a crash just before the newline keeps the line
+        std::fs::write(&journal_path, &whole[..whole.len() - 1]).unwrap();
+        let storage = JournalStorage::open(&path).unwrap();
+        assert_eq!(std::fs::read(&journal_path).unwrap(), whole);
+        let mut game: AnkiGame<JournalStorage, GiveFacts> =
+            AnkiGame::new_params(storage.read_db().unwrap(), storage, default_sag());
+        game.add_fact(f("b", "2")).unwrap();
+        let facts: Vec<String> = JournalStorage::open(&path)
+            .unwrap()
+            .read_db()
+            .unwrap()
+            .into_iter()
+            .map(|i| i.fact.term)
+            .collect();
+        assert_eq!(facts, ["a", "b"]);
+    }
+}
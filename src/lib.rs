@@ -11,28 +11,77 @@
     clippy::module_name_repetitions,
     clippy::multiple_crate_versions
 )]
+//lints from newer toolchains that the older code was written before
+#![allow(
+    clippy::assigning_clones,
+    clippy::bool_to_int_with_if,
+    clippy::doc_markdown,
+    clippy::elidable_lifetime_names,
+    clippy::mismatching_type_param_order,
+    clippy::needless_raw_string_hashes,
+    clippy::use_self,
+    mismatched_lifetime_syntaxes
+)]
 //! Welcome to Janki!
 //! Just a really janky version of Anki - the popular spaced repetition learning tool.
 
 #[macro_use]
 extern crate tracing;
 
+///A module to hold [`answer::AnswerType`] and the logic for checking answers
+pub mod answer;
+///A module to hold [`backup::BackupManager`], for rotating backups of the collection
+pub mod backup;
+///A module to hold the [`clock::Clock`] trait, so that timing can be tested
+pub mod clock;
+///A module to hold [`compression::Compression`], for compressing the database on disk
+pub mod compression;
+///A module to hold [`config::JankiConfig`], for settings which are stored with the collection
+pub mod config;
 ///A module to hold a the [`dummy_storage::DummyStorage`] struct
 pub mod dummy_storage;
 ///A module for the [`either::Either`] enum
 pub mod either;
+///A module to hold [`exam::Exam`], for timed tests with a scored report
+pub mod exam;
+///A module to hold [`format::Format`], for the file formats the database can be stored in
+pub mod format;
 ///A module to hold [`game::AnkiGame`]
 pub mod game;
 ///A module to hold [`item::Item`], [`item::ItemGuard`] and [`item::Fact`]
 pub mod item;
+///A module to hold [`matching::MatchingBoard`], for a game of pairing terms with definitions
+pub mod matching;
+///A module to hold the versioned format the database is stored in, and the [`migration::from_versioned_str`] function to upgrade older versions
+pub mod migration;
+///A module to hold [`optimiser::optimise`], for fitting [`game::SeeAgainGaps`] to how the user actually remembers things
+pub mod optimiser;
+///A module to hold [`profile::Profile`], for XP, levels, streaks and achievements
+pub mod profile;
+///A module to hold [`session::Session`], for studying a queue of items
+pub mod session;
+///A module to hold [`simulator::simulate`], for trying out [`game::SeeAgainGaps`] without waiting for months
+pub mod simulator;
+///A module to hold [`sprint::Sprint`], for answering as many items as possible against the clock
+pub mod sprint;
+///A module to hold [`stats::Stats`] and other statistics about how the user is doing
+pub mod stats;
 ///A module to hold the [`storage::Storage`] trait
 pub mod storage;
 
 #[cfg(feature = "eframe_storage")]
 ///A module to implement [`storage::Storage`] for [`eframe::Storage`]
 pub mod eframe_storage;
+#[cfg(feature = "encryption")]
+///A module to hold the [`encrypted_storage::EncryptedStorage`] wrapper
+pub mod encrypted_storage;
 ///A module to hold the [`file_storage::NamedFileStorage`] struct
 pub mod file_storage;
+///A module to hold the [`journal_storage::JournalStorage`] struct
+pub mod journal_storage;
+#[cfg(feature = "sqlite")]
+///A module to hold the [`sqlite_storage::SqliteStorage`] struct
+pub mod sqlite_storage;
 
 ///Module to deal with importing and exporting to and from CSV files
 pub mod csv;
@@ -62,6 +111,7 @@ pub mod test_utils {
         pub struct StringWrapper(String);
 
         impl StringWrapper {
+            #[must_use]
             pub fn to_inner(self) -> String {
                 self.0
             }
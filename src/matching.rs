@@ -0,0 +1,321 @@
+use crate::{
+    game::{AnkiCardReturnType, AnkiGame},
+    item::Fact,
+    session::{index_of, SessionFilter},
+    storage::Storage,
+};
+use chrono::{DateTime, Duration, Utc};
+use rand::{seq::SliceRandom, thread_rng, Rng};
+
+///A matching game - a board of terms and shuffled definitions, which the user has to pair up.
+///
+///The board keeps track of items by their [`Fact`] rather than their index, so if an item is deleted from the game while a board is being played, its result just isn't recorded.
+#[derive(Debug, Clone)]
+pub struct MatchingBoard {
+    ///The fact for each term - in the order the terms are shown
+    terms: Vec<Fact>,
+    ///The index into `terms` of each definition - in the order the definitions are shown
+    definitions: Vec<usize>,
+    ///Whether or not each term has been matched
+    terms_matched: Vec<bool>,
+    ///Whether or not each definition has been matched
+    definitions_matched: Vec<bool>,
+    ///How many wrong matches have been tried for each term
+    mistakes: Vec<usize>,
+    ///When the board was made
+    started: DateTime<Utc>,
+    ///When the last pair was matched, if the board is finished
+    finished: Option<DateTime<Utc>>,
+}
+
+///The result of a finished (or abandoned) [`MatchingBoard`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchingResult {
+    ///The number of pairs on the board
+    pub pairs: usize,
+    ///The number of pairs that were matched
+    pub matched: usize,
+    ///The number of wrong matches that were tried
+    pub mistakes: usize,
+    ///How long the user took
+    pub time_taken: Duration,
+}
+
+impl MatchingBoard {
+    ///Creates a new board with up to `size` random items from an [`AnkiGame`] that match the filter
+    pub fn new<S: Storage, T: AnkiCardReturnType>(
+        game: &AnkiGame<S, T>,
+        size: usize,
+        filter: &SessionFilter,
+    ) -> Self {
+        Self::with_rng(game, size, filter, &mut thread_rng())
+    }
+
+    ///Creates a new board like [`Self::new`], but picking and shuffling the items with a given [`Rng`] - eg. a seeded one, for the same board every time
+    #[instrument(skip(game, rng))]
+    pub fn with_rng<S: Storage, T: AnkiCardReturnType>(
+        game: &AnkiGame<S, T>,
+        size: usize,
+        filter: &SessionFilter,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let mut indices = filter.indices(game, true);
+        indices.shuffle(rng);
+        indices.truncate(size);
+
+        let mut definitions: Vec<usize> = (0..indices.len()).collect();
+        definitions.shuffle(rng);
+
+        Self {
+            terms_matched: vec![false; indices.len()],
+            definitions_matched: vec![false; indices.len()],
+            mistakes: vec![0; indices.len()],
+            terms: indices
+                .into_iter()
+                .map(|index| game.v[index].fact.clone())
+                .collect(),
+            definitions,
+            started: game.now(),
+            finished: None,
+        }
+    }
+
+    ///Gets the terms, in the order they should be shown
+    #[must_use]
+    pub fn terms(&self) -> Vec<&str> {
+        self.terms.iter().map(|f| f.term.as_str()).collect()
+    }
+
+    ///Gets the definitions, in the order they should be shown
+    #[must_use]
+    pub fn definitions(&self) -> Vec<&str> {
+        self.definitions
+            .iter()
+            .map(|t| self.terms[*t].definition.as_str())
+            .collect()
+    }
+
+    ///Whether or not the term at a given position has been matched
+    #[must_use]
+    pub fn is_term_matched(&self, term: usize) -> bool {
+        self.terms_matched.get(term).copied().unwrap_or_default()
+    }
+
+    ///Whether or not the definition at a given position has been matched
+    #[must_use]
+    pub fn is_definition_matched(&self, definition: usize) -> bool {
+        self.definitions_matched
+            .get(definition)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    ///Tries to match the term at one position with the definition at another.
+    ///
+    ///Returns whether or not they matched. Wrong matches count as mistakes against the term, and trying to use a term or definition that is already matched does nothing.
+    #[instrument(skip(self, game))]
+    pub fn try_match<S: Storage, T: AnkiCardReturnType>(
+        &mut self,
+        game: &AnkiGame<S, T>,
+        term: usize,
+        definition: usize,
+    ) -> bool {
+        if term >= self.terms.len()
+            || definition >= self.definitions.len()
+            || self.terms_matched[term]
+            || self.definitions_matched[definition]
+        {
+            return false;
+        }
+
+        //compare the text, in case two terms have the same definition
+        let correct =
+            self.terms[term].definition == self.terms[self.definitions[definition]].definition;
+
+        if correct {
+            self.terms_matched[term] = true;
+            self.definitions_matched[definition] = true;
+            if self.is_finished() {
+                self.finished = Some(game.now());
+            }
+        } else {
+            self.mistakes[term] += 1;
+        }
+
+        correct
+    }
+
+    ///Whether or not every pair has been matched
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.terms_matched.iter().all(|m| *m)
+    }
+
+    ///Gets the number of wrong matches that have been tried
+    #[must_use]
+    pub fn mistakes(&self) -> usize {
+        self.mistakes.iter().sum()
+    }
+
+    ///Gets how long the board has been played for
+    #[must_use]
+    pub fn elapsed<S: Storage, T: AnkiCardReturnType>(&self, game: &AnkiGame<S, T>) -> Duration {
+        self.finished.unwrap_or_else(|| game.now()) - self.started
+    }
+
+    ///Finishes the board and makes a [`MatchingResult`].
+    ///
+    ///If `record_results` is `true`, the results are added to the history of each item that still exists - items which were matched without any mistakes count as correct.
+    #[instrument(skip(self, game))]
+    pub fn finish<S: Storage, T: AnkiCardReturnType>(
+        self,
+        game: &mut AnkiGame<S, T>,
+        record_results: bool,
+    ) -> MatchingResult {
+        if record_results {
+            for ((fact, matched), mistakes) in self
+                .terms
+                .iter()
+                .zip(&self.terms_matched)
+                .zip(&self.mistakes)
+            {
+                let Some(index) = index_of(game, fact) else {
+                    warn!("Matching item no longer exists, so the answer wasn't recorded");
+                    continue;
+                };
+                if let Err(e) = game.record_answer(index, *matched && *mistakes == 0) {
+                    error!("Unable to record answer: {e:?}");
+                }
+            }
+        }
+
+        MatchingResult {
+            pairs: self.terms.len(),
+            matched: self.terms_matched.iter().filter(|m| **m).count(),
+            mistakes: self.mistakes(),
+            time_taken: self.elapsed(game),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        clock::MockClock,
+        dummy_storage::DummyStorage,
+        game::{default_sag, AnkiGame, GiveFacts},
+        matching::MatchingBoard,
+        session::SessionFilter,
+        test_utils::f,
+    };
+    use chrono::Duration;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn matching_test() {
+        let clock = MockClock::default();
+        let mut game = AnkiGame::<_, GiveFacts>::new(DummyStorage::default(), default_sag())
+            .unwrap()
+            .with_clock(clock.clone());
+        game.add_facts(vec![f("a", "1"), f("b", "2"), f("c", "3"), f("d", "3")])
+            .unwrap();
+
+        let mut board = MatchingBoard::with_rng(
+            &game,
+            3,
+            &SessionFilter::default(),
+            &mut StdRng::seed_from_u64(0),
+        );
+        let same = MatchingBoard::with_rng(
+            &game,
+            3,
+            &SessionFilter::default(),
+            &mut StdRng::seed_from_u64(0),
+        );
+        assert_eq!(board.terms(), same.terms());
+        assert_eq!(board.definitions(), same.definitions());
+        let terms: Vec<String> = board.terms().into_iter().map(String::from).collect();
+        let definitions: Vec<String> = board.definitions().into_iter().map(String::from).collect();
+        assert_eq!(terms.len(), 3);
+        assert_eq!(definitions.len(), 3);
+
+        let definition_of = |term: &str| {
+            game.get_all_facts()
+                .into_iter()
+                .find(|f| f.term == term)
+                .unwrap()
+                .definition
+        };
+
+        //make one mistake on the first term - at most two of the facts share a definition, so there is always a wrong one to pick
+        let first_def = definition_of(&terms[0]);
+        let wrong = definitions
+            .iter()
+            .position(|d| d != &first_def)
+            .expect("no wrong definition on the board");
+        assert!(!board.try_match(&game, 0, wrong));
+        assert_eq!(board.mistakes(), 1);
+
+        clock.advance(Duration::seconds(30));
+        assert_eq!(board.elapsed(&game), Duration::seconds(30));
+
+        for (t, term) in terms.iter().enumerate() {
+            let definition = definition_of(term);
+            let position = (0..definitions.len())
+                .find(|d| definitions[*d] == definition && !board.is_definition_matched(*d))
+                .unwrap();
+            assert!(board.try_match(&game, t, position));
+            assert!(board.is_term_matched(t));
+            assert!(!board.try_match(&game, t, position));
+            clock.advance(Duration::seconds(5));
+        }
+        assert!(board.is_finished());
+
+        //the time stops when the last pair is matched, not when the board is finished
+        clock.advance(Duration::seconds(60));
+
+        let result = board.finish(&mut game, true);
+        assert_eq!((result.pairs, result.matched), (3, 3));
+        assert_eq!(result.mistakes, 1);
+        assert_eq!(result.time_taken, Duration::seconds(40));
+
+        let histories: Vec<Vec<bool>> = game.v.iter().map(|i| i.history.clone()).collect();
+        assert_eq!(histories.iter().filter(|h| h.is_empty()).count(), 1);
+        assert_eq!(histories.iter().filter(|h| *h == &vec![false]).count(), 1);
+        assert_eq!(histories.iter().filter(|h| *h == &vec![true]).count(), 2);
+    }
+    #[test]
+    fn deleted_item_test() {
+        let mut game =
+            AnkiGame::<_, GiveFacts>::new(DummyStorage::default(), default_sag()).unwrap();
+        game.add_facts(vec![f("a", "1"), f("b", "2"), f("c", "3")])
+            .unwrap();
+
+        let mut board = MatchingBoard::with_rng(
+            &game,
+            3,
+            &SessionFilter::default(),
+            &mut StdRng::seed_from_u64(0),
+        );
+        let terms: Vec<String> = board.terms().into_iter().map(String::from).collect();
+        let definitions: Vec<String> = board.definitions().into_iter().map(String::from).collect();
+        for (t, term) in terms.iter().enumerate() {
+            let definition = game
+                .get_all_facts()
+                .into_iter()
+                .find(|f| &f.term == term)
+                .unwrap()
+                .definition;
+            let position = definitions.iter().position(|d| d == &definition).unwrap();
+            assert!(board.try_match(&game, t, position));
+        }
+
+        //delete the first item, so every other item moves down an index
+        game.delete_at_index(0).unwrap();
+        let result = board.finish(&mut game, true);
+        assert_eq!((result.pairs, result.matched), (3, 3));
+
+        assert_eq!(game.v.len(), 2);
+        assert!(game.v.iter().all(|i| i.history == vec![true]));
+    }
+}
@@ -0,0 +1,188 @@
+use crate::game::AnkiDB;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+
+///The version of the format that is written - this goes up by one every time [`MIGRATIONS`] gets a new step
+pub const CURRENT_VERSION: u32 = 1;
+
+///A function which upgrades stored items from one version to the next, as JSON
+type Migration = fn(Value) -> Result<Value, MigrationError>;
+
+///Every migration, in order - `MIGRATIONS[0]` upgrades version 0 to version 1 and so on
+const MIGRATIONS: [Migration; CURRENT_VERSION as usize] = [v0_to_v1];
+
+///The database, along with the version of the format it is in - this is what gets stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    ///The version of the format
+    pub version: u32,
+    ///The items
+    pub items: T,
+}
+
+///Error for reading a stored database, using `thiserror`
+#[derive(Error, Debug)]
+pub enum MigrationError {
+    ///Error with `serde_json` - comes from [`serde_json::Error`]
+    #[error("serde_json error: {0}")]
+    SJError(#[from] serde_json::Error),
+
+    ///The database was written by a newer version of janki, so it can't be read without losing data
+    #[error("database is version {0}, but the newest this can read is {CURRENT_VERSION}")]
+    TooNew(u32),
+
+    ///The database isn't a list of items, or a versioned envelope of one
+    #[error("unknown database format: {0}")]
+    UnknownFormat(String),
+}
+
+impl From<MigrationError> for serde_json::Error {
+    fn from(e: MigrationError) -> Self {
+        match e {
+            MigrationError::SJError(e) => e,
+            e => serde::de::Error::custom(e),
+        }
+    }
+}
+
+///Writes the database in the current version of the format
+pub fn to_versioned_string(db: &AnkiDB) -> serde_json::Result<String> {
+    serde_json::to_string(&Envelope {
+        version: CURRENT_VERSION,
+        items: db,
+    })
+}
+
+///Reads a database written in any version of the format, upgrading it to the current version step by step.
+///
+///Version 0 is the original format - just a list of items, with no envelope
+pub fn from_versioned_str(s: &str) -> Result<AnkiDB, MigrationError> {
+    from_versioned_value(serde_json::from_str(s)?)
+}
+
+///Reads a database which has already been parsed, from any version of the format - see [`from_versioned_str`]
+pub fn from_versioned_value(value: Value) -> Result<AnkiDB, MigrationError> {
+    let mut value = match value {
+        Value::Array(items) => json!({ "version": 0, "items": items }),
+        Value::Object(envelope) => Value::Object(envelope),
+        other => {
+            return Err(MigrationError::UnknownFormat(format!(
+                "expected a list or an object, found {other}"
+            )))
+        }
+    };
+
+    let version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| MigrationError::UnknownFormat("missing version".into()))?;
+    let version = u32::try_from(version).map_err(|_| MigrationError::TooNew(u32::MAX))?;
+    if version > CURRENT_VERSION {
+        return Err(MigrationError::TooNew(version));
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        info!(?from, "Migrating database");
+        value = migration(value)?;
+        value["version"] = json!(from + 1);
+    }
+
+    let envelope: Envelope<AnkiDB> = serde_json::from_value(value)?;
+    Ok(envelope.items)
+}
+
+///Gets the list of items from an envelope, to change them in a migration
+fn items_mut(value: &mut Value) -> Result<&mut Vec<Value>, MigrationError> {
+    value
+        .get_mut("items")
+        .and_then(Value::as_array_mut)
+        .ok_or_else(|| MigrationError::UnknownFormat("missing items".into()))
+}
+
+///Gets an item as an object, to change it in a migration
+fn object_mut(value: &mut Value) -> Result<&mut Map<String, Value>, MigrationError> {
+    match value {
+        Value::Object(map) => Ok(map),
+        other => Err(MigrationError::UnknownFormat(format!(
+            "expected an object, found {other}"
+        ))),
+    }
+}
+
+///Version 1 added answer types to facts, and reviews and due dates to items
+fn v0_to_v1(mut value: Value) -> Result<Value, MigrationError> {
+    for item in items_mut(&mut value)? {
+        let item = object_mut(item)?;
+        let fact = object_mut(
+            item.get_mut("fact")
+                .ok_or_else(|| MigrationError::UnknownFormat("item without a fact".into()))?,
+        )?;
+        fact.entry("answer_type").or_insert_with(|| json!("Text"));
+
+        item.entry("reviews").or_insert_with(|| json!([]));
+        item.entry("due").or_insert(Value::Null);
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        answer::AnswerType,
+        migration::{from_versioned_str, to_versioned_string, MigrationError, CURRENT_VERSION},
+    };
+    use chrono::{TimeZone, Utc};
+
+    ///A database from every version, which all have the same items - apart from what older versions couldn't store
+    const FIXTURES: [&str; CURRENT_VERSION as usize + 1] = [
+        include_str!("../fixtures/db_v0.json"),
+        include_str!("../fixtures/db_v1.json"),
+    ];
+
+    #[test]
+    fn fixtures_test() {
+        for (version, fixture) in FIXTURES.iter().enumerate() {
+            let db = from_versioned_str(fixture).unwrap();
+            assert_eq!(db.len(), 2, "version {version}");
+            assert_eq!(db[0].fact.term, "hello");
+            assert_eq!(db[0].fact.answer_type, AnswerType::Text);
+            assert_eq!(db[1].history, vec![true, false, true]);
+            assert_eq!(
+                db[1].last_tested,
+                Some(Utc.with_ymd_and_hms(2022, 7, 1, 12, 0, 0).unwrap())
+            );
+
+            //writing it again gives the current version, which reads the same
+            let written = to_versioned_string(&db).unwrap();
+            assert!(written.starts_with(&format!("{{\"version\":{CURRENT_VERSION},")));
+            assert_eq!(from_versioned_str(&written).unwrap(), db);
+        }
+
+        let latest = from_versioned_str(FIXTURES[CURRENT_VERSION as usize]).unwrap();
+        assert_eq!(latest[1].reviews.len(), 1);
+        assert!(latest[1].due.is_some());
+    }
+
+    #[test]
+    fn bad_version_test() {
+        let newer = format!(r#"{{"version": {}, "items": []}}"#, CURRENT_VERSION + 1);
+        assert!(matches!(
+            from_versioned_str(&newer),
+            Err(MigrationError::TooNew(v)) if v == CURRENT_VERSION + 1
+        ));
+        assert!(matches!(
+            from_versioned_str(r#"{"items": []}"#),
+            Err(MigrationError::UnknownFormat(_))
+        ));
+        assert!(matches!(
+            from_versioned_str("5"),
+            Err(MigrationError::UnknownFormat(_))
+        ));
+        assert!(matches!(
+            from_versioned_str("[{"),
+            Err(MigrationError::SJError(_))
+        ));
+        assert!(from_versioned_str("[]").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+use crate::{
+    game::SeeAgainGaps,
+    item::{streak_of, Item},
+};
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+///The smallest stability that will be fitted, in seconds
+const MIN_STABILITY_SECS: f64 = 1.0;
+///The largest stability that will be fitted, in seconds - about 10 years
+const MAX_STABILITY_SECS: f64 = 10.0 * 365.0 * 86_400.0;
+///How many steps of golden section search to do when fitting - plenty to get within a second
+const FIT_STEPS: usize = 100;
+
+///Options for [`optimise`]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OptimiserOptions {
+    ///The fraction of answers that should be correct, from `0.0` to `1.0`
+    pub target_retention: f64,
+    ///The number of answers needed at a streak before it gets its own fitted gap
+    pub min_observations: usize,
+    ///The highest streak to make a gap for - higher streaks are left out, like in [`crate::game::default_sag`]
+    pub max_streak: u32,
+    ///The shortest allowed gap, in seconds
+    pub min_gap_secs: i64,
+    ///The longest allowed gap, in seconds - if this is less than `min_gap_secs`, every gap is this long
+    pub max_gap_secs: i64,
+    ///How much longer to make each gap than the last one, for streaks without enough answers to fit and which aren't in the current gaps
+    pub growth: f64,
+}
+
+impl Default for OptimiserOptions {
+    fn default() -> Self {
+        Self {
+            target_retention: 0.9,
+            min_observations: 5,
+            max_streak: 10,
+            min_gap_secs: 30,
+            max_gap_secs: 365 * 86_400,
+            growth: 2.0,
+        }
+    }
+}
+
+///The gap for one streak
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FittedLevel {
+    ///How long to wait before testing again, in seconds
+    pub gap_secs: i64,
+    ///The number of answers given at this streak
+    pub observations: usize,
+    ///The fitted memory stability in seconds - the chance of remembering after `t` seconds is `e^(-t / stability)`.
+    ///
+    ///This is [`Option::None`] if there weren't enough answers to fit it
+    pub stability_secs: Option<f64>,
+}
+
+///A set of gaps fitted to the user's answers by [`optimise`] - these can be saved with [`crate::storage::write_extra_json`] using [`OptimisedGaps::KEY`], and turned into [`SeeAgainGaps`] with [`OptimisedGaps::to_sag`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OptimisedGaps {
+    ///The retention the gaps were fitted for
+    pub target_retention: f64,
+    ///The gap for each streak
+    pub levels: BTreeMap<u32, FittedLevel>,
+}
+
+impl OptimisedGaps {
+    ///The key used to store the gaps with [`crate::storage::Storage::write_extra`]
+    pub const KEY: &'static str = "optimised_gaps.json";
+
+    ///Turns the fitted gaps into [`SeeAgainGaps`] to use with an [`crate::game::AnkiGame`]
+    #[must_use]
+    pub fn to_sag(&self) -> SeeAgainGaps {
+        self.levels
+            .iter()
+            .map(|(streak, level)| (*streak, Duration::seconds(level.gap_secs)))
+            .collect()
+    }
+}
+
+///Fits a gap for each streak to the user's answers, so that about `target_retention` of answers will be correct.
+///
+///Each streak is fitted separately using an exponential forgetting curve. Streaks without enough answers keep their gap from `current`, or grow from the last gap if they aren't in there either. Gaps never get shorter as the streak goes up.
+#[must_use]
+#[instrument(skip(items, current))]
+pub fn optimise(
+    items: &[Item],
+    current: &SeeAgainGaps,
+    options: &OptimiserOptions,
+) -> OptimisedGaps {
+    let observations = observations(items);
+    let target = options.target_retention.clamp(0.01, 0.99);
+
+    let mut levels = BTreeMap::new();
+    let mut last_gap = options.min_gap_secs;
+    for streak in 1..=options.max_streak {
+        let obs = observations.get(&streak).map_or(&[][..], Vec::as_slice);
+
+        let stability = (obs.len() >= options.min_observations).then(|| fit_stability(obs));
+        #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+        let gap = stability.map_or_else(
+            || {
+                current.get(&streak).map_or_else(
+                    || (last_gap as f64 * options.growth) as i64,
+                    Duration::num_seconds,
+                )
+            },
+            |stability| (-stability * target.ln()) as i64,
+        );
+        //not `clamp`, which panics if the options have the minimum above the maximum - the maximum wins instead
+        let gap = gap
+            .max(last_gap)
+            .max(options.min_gap_secs)
+            .min(options.max_gap_secs);
+        trace!(?streak, ?gap, ?stability, "Fitted gap");
+
+        levels.insert(
+            streak,
+            FittedLevel {
+                gap_secs: gap,
+                observations: obs.len(),
+                stability_secs: stability,
+            },
+        );
+        last_gap = gap;
+    }
+
+    OptimisedGaps {
+        target_retention: target,
+        levels,
+    }
+}
+
+///Gets every answer that came after another answer, grouped by the streak when it was given, as the seconds since the last answer and whether or not it was correct
+fn observations(items: &[Item]) -> BTreeMap<u32, Vec<(f64, bool)>> {
+    let mut observations: BTreeMap<u32, Vec<(f64, bool)>> = BTreeMap::new();
+
+    for item in items {
+        let reviews: Vec<_> = item.reviews().iter().filter(|r| !r.cram).collect();
+        let history: Vec<bool> = reviews.iter().map(|r| r.correct).collect();
+
+        for (i, pair) in reviews.windows(2).enumerate() {
+            #[allow(clippy::cast_precision_loss)]
+            let elapsed = (pair[1].at - pair[0].at).num_seconds() as f64;
+            observations
+                .entry(streak_of(&history[..=i]))
+                .or_default()
+                .push((elapsed.max(0.0), pair[1].correct));
+        }
+    }
+
+    observations
+}
+
+///Finds the stability which best explains a set of answers, using a golden section search for the maximum likelihood
+fn fit_stability(observations: &[(f64, bool)]) -> f64 {
+    let log_likelihood = |ln_stability: f64| -> f64 {
+        let stability = ln_stability.exp();
+        observations
+            .iter()
+            .map(|(elapsed, correct)| {
+                let exponent = -elapsed / stability;
+                if *correct {
+                    exponent
+                } else {
+                    (-exponent.exp_m1()).max(f64::MIN_POSITIVE).ln()
+                }
+            })
+            .sum()
+    };
+
+    let ratio = (5.0_f64.sqrt() - 1.0) / 2.0;
+    let (mut low, mut high) = (MIN_STABILITY_SECS.ln(), MAX_STABILITY_SECS.ln());
+    for _ in 0..FIT_STEPS {
+        let a = high - ratio * (high - low);
+        let b = low + ratio * (high - low);
+        if log_likelihood(a) < log_likelihood(b) {
+            low = a;
+        } else {
+            high = b;
+        }
+    }
+
+    f64::midpoint(low, high).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        game::default_sag,
+        item::{Item, Review},
+        optimiser::{fit_stability, optimise, OptimisedGaps, OptimiserOptions},
+        test_utils::f,
+    };
+    use chrono::{Duration, TimeZone, Utc};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn fit_test() {
+        let stability = 3600.0;
+        let mut rng = StdRng::seed_from_u64(0);
+        let observations: Vec<(f64, bool)> = (0..5000)
+            .map(|i| {
+                let elapsed = f64::from(i % 100) * 100.0;
+                (elapsed, rng.gen_bool((-elapsed / stability).exp()))
+            })
+            .collect();
+
+        let fitted = fit_stability(&observations);
+        assert!((fitted - stability).abs() / stability < 0.1, "{fitted}");
+
+        assert!(fit_stability(&[(60.0, true); 10]) > 1e8);
+        assert!(fit_stability(&[(60.0, false); 10]) < 10.0);
+    }
+
+    #[test]
+    fn optimise_test() {
+        let start = Utc.with_ymd_and_hms(2022, 7, 1, 12, 0, 0).unwrap();
+        //each item is answered right, then wrong an hour later - so streak 1 is too long at an hour
+        let items: Vec<Item> = (0..10)
+            .map(|i| {
+                let mut item = Item::new(f(i.to_string(), "def"));
+                for (at, correct) in [(start, true), (start + Duration::hours(1), false)] {
+                    item.reviews.push(Review {
+                        at,
+                        correct,
+                        cram: false,
+                    });
+                }
+                item
+            })
+            .collect();
+
+        let options = OptimiserOptions::default();
+        let gaps = optimise(&items, &default_sag(), &options);
+        let level = gaps.levels[&1];
+        assert_eq!(level.observations, 10);
+        assert!(level.stability_secs.is_some());
+        assert_eq!(level.gap_secs, options.min_gap_secs);
+        //streaks without data keep the current gaps
+        assert_eq!(gaps.levels[&2].stability_secs, None);
+        assert_eq!(gaps.levels[&2].gap_secs, 60);
+        assert_eq!(gaps.levels.len(), 10);
+
+        let sag = gaps.to_sag();
+        assert_eq!(sag[&3], Duration::seconds(90));
+        let json = serde_json::to_string(&gaps).unwrap();
+        assert_eq!(serde_json::from_str::<OptimisedGaps>(&json).unwrap(), gaps);
+    }
+
+    #[test]
+    fn backwards_limits_test() {
+        let options = OptimiserOptions {
+            min_gap_secs: 1000,
+            max_gap_secs: 10,
+            ..OptimiserOptions::default()
+        };
+        let gaps = optimise(&[], &default_sag(), &options);
+        assert!(gaps.levels.values().all(|l| l.gap_secs == 10));
+    }
+}
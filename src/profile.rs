@@ -0,0 +1,299 @@
+use crate::{game::AnkiDB, item::Item};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+///The XP given for a correct answer to the easiest possible item - harder items give up to three times this
+pub const BASE_XP: u64 = 10;
+///The XP given for an incorrect answer - for effort
+pub const INCORRECT_XP: u64 = 2;
+///The XP needed to go from level 1 to level 2 - each level after that needs this much more than the last
+pub const LEVEL_XP_STEP: u64 = 100;
+///The number of correct answers in a row needed for an item to count as mastered
+pub const MASTERED_STREAK: u32 = 5;
+
+///Something the user can unlock by studying
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Achievement {
+    ///Answer a question
+    FirstReview,
+    ///Answer 100 questions
+    HundredReviews,
+    ///Answer 1000 questions
+    ThousandReviews,
+    ///Study for 7 days in a row
+    WeekStreak,
+    ///Study for 30 days in a row
+    MonthStreak,
+    ///Master 10 items
+    TenMastered,
+    ///Master 100 items
+    HundredMastered,
+    ///Reach level 5
+    LevelFive,
+    ///Reach level 10
+    LevelTen,
+}
+
+impl Achievement {
+    ///Every achievement, in the order they should be shown
+    pub const ALL: [Self; 9] = [
+        Self::FirstReview,
+        Self::HundredReviews,
+        Self::ThousandReviews,
+        Self::WeekStreak,
+        Self::MonthStreak,
+        Self::TenMastered,
+        Self::HundredMastered,
+        Self::LevelFive,
+        Self::LevelTen,
+    ];
+
+    ///Gets the name of the achievement
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::FirstReview => "First Steps",
+            Self::HundredReviews => "Centurion",
+            Self::ThousandReviews => "Dedicated",
+            Self::WeekStreak => "Week Warrior",
+            Self::MonthStreak => "Creature of Habit",
+            Self::TenMastered => "Getting There",
+            Self::HundredMastered => "Walking Encyclopedia",
+            Self::LevelFive => "Apprentice",
+            Self::LevelTen => "Scholar",
+        }
+    }
+
+    ///Gets a description of how to unlock the achievement
+    #[must_use]
+    pub const fn description(self) -> &'static str {
+        match self {
+            Self::FirstReview => "Answer your first question",
+            Self::HundredReviews => "Answer 100 questions",
+            Self::ThousandReviews => "Answer 1000 questions",
+            Self::WeekStreak => "Study for 7 days in a row",
+            Self::MonthStreak => "Study for 30 days in a row",
+            Self::TenMastered => "Master 10 items",
+            Self::HundredMastered => "Master 100 items",
+            Self::LevelFive => "Reach level 5",
+            Self::LevelTen => "Reach level 10",
+        }
+    }
+
+    ///Whether or not the user has earned the achievement, given their profile and items
+    fn is_earned(self, profile: &Profile, db: &[Item]) -> bool {
+        let mastered = || {
+            db.iter()
+                .filter(|i| i.true_streak() >= MASTERED_STREAK)
+                .count()
+        };
+
+        match self {
+            Self::FirstReview => profile.reviews >= 1,
+            Self::HundredReviews => profile.reviews >= 100,
+            Self::ThousandReviews => profile.reviews >= 1000,
+            Self::WeekStreak => profile.longest_streak >= 7,
+            Self::MonthStreak => profile.longest_streak >= 30,
+            Self::TenMastered => mastered() >= 10,
+            Self::HundredMastered => mastered() >= 100,
+            Self::LevelFive => profile.level() >= 5,
+            Self::LevelTen => profile.level() >= 10,
+        }
+    }
+}
+
+///The user's profile - XP, levels, daily study streaks and achievements.
+///
+///This is kept up to date by the [`crate::game::AnkiGame`], and stored with [`crate::storage::Storage::write_extra`] using [`Profile::KEY`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    ///The total XP earned
+    pub xp: u64,
+    ///The total number of answers given
+    pub reviews: u64,
+    ///The total number of correct answers given
+    pub correct: u64,
+    ///The number of days in a row, up to the last day studied, with at least one answer
+    streak: u32,
+    ///The longest number of days in a row with at least one answer
+    pub longest_streak: u32,
+    ///The last day (in UTC) with at least one answer
+    pub last_study_day: Option<NaiveDate>,
+    ///Every achievement that has been unlocked, and when
+    pub achievements: BTreeMap<Achievement, DateTime<Utc>>,
+}
+
+impl Profile {
+    ///The key used to store the profile with [`crate::storage::Storage::write_extra`]
+    pub const KEY: &'static str = "profile.json";
+
+    ///Records an answer for an item, before the answer is added to the item's history.
+    ///
+    ///Returns the XP given - correct answers to items which are often answered incorrectly give more XP
+    pub fn record_review(&mut self, item: &Item, correct: bool, now: DateTime<Utc>) -> u64 {
+        let xp = if correct {
+            #[allow(
+                clippy::cast_possible_truncation,
+                clippy::cast_sign_loss,
+                clippy::cast_precision_loss
+            )]
+            let bonus = (BASE_XP as f64 * 2.0 * item.difficulty()).round() as u64;
+            BASE_XP + bonus
+        } else {
+            INCORRECT_XP
+        };
+
+        self.xp += xp;
+        self.reviews += 1;
+        if correct {
+            self.correct += 1;
+        }
+
+        let today = now.date_naive();
+        match self.last_study_day {
+            Some(last) if last == today => {}
+            Some(last) if last + Duration::days(1) == today => self.streak += 1,
+            _ => self.streak = 1,
+        }
+        self.last_study_day = Some(today);
+        self.longest_streak = self.longest_streak.max(self.streak);
+
+        xp
+    }
+
+    ///Unlocks any achievements which have been earned, and returns the ones which were newly unlocked
+    pub fn unlock_achievements(&mut self, db: &AnkiDB, now: DateTime<Utc>) -> Vec<Achievement> {
+        let new: Vec<Achievement> = Achievement::ALL
+            .into_iter()
+            .filter(|a| !self.achievements.contains_key(a) && a.is_earned(self, db))
+            .collect();
+
+        for a in &new {
+            info!("Unlocked achievement {a:?}");
+            self.achievements.insert(*a, now);
+        }
+        new
+    }
+
+    ///Gets the current daily streak - this is 0 if the user didn't study today or yesterday
+    #[must_use]
+    pub fn current_streak(&self, now: DateTime<Utc>) -> u32 {
+        let today = now.date_naive();
+        match self.last_study_day {
+            Some(last) if last == today || last + Duration::days(1) == today => self.streak,
+            _ => 0,
+        }
+    }
+
+    ///Gets the current level, starting from 1
+    #[must_use]
+    pub const fn level(&self) -> u64 {
+        let mut level = 1;
+        while Self::xp_for_level(level + 1) <= self.xp {
+            level += 1;
+        }
+        level
+    }
+
+    ///Gets the total XP needed to reach a level - 0 for level 1, then [`LEVEL_XP_STEP`] more than the previous level's gap each time
+    #[must_use]
+    pub const fn xp_for_level(level: u64) -> u64 {
+        let n = level.saturating_sub(1);
+        LEVEL_XP_STEP * n * (n + 1) / 2
+    }
+
+    ///Gets how far through the current level the user is, from `0.0` to `1.0`
+    #[must_use]
+    pub fn level_progress(&self) -> f64 {
+        let level = self.level();
+        let start = Self::xp_for_level(level);
+        let end = Self::xp_for_level(level + 1);
+
+        #[allow(clippy::cast_precision_loss)]
+        let progress = (self.xp - start) as f64 / (end - start) as f64;
+        progress
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        item::Item,
+        profile::{Achievement, Profile, BASE_XP, INCORRECT_XP, LEVEL_XP_STEP},
+        test_utils::f,
+    };
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn xp_and_levels_test() {
+        let now = Utc.with_ymd_and_hms(2022, 7, 1, 12, 0, 0).unwrap();
+        let mut profile = Profile::default();
+        assert_eq!(profile.level(), 1);
+
+        let new_item = Item::new(f("a", "b"));
+        assert_eq!(profile.record_review(&new_item, true, now), BASE_XP * 2);
+        assert_eq!(profile.record_review(&new_item, false, now), INCORRECT_XP);
+
+        let mut easy_item = Item::new(f("a", "b"));
+        easy_item.history = vec![true; 98];
+        let mut hard_item = Item::new(f("a", "b"));
+        hard_item.history = vec![false; 98];
+        assert_eq!(profile.record_review(&easy_item, true, now), BASE_XP);
+        assert_eq!(profile.record_review(&hard_item, true, now), BASE_XP * 3);
+
+        assert_eq!(profile.xp, 62);
+        assert_eq!((profile.reviews, profile.correct), (4, 3));
+
+        assert_eq!(Profile::xp_for_level(1), 0);
+        assert_eq!(Profile::xp_for_level(2), LEVEL_XP_STEP);
+        assert_eq!(Profile::xp_for_level(3), LEVEL_XP_STEP * 3);
+        profile.xp = LEVEL_XP_STEP * 3;
+        assert_eq!(profile.level(), 3);
+        profile.xp += LEVEL_XP_STEP;
+        assert!((profile.level_progress() - 1.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn streak_test() {
+        let day = |d| Utc.with_ymd_and_hms(2022, 7, d, 12, 0, 0).unwrap();
+        let item = Item::new(f("a", "b"));
+        let mut profile = Profile::default();
+        assert_eq!(profile.current_streak(day(1)), 0);
+
+        for d in 1..=7 {
+            profile.record_review(&item, true, day(d));
+            profile.record_review(&item, true, day(d) + Duration::hours(1));
+        }
+        assert_eq!(profile.current_streak(day(7)), 7);
+        assert_eq!(profile.current_streak(day(8)), 7);
+        assert_eq!(profile.current_streak(day(9)), 0);
+
+        profile.record_review(&item, true, day(10));
+        assert_eq!(profile.current_streak(day(10)), 1);
+        assert_eq!(profile.longest_streak, 7);
+
+        let new = profile.unlock_achievements(&vec![item], day(10));
+        assert_eq!(new, vec![Achievement::FirstReview, Achievement::WeekStreak]);
+        assert!(profile.unlock_achievements(&vec![], day(10)).is_empty());
+        assert_eq!(
+            profile.achievements.get(&Achievement::WeekStreak),
+            Some(&day(10))
+        );
+    }
+
+    #[test]
+    fn mastered_test() {
+        let mut mastered = Item::new(f("a", "b"));
+        mastered.history = vec![true; 5];
+        let mut profile = Profile::default();
+
+        assert!(profile
+            .unlock_achievements(&vec![mastered.clone(); 9], Utc::now())
+            .is_empty());
+        assert_eq!(
+            profile.unlock_achievements(&vec![mastered; 10], Utc::now()),
+            vec![Achievement::TenMastered]
+        );
+    }
+}
@@ -0,0 +1,564 @@
+use crate::{
+    answer::AnswerError,
+    game::{AnkiCardReturnType, AnkiGame},
+    item::Fact,
+    storage::Storage,
+};
+use chrono::{DateTime, Duration, Utc};
+use rand::{seq::SliceRandom, thread_rng};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+///The order that items are put into a [`Session`]'s queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SessionOrder {
+    ///Items are shuffled randomly
+    #[default]
+    Random,
+    ///Items that have never been tested come first, then the items that were tested the longest time ago
+    LeastRecent,
+    ///Items are in the order that they were added
+    Added,
+}
+
+///Whether or not the answers in a [`Session`] affect the scheduling
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SessionMode {
+    ///Normal spaced repetition - answers are added to each item's history, and change when it is next eligible
+    #[default]
+    Review,
+    ///Cramming/previewing - items are drawn regardless of when they are due, and answers are logged with a cram flag without changing the scheduling
+    Cram,
+}
+
+///Which items get put into a [`Session`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionFilter {
+    ///Only include items which are eligible according to the [`crate::game::SeeAgainGaps`] - this is ignored in [`SessionMode::Cram`]
+    pub only_eligible: bool,
+    ///Only include items where the term or definition contains this text, ignoring case
+    pub contains: Option<String>,
+}
+
+impl SessionFilter {
+    ///Gets the indices of all of the items in an [`AnkiGame`] which match the filter, in the order they were added.
+    ///
+    ///If `check_eligible` is `false`, then [`Self::only_eligible`] is ignored
+    pub(crate) fn indices<S: Storage, T: AnkiCardReturnType>(
+        &self,
+        game: &AnkiGame<S, T>,
+        check_eligible: bool,
+    ) -> Vec<usize> {
+        let mut indices: Vec<usize> = if self.only_eligible && check_eligible {
+            game.eligible_indices()
+        } else {
+            (0..game.v.len()).collect()
+        };
+
+        if let Some(contains) = &self.contains {
+            let contains = contains.to_lowercase();
+            indices.retain(|index| {
+                let fact = &game.v[*index].fact;
+                fact.term.to_lowercase().contains(&contains)
+                    || fact.definition.to_lowercase().contains(&contains)
+            });
+        }
+
+        indices
+    }
+
+    ///Gets a name for the items that match the filter, like `eligible facts containing "verb"` - used to keep separate [`crate::sprint::HighScores`] for each filter
+    #[must_use]
+    pub fn deck_name(&self) -> String {
+        let facts = if self.only_eligible {
+            "eligible facts"
+        } else {
+            "all facts"
+        };
+
+        self.contains.as_ref().map_or_else(
+            || facts.to_string(),
+            |contains| format!("{facts} containing {:?}", contains.to_lowercase()),
+        )
+    }
+}
+
+///Options for creating a [`Session`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionOptions {
+    ///The most items to put in the queue - [`None`] for no limit
+    pub limit: Option<usize>,
+    ///Which items to put in the queue
+    pub filter: SessionFilter,
+    ///The order of the queue
+    pub order: SessionOrder,
+    ///How many items later a failed item is asked again
+    pub requeue_gap: usize,
+    ///Whether or not answers affect the scheduling
+    pub mode: SessionMode,
+}
+
+impl SessionOptions {
+    ///Options for a [`SessionMode::Cram`] session over all items, with no limit
+    #[must_use]
+    pub fn cram() -> Self {
+        Self {
+            limit: None,
+            mode: SessionMode::Cram,
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        Self {
+            limit: Some(20),
+            filter: SessionFilter {
+                only_eligible: true,
+                contains: None,
+            },
+            order: SessionOrder::default(),
+            requeue_gap: 3,
+            mode: SessionMode::default(),
+        }
+    }
+}
+
+///One answer given during a [`Session`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionAnswer {
+    ///The fact that was asked
+    pub fact: Fact,
+    ///Whether or not the user was correct
+    pub correct: bool,
+    ///How long the user took to answer, since the previous answer or the start of the session
+    pub time_taken: Duration,
+}
+
+///How far through a [`Session`] the user is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionProgress {
+    ///The number of answers given so far, including repeats of failed items
+    pub done: usize,
+    ///The number of items left in the queue, including failed items waiting to be asked again
+    pub remaining: usize,
+    ///The number of correct answers so far
+    pub correct: usize,
+    ///The number of incorrect answers so far
+    pub incorrect: usize,
+    ///How long the session has been going
+    pub elapsed: Duration,
+}
+
+///A summary of a finished (or abandoned) [`Session`], for displaying to the user
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionSummary {
+    ///The number of different items in the session
+    pub items: usize,
+    ///The number of answers given, including repeats of failed items
+    pub reviews: usize,
+    ///The number of correct answers
+    pub correct: usize,
+    ///The number of incorrect answers
+    pub incorrect: usize,
+    ///The fraction of answers that were correct, from `0.0` to `1.0`
+    pub accuracy: f64,
+    ///How long the session took
+    pub duration: Duration,
+    ///The facts that were answered incorrectly at least once, with how many times - most missed first
+    pub missed: Vec<(Fact, usize)>,
+    ///Every answer given, in order
+    pub answers: Vec<SessionAnswer>,
+}
+
+///A study session - an ordered queue of items from an [`AnkiGame`], where failed items are asked again later in the same session.
+///
+///The session keeps track of items by their [`Fact`] rather than their index, so if an item is deleted from the game while a session is running, it just gets skipped.
+///
+///Only the first answer to each item is recorded in the game - failed items that get asked again are just for practice.
+#[derive(Debug, Clone)]
+pub struct Session {
+    ///The facts left to ask, with the current one at the front
+    queue: VecDeque<Fact>,
+    ///The number of different items that the session started with
+    items: usize,
+    ///How many items later a failed item is asked again
+    requeue_gap: usize,
+    ///Whether or not answers affect the scheduling
+    mode: SessionMode,
+    ///Every answer given so far
+    answers: Vec<SessionAnswer>,
+    ///When the session started
+    started: DateTime<Utc>,
+    ///When the last answer was given, or the session started
+    last_answer: DateTime<Utc>,
+    ///When the last item was answered, if the queue is empty
+    finished: Option<DateTime<Utc>>,
+}
+
+impl Session {
+    ///Creates a new session, building the queue from the items in an [`AnkiGame`] using the [`SessionOptions`]
+    #[instrument(skip(game))]
+    pub fn new<S: Storage, T: AnkiCardReturnType>(
+        game: &AnkiGame<S, T>,
+        options: &SessionOptions,
+    ) -> Self {
+        let mut indices = options
+            .filter
+            .indices(game, options.mode == SessionMode::Review);
+
+        match options.order {
+            SessionOrder::Random => indices.shuffle(&mut thread_rng()),
+            SessionOrder::LeastRecent => indices.sort_by_key(|index| game.v[*index].last_tested),
+            SessionOrder::Added => {}
+        }
+
+        if let Some(limit) = options.limit {
+            indices.truncate(limit);
+        }
+        info!("Starting session with {} items", indices.len());
+
+        let now = game.now();
+        Self {
+            items: indices.len(),
+            queue: indices
+                .into_iter()
+                .map(|index| game.v[index].fact.clone())
+                .collect(),
+            requeue_gap: options.requeue_gap,
+            mode: options.mode,
+            answers: vec![],
+            started: now,
+            last_answer: now,
+            finished: None,
+        }
+    }
+
+    ///Gets the fact that is currently being asked, or [`None`] if the session is finished.
+    ///
+    ///Facts that have been deleted from the game are skipped
+    #[must_use]
+    pub fn current_fact<S: Storage, T: AnkiCardReturnType>(
+        &self,
+        game: &AnkiGame<S, T>,
+    ) -> Option<Fact> {
+        self.queue
+            .iter()
+            .find(|fact| index_of(game, fact).is_some())
+            .cloned()
+    }
+
+    ///Checks an answer for the current fact using [`Fact::check`], and records it using [`Self::mark`].
+    ///
+    ///Returns whether or not the answer was correct, or an [`AnswerError`] without recording anything if the answer couldn't be checked.
+    ///If the session is already finished, this returns `Ok(false)`
+    pub fn answer<S: Storage, T: AnkiCardReturnType>(
+        &mut self,
+        game: &mut AnkiGame<S, T>,
+        answer: &str,
+    ) -> Result<bool, AnswerError> {
+        let Some(fact) = self.current_fact(game) else {
+            return Ok(false);
+        };
+
+        let correct = fact.check(answer)?;
+        self.mark(game, correct);
+        Ok(correct)
+    }
+
+    ///Records whether or not the user was correct for the current fact, and moves on to the next one.
+    ///
+    ///Failed items are put back into the queue to be asked again later, but only the first answer for each item is recorded in the game.
+    #[instrument(skip(self, game))]
+    pub fn mark<S: Storage, T: AnkiCardReturnType>(
+        &mut self,
+        game: &mut AnkiGame<S, T>,
+        correct: bool,
+    ) {
+        let (fact, index) = loop {
+            let Some(fact) = self.queue.pop_front() else {
+                warn!("Marking an answer for a finished session");
+                return;
+            };
+            if let Some(index) = index_of(game, &fact) {
+                break (fact, index);
+            }
+            warn!("Session item no longer exists, skipping");
+        };
+
+        if self.answers.iter().any(|a| a.fact == fact) {
+            trace!("Already recorded an answer for this item this session");
+        } else {
+            let written = match self.mode {
+                SessionMode::Review => game.record_answer(index, correct),
+                SessionMode::Cram => game.record_cram_answer(index, correct),
+            };
+            //like with backups, this shouldn't stop the session
+            if let Err(e) = written {
+                error!("Unable to record answer: {e:?}");
+            }
+        }
+
+        if !correct {
+            self.queue
+                .insert(self.requeue_gap.min(self.queue.len()), fact.clone());
+        }
+
+        let now = game.now();
+        self.answers.push(SessionAnswer {
+            fact,
+            correct,
+            time_taken: now - self.last_answer,
+        });
+        self.last_answer = now;
+
+        if self.is_finished(game) {
+            self.finished = Some(now);
+        }
+    }
+
+    ///Gets the [`SessionMode`] of the session
+    #[must_use]
+    pub const fn mode(&self) -> SessionMode {
+        self.mode
+    }
+
+    ///Whether or not there are no items left in the queue that still exist in the game
+    #[must_use]
+    pub fn is_finished<S: Storage, T: AnkiCardReturnType>(&self, game: &AnkiGame<S, T>) -> bool {
+        self.current_fact(game).is_none()
+    }
+
+    ///Gets how far through the session the user is, using the game's [`crate::clock::Clock`]
+    #[must_use]
+    pub fn progress<S: Storage, T: AnkiCardReturnType>(
+        &self,
+        game: &AnkiGame<S, T>,
+    ) -> SessionProgress {
+        let correct = self.answers.iter().filter(|a| a.correct).count();
+
+        SessionProgress {
+            done: self.answers.len(),
+            remaining: self.queue.len(),
+            correct,
+            incorrect: self.answers.len() - correct,
+            elapsed: self.finished.unwrap_or_else(|| game.now()) - self.started,
+        }
+    }
+
+    ///Gets a summary of the session - this can be called before the session is finished, for example if the user stops early
+    #[must_use]
+    pub fn summary<S: Storage, T: AnkiCardReturnType>(
+        &self,
+        game: &AnkiGame<S, T>,
+    ) -> SessionSummary {
+        let progress = self.progress(game);
+
+        let mut missed: Vec<(Fact, usize)> = vec![];
+        for answer in self.answers.iter().filter(|a| !a.correct) {
+            match missed.iter_mut().find(|(f, _)| f == &answer.fact) {
+                Some((_, count)) => *count += 1,
+                None => missed.push((answer.fact.clone(), 1)),
+            }
+        }
+        missed.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        #[allow(clippy::cast_precision_loss)]
+        let accuracy = if progress.done == 0 {
+            0.0
+        } else {
+            progress.correct as f64 / progress.done as f64
+        };
+
+        SessionSummary {
+            items: self.items,
+            reviews: progress.done,
+            correct: progress.correct,
+            incorrect: progress.incorrect,
+            accuracy,
+            duration: progress.elapsed,
+            missed,
+            answers: self.answers.clone(),
+        }
+    }
+}
+
+///Finds the index of a fact in an [`AnkiGame`], if it still exists - used to keep track of items by their [`Fact`], as indices change when items are deleted
+pub(crate) fn index_of<S: Storage, T: AnkiCardReturnType>(
+    game: &AnkiGame<S, T>,
+    fact: &Fact,
+) -> Option<usize> {
+    game.v.iter().position(|item| &item.fact == fact)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        clock::MockClock,
+        dummy_storage::DummyStorage,
+        game::{default_sag, AnkiGame, GiveFacts},
+        session::{Session, SessionFilter, SessionMode, SessionOptions, SessionOrder},
+        test_utils::f,
+    };
+    use chrono::{Duration, Utc};
+
+    fn game() -> AnkiGame<DummyStorage, GiveFacts> {
+        let mut game = AnkiGame::new(DummyStorage::default(), default_sag()).unwrap();
+        game.add_facts(vec![f("a", "1"), f("b", "2"), f("c", "3"), f("ab", "4")])
+            .unwrap();
+        game
+    }
+
+    #[test]
+    fn queue_test() {
+        let game = game();
+
+        let session = Session::new(
+            &game,
+            &SessionOptions {
+                limit: Some(2),
+                order: SessionOrder::Added,
+                ..SessionOptions::default()
+            },
+        );
+        assert_eq!(session.progress(&game).remaining, 2);
+        assert_eq!(session.current_fact(&game), Some(f("a", "1")));
+
+        let filtered = Session::new(
+            &game,
+            &SessionOptions {
+                limit: None,
+                filter: SessionFilter {
+                    only_eligible: false,
+                    contains: Some("B".into()),
+                },
+                order: SessionOrder::Added,
+                requeue_gap: 1,
+                mode: SessionMode::Review,
+            },
+        );
+        assert_eq!(filtered.progress(&game).remaining, 2);
+        assert_eq!(filtered.current_fact(&game), Some(f("b", "2")));
+    }
+
+    #[test]
+    fn requeue_test() {
+        let mut game = game();
+        let mut session = Session::new(
+            &game,
+            &SessionOptions {
+                limit: Some(3),
+                order: SessionOrder::Added,
+                requeue_gap: 1,
+                ..SessionOptions::default()
+            },
+        );
+
+        assert_eq!(session.answer(&mut game, "wrong"), Ok(false));
+        assert_eq!(session.current_fact(&game), Some(f("b", "2")));
+        assert_eq!(session.answer(&mut game, "2"), Ok(true));
+        assert_eq!(session.current_fact(&game), Some(f("a", "1")));
+        assert_eq!(session.answer(&mut game, "1"), Ok(true));
+        assert_eq!(session.answer(&mut game, "3"), Ok(true));
+        assert!(session.is_finished(&game));
+        assert_eq!(session.current_fact(&game), None);
+
+        let progress = session.progress(&game);
+        assert_eq!((progress.done, progress.remaining), (4, 0));
+        assert_eq!((progress.correct, progress.incorrect), (3, 1));
+
+        let summary = session.summary(&game);
+        assert_eq!(summary.items, 3);
+        assert_eq!(summary.missed, vec![(f("a", "1"), 1)]);
+        assert!((summary.accuracy - 0.75).abs() < f64::EPSILON);
+
+        //only the first answer for each item is recorded
+        assert_eq!(game.v[0].history, vec![false]);
+        assert_eq!(game.v[0].reviews().len(), 1);
+        assert_eq!(game.v[1].history, vec![true]);
+        assert!(game.v[3].history.is_empty());
+    }
+
+    #[test]
+    fn cram_test() {
+        let mut game = game();
+        game.record_answer(0, true).unwrap();
+        let last_tested = game.v[0].last_tested;
+
+        let review = Session::new(&game, &SessionOptions::default());
+        assert_eq!(review.progress(&game).remaining, 3);
+
+        let mut cram = Session::new(
+            &game,
+            &SessionOptions {
+                order: SessionOrder::Added,
+                ..SessionOptions::cram()
+            },
+        );
+        assert_eq!(cram.mode(), SessionMode::Cram);
+        assert_eq!(cram.progress(&game).remaining, 4);
+
+        assert_eq!(cram.answer(&mut game, "wrong"), Ok(false));
+        assert_eq!(cram.answer(&mut game, "2"), Ok(true));
+
+        assert_eq!(game.v[0].history, vec![true]);
+        assert_eq!(game.v[0].last_tested, last_tested);
+        assert!(game.v[1].history.is_empty());
+        assert_eq!(game.v[1].last_tested, None);
+
+        let reviews = game.v[0].reviews();
+        assert_eq!(reviews.len(), 2);
+        assert!(!reviews[0].cram && reviews[0].correct);
+        assert!(reviews[1].cram && !reviews[1].correct);
+        assert!(game.v[1].reviews()[0].cram);
+    }
+
+    #[test]
+    fn deleted_item_test() {
+        let mut game = game();
+        let mut session = Session::new(
+            &game,
+            &SessionOptions {
+                limit: None,
+                order: SessionOrder::Added,
+                ..SessionOptions::cram()
+            },
+        );
+
+        game.delete_at_index(1).unwrap();
+        assert_eq!(session.answer(&mut game, "1"), Ok(true));
+        //"b" was deleted, so "c" is next - even though it has moved to index 1
+        assert_eq!(session.current_fact(&game), Some(f("c", "3")));
+        assert_eq!(session.answer(&mut game, "3"), Ok(true));
+        assert_eq!(game.v[1].reviews().len(), 1);
+        assert!(game.v[2].reviews().is_empty());
+
+        game.delete_at_index(2).unwrap();
+        assert!(session.is_finished(&game));
+    }
+
+    #[test]
+    fn clock_test() {
+        let start = Utc::now();
+        let clock = MockClock::new(start);
+        let mut game = game().with_clock(clock.clone());
+
+        let mut session = Session::new(
+            &game,
+            &SessionOptions {
+                order: SessionOrder::Added,
+                ..SessionOptions::cram()
+            },
+        );
+        clock.advance(Duration::seconds(30));
+        assert_eq!(session.progress(&game).elapsed, Duration::seconds(30));
+
+        assert_eq!(session.answer(&mut game, "1"), Ok(true));
+        assert_eq!(
+            session.summary(&game).answers[0].time_taken,
+            Duration::seconds(30)
+        );
+        assert_eq!(game.v[0].reviews()[0].at, start + Duration::seconds(30));
+    }
+}
@@ -0,0 +1,378 @@
+use crate::{
+    clock::MockClock,
+    dummy_storage::DummyStorage,
+    game::{AnkiGame, Extrapolation, GiveFacts, SeeAgainGaps},
+    item::{Fact, Item},
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+///How likely the simulated user is to remember an item
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RecallModel {
+    ///Always remembers with the same probability, from `0.0` to `1.0`
+    Constant(f64),
+    ///An exponential forgetting curve - the chance of remembering is `e^(-days since last test / stability)`.
+    ///
+    ///The stability starts at `initial_stability_days`, and is multiplied by `growth` for each correct answer in a row. Items which have never been seen are never remembered.
+    Forgetting {
+        ///The stability, in days, for an item which was answered wrong last time
+        initial_stability_days: f64,
+        ///How much the stability grows with each correct answer in a row
+        growth: f64,
+    },
+    ///Recall rates measured from real reviews, from [`RecallModel::recorded`] - `rates[n]` is the chance of remembering an item with a streak of `n`, and the last rate is used for longer streaks
+    Recorded(Vec<f64>),
+}
+
+impl Default for RecallModel {
+    fn default() -> Self {
+        Self::Forgetting {
+            initial_stability_days: 1.0,
+            growth: 2.5,
+        }
+    }
+}
+
+impl RecallModel {
+    ///Measures the recall rate for each streak from the `history` of real items.
+    ///
+    ///If there's no history at all, every item has an even chance of being remembered
+    #[must_use]
+    pub fn recorded(items: &[Item]) -> Self {
+        let mut counts: Vec<(usize, usize)> = vec![];
+        for item in items {
+            let mut streak = 0;
+            for correct in &item.history {
+                if counts.len() <= streak {
+                    counts.resize(streak + 1, (0, 0));
+                }
+                counts[streak].0 += 1;
+                if *correct {
+                    counts[streak].1 += 1;
+                    streak += 1;
+                } else {
+                    streak = 0;
+                }
+            }
+        }
+
+        #[allow(clippy::cast_precision_loss)]
+        Self::Recorded(
+            counts
+                .into_iter()
+                .map(|(total, correct)| correct as f64 / total as f64)
+                .collect(),
+        )
+    }
+
+    ///Gets the chance of the simulated user remembering an item at a given time, from `0.0` to `1.0`
+    #[must_use]
+    pub fn probability(&self, item: &Item, now: DateTime<Utc>) -> f64 {
+        let p = match self {
+            Self::Constant(p) => *p,
+            Self::Forgetting {
+                initial_stability_days,
+                growth,
+            } => item.time_since_last_test_at(now).map_or(0.0, |elapsed| {
+                #[allow(clippy::cast_precision_loss, clippy::cast_possible_wrap)]
+                let stability = initial_stability_days * growth.powi(item.true_streak() as i32);
+                #[allow(clippy::cast_precision_loss)]
+                let days = elapsed.num_seconds() as f64 / 86_400.0;
+                (-days / stability).exp()
+            }),
+            Self::Recorded(rates) => rates
+                .get(item.true_streak() as usize)
+                .or_else(|| rates.last())
+                .copied()
+                .unwrap_or(0.5),
+        };
+        p.clamp(0.0, 1.0)
+    }
+}
+
+///Options for [`simulate`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimulationOptions {
+    ///The number of items in the simulated deck
+    pub deck_size: usize,
+    ///The number of days to simulate
+    pub days: u32,
+    ///How long the simulated user studies for each day, in seconds
+    pub daily_budget_secs: i64,
+    ///How long each answer takes, in seconds
+    pub seconds_per_review: i64,
+    ///The most new items the simulated user will see in a day
+    pub new_per_day: usize,
+    ///How likely the simulated user is to remember things
+    pub model: RecallModel,
+    ///What to do for streaks longer than any in the [`SeeAgainGaps`]
+    pub extrapolation: Extrapolation,
+    ///When the first day of studying starts - each day starts at the same time
+    pub start: DateTime<Utc>,
+    ///The seed for the random number generator, so that runs can be repeated
+    pub seed: u64,
+}
+
+impl Default for SimulationOptions {
+    fn default() -> Self {
+        Self {
+            deck_size: 200,
+            days: 90,
+            daily_budget_secs: 10 * 60,
+            seconds_per_review: 10,
+            new_per_day: 20,
+            model: RecallModel::default(),
+            extrapolation: Extrapolation::default(),
+            start: Utc.with_ymd_and_hms(2022, 1, 1, 9, 0, 0).unwrap(),
+            seed: 0,
+        }
+    }
+}
+
+///What happened on one simulated day
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SimulatedDay {
+    ///The day number, starting from 0
+    pub day: u32,
+    ///The number of answers given, including the first answers for new items
+    pub reviews: usize,
+    ///The number of new items seen
+    pub new: usize,
+    ///The number of correct answers
+    pub correct: usize,
+    ///The fraction of answers for items which had already been seen that were correct - or [`Option::None`] if there weren't any
+    pub retention: Option<f64>,
+    ///How long was spent studying, in seconds
+    pub time_spent_secs: i64,
+    ///The number of items which had been seen before and were still due at the end of the day
+    pub backlog: usize,
+    ///The number of items which have been seen at least once
+    pub learned: usize,
+    ///The average chance of remembering each seen item at the end of the day, using the [`RecallModel`]
+    pub average_recall: f64,
+}
+
+///The results of a [`simulate`] run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimulationReport {
+    ///What happened on each day
+    pub days: Vec<SimulatedDay>,
+    ///The total number of answers given
+    pub total_reviews: usize,
+    ///The fraction of answers for items which had already been seen that were correct, over the whole run
+    pub retention: Option<f64>,
+}
+
+impl SimulationReport {
+    ///Exports the report as pretty-printed JSON
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    ///Exports the days as CSV with a header row - one row per day. Days without a retention have an empty cell
+    #[instrument(skip(self, writer))]
+    pub fn write_csv(&self, mut writer: impl Write) -> Result<(), std::io::Error> {
+        writeln!(
+            writer,
+            "day,reviews,new,correct,retention,time_spent_secs,backlog,learned,average_recall"
+        )?;
+        for d in &self.days {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{:.4}",
+                d.day,
+                d.reviews,
+                d.new,
+                d.correct,
+                d.retention.map(|r| format!("{r:.4}")).unwrap_or_default(),
+                d.time_spent_secs,
+                d.backlog,
+                d.learned,
+                d.average_recall
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+///Simulates studying a deck for a number of days with a set of [`SeeAgainGaps`], using an [`AnkiGame`] with a [`MockClock`].
+///
+///Each day, the simulated user answers due items (least recently tested first), and then new ones, until they run out of time or there's nothing due
+#[must_use]
+#[instrument(skip(sag, options))]
+pub fn simulate(sag: SeeAgainGaps, options: &SimulationOptions) -> SimulationReport {
+    let clock = MockClock::new(options.start);
+    let mut game: AnkiGame<DummyStorage, GiveFacts> =
+        AnkiGame::new_params(vec![], DummyStorage::default(), sag)
+            .with_clock(clock.clone())
+            .with_seed(options.seed);
+    game.set_extrapolation(options.extrapolation);
+    game.v = (0..options.deck_size)
+        .map(|i| Fact::new(format!("term {i}"), format!("definition {i}")).into())
+        .collect();
+
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let reviews_per_day = options.daily_budget_secs / options.seconds_per_review.max(1);
+    let mut days = vec![];
+    let (mut seen_reviews, mut seen_correct) = (0, 0);
+
+    for day in 0..options.days {
+        clock.set(options.start + Duration::days(i64::from(day)));
+        let mut today = SimulatedDay {
+            day,
+            reviews: 0,
+            new: 0,
+            correct: 0,
+            retention: None,
+            time_spent_secs: 0,
+            backlog: 0,
+            learned: 0,
+            average_recall: 0.0,
+        };
+        let (mut day_seen_reviews, mut day_seen_correct) = (0, 0);
+
+        #[allow(clippy::cast_possible_wrap)]
+        while (today.reviews as i64) < reviews_per_day {
+            let eligible = game.eligible_indices();
+            let next = eligible
+                .iter()
+                .filter_map(|i| game.v[*i].last_tested.map(|lt| (lt, *i)))
+                .min()
+                .map(|(_, i)| i)
+                .or_else(|| {
+                    (today.new < options.new_per_day)
+                        .then(|| {
+                            eligible
+                                .iter()
+                                .copied()
+                                .find(|i| game.v[*i].last_tested.is_none())
+                        })
+                        .flatten()
+                });
+            let Some(index) = next else {
+                break;
+            };
+
+            let is_new = game.v[index].last_tested.is_none();
+            let correct = rng.gen_bool(options.model.probability(&game.v[index], game.now()));
+            if let Err(e) = game.record_answer(index, correct) {
+                error!("Unable to record simulated answer: {e:?}");
+            }
+            clock.advance(Duration::seconds(options.seconds_per_review));
+
+            today.reviews += 1;
+            today.time_spent_secs += options.seconds_per_review;
+            if correct {
+                today.correct += 1;
+            }
+            if is_new {
+                today.new += 1;
+            } else {
+                day_seen_reviews += 1;
+                if correct {
+                    day_seen_correct += 1;
+                }
+            }
+        }
+
+        let now = game.now();
+        let seen: Vec<&Item> = game.v.iter().filter(|i| i.last_tested.is_some()).collect();
+        today.learned = seen.len();
+        today.backlog = game
+            .eligible_indices()
+            .into_iter()
+            .filter(|i| game.v[*i].last_tested.is_some())
+            .count();
+        #[allow(clippy::cast_precision_loss)]
+        if !seen.is_empty() {
+            today.average_recall = seen
+                .iter()
+                .map(|i| options.model.probability(i, now))
+                .sum::<f64>()
+                / seen.len() as f64;
+        }
+        today.retention = rate(day_seen_correct, day_seen_reviews);
+        trace!("Simulated day {day}: {today:?}");
+
+        seen_reviews += day_seen_reviews;
+        seen_correct += day_seen_correct;
+        days.push(today);
+    }
+
+    SimulationReport {
+        total_reviews: days.iter().map(|d| d.reviews).sum(),
+        retention: rate(seen_correct, seen_reviews),
+        days,
+    }
+}
+
+///Gets `correct / total`, or [`Option::None`] if `total` is 0
+fn rate(correct: usize, total: usize) -> Option<f64> {
+    #[allow(clippy::cast_precision_loss)]
+    (total > 0).then(|| correct as f64 / total as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        game::default_sag,
+        item::Item,
+        simulator::{simulate, RecallModel, SimulationOptions},
+        test_utils::{f, string_wrapper::StringWrapper},
+    };
+    use chrono::Duration;
+    use std::collections::HashMap;
+
+    #[test]
+    fn simulate_test() {
+        let options = SimulationOptions {
+            deck_size: 30,
+            days: 10,
+            new_per_day: 5,
+            ..Default::default()
+        };
+
+        let short = simulate(default_sag(), &options);
+        assert_eq!(short.days.len(), 10);
+        assert!(short.days.iter().all(|d| d.new <= 5));
+        assert_eq!(short, simulate(default_sag(), &options));
+
+        let long_sag = (1..11)
+            .map(|i| (i, Duration::days(i64::from(i) * 2)))
+            .collect::<HashMap<_, _>>();
+        let long = simulate(long_sag, &options);
+        assert!(long.total_reviews < short.total_reviews);
+        assert_eq!(long.days[9].learned, 30);
+
+        let mut csv = StringWrapper::default();
+        long.write_csv(&mut csv).unwrap();
+        let csv = csv.to_inner();
+        assert_eq!(csv.lines().count(), 11);
+        assert!(csv.starts_with("day,reviews,new,correct,retention"));
+        assert!(long.to_json().is_ok());
+    }
+
+    #[test]
+    fn recall_model_test() {
+        let mut a = Item::new(f("a", "b"));
+        a.history = vec![false, true, true, false];
+        let mut b = Item::new(f("c", "d"));
+        b.history = vec![true, true];
+
+        assert_eq!(
+            RecallModel::recorded(&[a, b]),
+            RecallModel::Recorded(vec![2.0 / 3.0, 1.0, 0.0])
+        );
+        assert_eq!(RecallModel::recorded(&[]), RecallModel::Recorded(vec![]));
+        assert!(
+            (RecallModel::Constant(2.0).probability(&Item::new(f("a", "b")), chrono::Utc::now())
+                - 1.0)
+                .abs()
+                < f64::EPSILON
+        );
+    }
+}
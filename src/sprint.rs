@@ -0,0 +1,455 @@
+use crate::{
+    answer::AnswerError,
+    game::{AnkiCardReturnType, AnkiGame},
+    item::Fact,
+    session::{index_of, SessionFilter},
+    storage::Storage,
+};
+use chrono::{DateTime, Duration, Utc};
+use rand::{seq::SliceRandom, thread_rng};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+///The points given for a correct answer, before the combo multiplier
+pub const BASE_POINTS: u32 = 10;
+///How many correct answers in a row are needed to increase the combo multiplier by one
+pub const COMBO_STEP: u32 = 5;
+///The highest that the combo multiplier can go
+pub const MAX_MULTIPLIER: u32 = 5;
+///How many scores are kept for each deck in [`HighScores`]
+pub const HIGH_SCORES_KEPT: usize = 10;
+
+///Options for creating a [`Sprint`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SprintOptions {
+    ///How long the sprint lasts
+    pub duration: Duration,
+    ///Which items can be asked
+    pub filter: SessionFilter,
+    ///Whether or not answers are added to each item's history, changing when it is next eligible
+    pub record_results: bool,
+}
+
+impl Default for SprintOptions {
+    fn default() -> Self {
+        Self {
+            duration: Duration::seconds(60),
+            filter: SessionFilter {
+                only_eligible: true,
+                contains: None,
+            },
+            record_results: true,
+        }
+    }
+}
+
+///The state of a [`Sprint`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SprintState {
+    ///The sprint has been made, but [`Sprint::start`] hasn't been called yet
+    Ready,
+    ///The clock is running
+    Running,
+    ///The time has run out, or there are no items left to ask
+    Finished,
+}
+
+///A sprint - answer as many items as possible before the time runs out, with a combo multiplier for correct answers in a row.
+///
+///The sprint is timed using the [`AnkiGame`]'s [`crate::clock::Clock`], so the game needs to be passed in to anything that checks the time.
+///Items are kept track of by their [`Fact`], so any that are deleted from the game during the sprint are just skipped
+#[derive(Debug, Clone)]
+pub struct Sprint {
+    ///The facts left to ask - with the current one at the front
+    queue: VecDeque<Fact>,
+    ///How long the sprint lasts
+    duration: Duration,
+    ///Whether or not answers are added to each item's history
+    record_results: bool,
+    ///The name of the items that the sprint was made from, for the [`HighScores`]
+    deck: String,
+    ///When the sprint started, if it has
+    started: Option<DateTime<Utc>>,
+    ///The current score
+    score: u32,
+    ///The number of correct answers in a row
+    combo: u32,
+    ///The highest combo so far
+    best_combo: u32,
+    ///The number of correct answers
+    correct: u32,
+    ///The number of incorrect answers
+    incorrect: u32,
+}
+
+///The result of a [`Sprint`], which can be put into the [`HighScores`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SprintResult {
+    ///The final score
+    pub score: u32,
+    ///The number of correct answers
+    pub correct: u32,
+    ///The number of incorrect answers
+    pub incorrect: u32,
+    ///The highest number of correct answers in a row
+    pub best_combo: u32,
+    ///When the sprint started
+    pub at: DateTime<Utc>,
+}
+
+impl Sprint {
+    ///Creates a new sprint with shuffled items from an [`AnkiGame`] using the [`SprintOptions`].
+    ///
+    ///The sprint doesn't start until [`Self::start`] is called.
+    #[instrument(skip(game))]
+    pub fn new<S: Storage, T: AnkiCardReturnType>(
+        game: &AnkiGame<S, T>,
+        options: &SprintOptions,
+    ) -> Self {
+        let mut indices = options.filter.indices(game, true);
+        indices.shuffle(&mut thread_rng());
+
+        Self {
+            queue: indices
+                .into_iter()
+                .map(|index| game.v[index].fact.clone())
+                .collect(),
+            duration: options.duration,
+            record_results: options.record_results,
+            deck: options.filter.deck_name(),
+            started: None,
+            score: 0,
+            combo: 0,
+            best_combo: 0,
+            correct: 0,
+            incorrect: 0,
+        }
+    }
+
+    ///Starts the clock - this does nothing if the sprint has already started
+    pub fn start<S: Storage, T: AnkiCardReturnType>(&mut self, game: &AnkiGame<S, T>) {
+        if self.started.is_none() {
+            self.started = Some(game.now());
+        }
+    }
+
+    ///Gets the current [`SprintState`]
+    #[must_use]
+    pub fn state<S: Storage, T: AnkiCardReturnType>(&self, game: &AnkiGame<S, T>) -> SprintState {
+        match self.started {
+            None => SprintState::Ready,
+            Some(_)
+                if self.next(game).is_none() || self.time_remaining(game) <= Duration::zero() =>
+            {
+                SprintState::Finished
+            }
+            Some(_) => SprintState::Running,
+        }
+    }
+
+    ///Gets how long is left before the time runs out
+    #[must_use]
+    pub fn time_remaining<S: Storage, T: AnkiCardReturnType>(
+        &self,
+        game: &AnkiGame<S, T>,
+    ) -> Duration {
+        self.started.map_or(self.duration, |started| {
+            (self.duration - (game.now() - started)).max(Duration::zero())
+        })
+    }
+
+    ///Gets the fact that is currently being asked, or [`None`] if the sprint isn't running
+    #[must_use]
+    pub fn current_fact<S: Storage, T: AnkiCardReturnType>(
+        &self,
+        game: &AnkiGame<S, T>,
+    ) -> Option<&Fact> {
+        if self.state(game) == SprintState::Running {
+            self.next(game).map(|(_, fact)| fact)
+        } else {
+            None
+        }
+    }
+
+    ///Gets the first fact in the queue which is still in the game, and its index in the game
+    fn next<S: Storage, T: AnkiCardReturnType>(
+        &self,
+        game: &AnkiGame<S, T>,
+    ) -> Option<(usize, &Fact)> {
+        self.queue
+            .iter()
+            .find_map(|fact| index_of(game, fact).map(|index| (index, fact)))
+    }
+
+    ///Checks an answer for the current fact using [`Fact::check`], updates the score and moves on to the next fact.
+    ///
+    ///Returns whether or not the answer was correct, or an [`AnswerError`] without changing anything if the answer couldn't be checked.
+    ///If the sprint isn't running, this returns `Ok(false)`
+    #[instrument(skip(self, game))]
+    pub fn answer<S: Storage, T: AnkiCardReturnType>(
+        &mut self,
+        game: &mut AnkiGame<S, T>,
+        answer: &str,
+    ) -> Result<bool, AnswerError> {
+        if self.state(game) != SprintState::Running {
+            return Ok(false);
+        }
+        let Some((index, fact)) = self.next(game) else {
+            return Ok(false);
+        };
+        let correct = fact.check(answer)?;
+
+        //drop any deleted facts from the front of the queue, along with this one
+        while let Some(fact) = self.queue.pop_front() {
+            if index_of(game, &fact).is_some() {
+                break;
+            }
+        }
+        if self.record_results {
+            //like with backups, this shouldn't stop the sprint
+            if let Err(e) = game.record_answer(index, correct) {
+                error!("Unable to record answer: {e:?}");
+            }
+        }
+
+        if correct {
+            self.score += BASE_POINTS * self.multiplier();
+            self.combo += 1;
+            self.best_combo = self.best_combo.max(self.combo);
+            self.correct += 1;
+        } else {
+            self.combo = 0;
+            self.incorrect += 1;
+        }
+
+        Ok(correct)
+    }
+
+    ///Gets the multiplier that the next correct answer will get - this goes up by one for every [`COMBO_STEP`] correct answers in a row, up to [`MAX_MULTIPLIER`]
+    #[must_use]
+    pub fn multiplier(&self) -> u32 {
+        (1 + self.combo / COMBO_STEP).min(MAX_MULTIPLIER)
+    }
+
+    ///Gets the current score
+    #[must_use]
+    pub const fn score(&self) -> u32 {
+        self.score
+    }
+
+    ///Gets the current number of correct answers in a row
+    #[must_use]
+    pub const fn combo(&self) -> u32 {
+        self.combo
+    }
+
+    ///Gets the name of the items that the sprint was made from, from [`SessionFilter::deck_name`] - this is what the result should be put under in the [`HighScores`]
+    #[must_use]
+    pub fn deck(&self) -> &str {
+        &self.deck
+    }
+
+    ///Gets the [`SprintResult`] so far
+    #[must_use]
+    pub fn result<S: Storage, T: AnkiCardReturnType>(&self, game: &AnkiGame<S, T>) -> SprintResult {
+        SprintResult {
+            score: self.score,
+            correct: self.correct,
+            incorrect: self.incorrect,
+            best_combo: self.best_combo,
+            at: self.started.unwrap_or_else(|| game.now()),
+        }
+    }
+}
+
+///A table of the best [`SprintResult`]s for each deck - normally [`Sprint::deck`] - which can be stored using [`crate::storage::write_extra_json`] with [`HighScores::KEY`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HighScores(BTreeMap<String, Vec<SprintResult>>);
+
+impl HighScores {
+    ///The key used to store high scores with [`Storage::write_extra`]
+    pub const KEY: &'static str = "high_scores.json";
+
+    ///Adds a result to the table for a deck, keeping only the best [`HIGH_SCORES_KEPT`].
+    ///
+    ///Returns the position in the table, starting from 0, or [`None`] if it wasn't good enough to be kept
+    pub fn submit(&mut self, deck: impl Into<String>, result: SprintResult) -> Option<usize> {
+        let scores = self.0.entry(deck.into()).or_default();
+
+        let position = scores
+            .iter()
+            .position(|s| s.score < result.score)
+            .unwrap_or(scores.len());
+        scores.insert(position, result);
+        scores.truncate(HIGH_SCORES_KEPT);
+
+        (position < HIGH_SCORES_KEPT).then_some(position)
+    }
+
+    ///Gets the best results for a deck, best first
+    #[must_use]
+    pub fn get(&self, deck: &str) -> &[SprintResult] {
+        self.0.get(deck).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        clock::MockClock,
+        dummy_storage::DummyStorage,
+        game::{default_sag, AnkiGame, GiveFacts},
+        session::SessionFilter,
+        sprint::{HighScores, Sprint, SprintOptions, SprintState, HIGH_SCORES_KEPT},
+        storage::{read_extra_json, write_extra_json},
+        test_utils::f,
+    };
+    use chrono::Duration;
+
+    fn game(n: usize) -> AnkiGame<DummyStorage, GiveFacts> {
+        let mut game = AnkiGame::new(DummyStorage::default(), default_sag()).unwrap();
+        game.add_facts((0..n).map(|i| f(format!("t{i}"), "d")).collect())
+            .unwrap();
+        game
+    }
+
+    #[test]
+    fn sprint_test() {
+        let clock = MockClock::default();
+        let mut game = game(20).with_clock(clock.clone());
+        let mut sprint = Sprint::new(&game, &SprintOptions::default());
+
+        assert_eq!(sprint.state(&game), SprintState::Ready);
+        assert!(sprint.current_fact(&game).is_none());
+        assert_eq!(sprint.answer(&mut game, "d"), Ok(false));
+
+        sprint.start(&game);
+        assert_eq!(sprint.state(&game), SprintState::Running);
+
+        for _ in 0..6 {
+            assert_eq!(sprint.answer(&mut game, "d"), Ok(true));
+            clock.advance(Duration::seconds(1));
+        }
+        //5 at 1x, then 1 at 2x
+        assert_eq!(sprint.score(), 70);
+        assert_eq!(sprint.combo(), 6);
+        assert_eq!(sprint.multiplier(), 2);
+
+        assert_eq!(sprint.answer(&mut game, "wrong"), Ok(false));
+        assert_eq!(sprint.combo(), 0);
+        assert_eq!(sprint.multiplier(), 1);
+        assert_eq!(sprint.answer(&mut game, "d"), Ok(true));
+        assert_eq!(sprint.score(), 80);
+
+        assert_eq!(sprint.time_remaining(&game), Duration::seconds(54));
+        clock.advance(Duration::seconds(54));
+        assert_eq!(sprint.state(&game), SprintState::Finished);
+        assert_eq!(sprint.answer(&mut game, "d"), Ok(false));
+
+        let result = sprint.result(&game);
+        assert_eq!((result.correct, result.incorrect), (7, 1));
+        assert_eq!(result.best_combo, 6);
+
+        assert_eq!(game.v.iter().filter(|i| !i.history.is_empty()).count(), 8);
+    }
+
+    #[test]
+    fn sprint_runs_out_test() {
+        let mut game = game(2).with_clock(MockClock::default());
+        let mut sprint = Sprint::new(&game, &SprintOptions::default());
+        sprint.start(&game);
+        sprint.answer(&mut game, "d").unwrap();
+        sprint.answer(&mut game, "d").unwrap();
+        assert_eq!(sprint.state(&game), SprintState::Finished);
+    }
+
+    #[test]
+    fn deleted_item_test() {
+        let mut game = game(3).with_clock(MockClock::default());
+        let mut sprint = Sprint::new(&game, &SprintOptions::default());
+        sprint.start(&game);
+
+        let position = |game: &AnkiGame<DummyStorage, GiveFacts>, term: &str| {
+            game.v.iter().position(|i| i.fact.term == term).unwrap()
+        };
+
+        //delete the current item, so every item after it moves down an index
+        let first = sprint.current_fact(&game).unwrap().term.clone();
+        game.delete_at_index(position(&game, &first)).unwrap();
+
+        let second = sprint.current_fact(&game).unwrap().term.clone();
+        assert_ne!(first, second);
+        assert_eq!(sprint.answer(&mut game, "d"), Ok(true));
+        assert_eq!(game.v[position(&game, &second)].history, vec![true]);
+        assert_eq!(game.v.iter().filter(|i| !i.history.is_empty()).count(), 1);
+
+        let third = sprint.current_fact(&game).unwrap().term.clone();
+        game.delete_at_index(position(&game, &third)).unwrap();
+        assert_eq!(sprint.state(&game), SprintState::Finished);
+        assert_eq!(sprint.answer(&mut game, "d"), Ok(false));
+    }
+
+    #[test]
+    fn high_scores_test() {
+        let game = game(1).with_clock(MockClock::default());
+        let result = |score| {
+            let mut r = Sprint::new(&game, &SprintOptions::default()).result(&game);
+            r.score = score;
+            r
+        };
+
+        let mut scores = HighScores::default();
+        assert_eq!(scores.submit("a", result(10)), Some(0));
+        assert_eq!(scores.submit("a", result(30)), Some(0));
+        assert_eq!(scores.submit("a", result(20)), Some(1));
+        assert_eq!(scores.submit("b", result(5)), Some(0));
+        let a: Vec<u32> = scores.get("a").iter().map(|r| r.score).collect();
+        assert_eq!(a, vec![30, 20, 10]);
+        assert!(scores.get("c").is_empty());
+
+        for _ in 0..HIGH_SCORES_KEPT {
+            scores.submit("a", result(100));
+        }
+        assert_eq!(scores.submit("a", result(1)), None);
+        assert_eq!(scores.get("a").len(), HIGH_SCORES_KEPT);
+
+        let mut storage = DummyStorage::default();
+        assert_eq!(
+            read_extra_json::<HighScores, _>(&storage, HighScores::KEY).unwrap(),
+            HighScores::default()
+        );
+        write_extra_json(&mut storage, HighScores::KEY, &scores).unwrap();
+        assert_eq!(
+            read_extra_json::<HighScores, _>(&storage, HighScores::KEY).unwrap(),
+            scores
+        );
+    }
+
+    #[test]
+    fn deck_test() {
+        let game = game(1);
+        let sprint = |filter| {
+            Sprint::new(
+                &game,
+                &SprintOptions {
+                    filter,
+                    ..SprintOptions::default()
+                },
+            )
+        };
+
+        assert_eq!(sprint(SessionFilter::default()).deck(), "all facts");
+        assert_eq!(
+            sprint(SessionFilter {
+                only_eligible: true,
+                contains: Some("Verbs".into()),
+            })
+            .deck(),
+            "eligible facts containing \"verbs\""
+        );
+        assert_ne!(
+            SprintOptions::default().filter.deck_name(),
+            SessionFilter::default().deck_name()
+        );
+    }
+}
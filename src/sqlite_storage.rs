@@ -0,0 +1,411 @@
+use crate::{
+    game::AnkiDB,
+    item::{Fact, Item, Review},
+    storage::Storage,
+};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{cell::RefCell, path::Path};
+use thiserror::Error;
+
+///The tables used by [`SqliteStorage`] - items are kept in order with their `position`, and each review is stored separately so that answering only adds a row
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS items (
+    position INTEGER PRIMARY KEY,
+    term TEXT NOT NULL,
+    definition TEXT NOT NULL,
+    answer_type TEXT NOT NULL,
+    last_tested TEXT,
+    history TEXT NOT NULL,
+    due TEXT
+);
+CREATE TABLE IF NOT EXISTS reviews (
+    item INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    at TEXT NOT NULL,
+    correct INTEGER NOT NULL,
+    cram INTEGER NOT NULL,
+    PRIMARY KEY (item, position)
+);
+CREATE TABLE IF NOT EXISTS extra (
+    key TEXT PRIMARY KEY,
+    contents TEXT NOT NULL
+);
+";
+
+///A struct implementing [`Storage`] for an `SQLite` database, with items, reviews and extra data (like the [`crate::config::JankiConfig`]) in their own tables.
+///
+///Unlike [`crate::file_storage::NamedFileStorage`], [`Storage::write_db`] only writes the items which have changed since the last read or write, and only adds the new reviews - so answering one question in a huge collection only changes a couple of rows. The finer-grained methods like [`Storage::append_review`] and transactions are also supported
+#[derive(Debug)]
+pub struct SqliteStorage {
+    ///The connection to the database
+    conn: Connection,
+    ///The items as of the last read or write, to work out what has changed
+    written: RefCell<AnkiDB>,
+}
+
+///Struct using `thiserror` that contains all errors for [`SqliteStorage`]
+#[derive(Error, Debug)]
+pub enum SqliteStorageError {
+    ///Error from `SQLite` - comes from [`rusqlite::Error`]
+    #[error("sqlite error: {0}")]
+    SqliteError(#[from] rusqlite::Error),
+
+    ///Error with `serde_json` - comes from [`serde_json::Error`]
+    #[error("serde_json error: {0}")]
+    SJError(#[from] serde_json::Error),
+
+    ///Error reading a time from the database - comes from [`chrono::ParseError`]
+    #[error("error parsing time: {0}")]
+    TimeError(#[from] chrono::ParseError),
+}
+
+impl SqliteStorage {
+    ///Opens an `SQLite` database at a given path, creating it and the tables if they don't exist
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SqliteStorageError> {
+        Self::new(Connection::open(path)?)
+    }
+
+    ///Opens an `SQLite` database which only lives in memory - useful for testing
+    pub fn open_in_memory() -> Result<Self, SqliteStorageError> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    ///Creates the tables if they don't exist, and reads in what is already there so that the first write is incremental
+    fn new(conn: Connection) -> Result<Self, SqliteStorageError> {
+        conn.execute_batch(SCHEMA)?;
+        let s = Self {
+            conn,
+            written: RefCell::new(vec![]),
+        };
+        s.read_db()?;
+        Ok(s)
+    }
+}
+
+///Turns a time into text to store in the database
+fn time_to_sql(t: DateTime<Utc>) -> String {
+    t.to_rfc3339()
+}
+
+///Reads a time that was stored with [`time_to_sql`]
+fn time_from_sql(s: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+    DateTime::parse_from_rfc3339(s).map(|t| t.with_timezone(&Utc))
+}
+
+///Writes one item to the database - `old` is what was there before, if anything, so that only the new reviews need writing
+fn write_item(
+    conn: &Connection,
+    index: usize,
+    item: &Item,
+    old: Option<&Item>,
+) -> Result<(), SqliteStorageError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO items (position, term, definition, answer_type, last_tested, history, due) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            index,
+            item.fact.term,
+            item.fact.definition,
+            serde_json::to_string(&item.fact.answer_type)?,
+            item.last_tested.map(time_to_sql),
+            serde_json::to_string(&item.history)?,
+            item.due.map(time_to_sql),
+        ],
+    )?;
+
+    //usually reviews are only ever added, so only the new ones need writing
+    let kept = match old {
+        Some(old) if item.reviews.starts_with(&old.reviews) => old.reviews.len(),
+        _ => {
+            conn.execute("DELETE FROM reviews WHERE item = ?1", [index])?;
+            0
+        }
+    };
+    for (position, review) in item.reviews.iter().enumerate().skip(kept) {
+        conn.execute(
+            "INSERT INTO reviews (item, position, at, correct, cram) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                index,
+                position,
+                time_to_sql(review.at),
+                review.correct,
+                review.cram
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    type ErrorType = SqliteStorageError;
+
+    #[instrument(skip(self))]
+    fn read_db(&self) -> Result<AnkiDB, Self::ErrorType> {
+        trace!("Reading from SqliteStorage");
+
+        let mut stmt = self.conn.prepare(
+            "SELECT term, definition, answer_type, last_tested, history, due FROM items ORDER BY position",
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut db = vec![];
+        while let Some(row) = rows.next()? {
+            let fact = Fact {
+                term: row.get(0)?,
+                definition: row.get(1)?,
+                answer_type: serde_json::from_str(&row.get::<_, String>(2)?)?,
+            };
+            let last_tested: Option<String> = row.get(3)?;
+            let due: Option<String> = row.get(5)?;
+
+            db.push(Item {
+                fact,
+                last_tested: last_tested.as_deref().map(time_from_sql).transpose()?,
+                history: serde_json::from_str(&row.get::<_, String>(4)?)?,
+                reviews: vec![],
+                due: due.as_deref().map(time_from_sql).transpose()?,
+            });
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT item, at, correct, cram FROM reviews ORDER BY item, position")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let index: usize = row.get(0)?;
+            let Some(item) = db.get_mut(index) else {
+                warn!(?index, "Found a review for an item that doesn't exist");
+                continue;
+            };
+            item.reviews.push(Review {
+                at: time_from_sql(&row.get::<_, String>(1)?)?,
+                correct: row.get(2)?,
+                cram: row.get(3)?,
+            });
+        }
+
+        self.written.borrow_mut().clone_from(&db);
+        Ok(db)
+    }
+
+    #[instrument(skip(self, db))]
+    fn write_db(&mut self, db: &AnkiDB) -> Result<(), Self::ErrorType> {
+        trace!("Writing to SqliteStorage");
+        let written = self.written.get_mut();
+        let sp = self.conn.savepoint()?;
+
+        let mut changed = 0;
+        for (index, item) in db.iter().enumerate() {
+            let old = written.get(index);
+            if old != Some(item) {
+                write_item(&sp, index, item, old)?;
+                changed += 1;
+            }
+        }
+
+        sp.execute("DELETE FROM items WHERE position >= ?1", [db.len()])?;
+        sp.execute("DELETE FROM reviews WHERE item >= ?1", [db.len()])?;
+        sp.commit()?;
+        trace!(?changed, "Wrote changed items");
+
+        written.clone_from(db);
+        Ok(())
+    }
+
+    #[instrument(skip(self, db))]
+    fn upsert_item(&mut self, db: &AnkiDB, index: usize) -> Result<(), Self::ErrorType> {
+        trace!("Upserting item in SqliteStorage");
+        let written = self.written.get_mut();
+        if index > written.len() || db.len() != written.len().max(index + 1) {
+            warn!("Out of sync with the database, writing everything");
+            return self.write_db(db);
+        }
+
+        let item = &db[index];
+        let sp = self.conn.savepoint()?;
+        write_item(&sp, index, item, written.get(index))?;
+        sp.commit()?;
+
+        if index == written.len() {
+            written.push(item.clone());
+        } else {
+            written[index].clone_from(item);
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self, db))]
+    fn delete_item(&mut self, db: &AnkiDB, index: usize) -> Result<(), Self::ErrorType> {
+        trace!("Deleting item from SqliteStorage");
+        let written = self.written.get_mut();
+        if index >= written.len() || db.len() + 1 != written.len() {
+            warn!("Out of sync with the database, writing everything");
+            return self.write_db(db);
+        }
+
+        //positions are moved down via negative numbers, as they're part of the primary keys
+        let sp = self.conn.savepoint()?;
+        sp.execute("DELETE FROM items WHERE position = ?1", [index])?;
+        sp.execute("DELETE FROM reviews WHERE item = ?1", [index])?;
+        sp.execute(
+            "UPDATE items SET position = -position WHERE position > ?1",
+            [index],
+        )?;
+        sp.execute(
+            "UPDATE items SET position = -position - 1 WHERE position < 0",
+            [],
+        )?;
+        sp.execute("UPDATE reviews SET item = -item WHERE item > ?1", [index])?;
+        sp.execute("UPDATE reviews SET item = -item - 1 WHERE item < 0", [])?;
+        sp.commit()?;
+
+        written.remove(index);
+        Ok(())
+    }
+
+    #[instrument(skip(self, db))]
+    fn append_review(&mut self, db: &AnkiDB, index: usize) -> Result<(), Self::ErrorType> {
+        //only the new reviews are written, as the old ones are a prefix
+        self.upsert_item(db, index)
+    }
+
+    #[instrument(skip(self))]
+    fn begin_transaction(&mut self) -> Result<(), Self::ErrorType> {
+        trace!("Beginning transaction");
+        Ok(self.conn.execute_batch("BEGIN")?)
+    }
+
+    #[instrument(skip(self))]
+    fn commit_transaction(&mut self) -> Result<(), Self::ErrorType> {
+        trace!("Committing transaction");
+        Ok(self.conn.execute_batch("COMMIT")?)
+    }
+
+    #[instrument(skip(self))]
+    fn rollback_transaction(&mut self) -> Result<(), Self::ErrorType> {
+        trace!("Rolling back transaction");
+        self.conn.execute_batch("ROLLBACK")?;
+        //the cache has the changes that were rolled back, so it needs reading again
+        self.read_db()?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    fn read_extra(&self, key: &str) -> Result<Option<String>, Self::ErrorType> {
+        trace!("Reading extra data from SqliteStorage");
+        Ok(self
+            .conn
+            .query_row("SELECT contents FROM extra WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    #[instrument(skip(self, contents))]
+    fn write_extra(&mut self, key: &str, contents: &str) -> Result<(), Self::ErrorType> {
+        trace!("Writing extra data to SqliteStorage");
+        self.conn.execute(
+            "INSERT OR REPLACE INTO extra (key, contents) VALUES (?1, ?2)",
+            [key, contents],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        answer::{AnswerType, NumericOptions},
+        config::JankiConfig,
+        game::{default_sag, AnkiGame, GiveFacts},
+        item::Item,
+        sqlite_storage::{SqliteStorage, SqliteStorageError},
+        storage::{transaction, Storage},
+        test_utils::f,
+    };
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn round_trip_test() {
+        let now = Utc.with_ymd_and_hms(2022, 7, 1, 12, 0, 0).unwrap();
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        assert!(storage.read_db().unwrap().is_empty());
+
+        let mut a = Item::new(f("a", "b"));
+        a.record(true, now);
+        a.record_cram(false, now + Duration::minutes(1));
+        a.due = Some(now + Duration::days(1));
+        let b =
+            Item::new(f("c", "4").with_answer_type(AnswerType::Numeric(NumericOptions::default())));
+        let db = vec![a, b];
+
+        storage.write_db(&db).unwrap();
+        assert_eq!(storage.read_db().unwrap(), db);
+
+        let config = JankiConfig::default();
+        config.save(&mut storage).unwrap();
+        assert_eq!(JankiConfig::load(&storage).unwrap(), config);
+        assert_eq!(storage.read_extra("nothing.json").unwrap(), None);
+    }
+
+    #[test]
+    fn incremental_test() {
+        let now = Utc.with_ymd_and_hms(2022, 7, 1, 12, 0, 0).unwrap();
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        let mut db: Vec<Item> = (0..100)
+            .map(|i| Item::new(f(i.to_string(), "def")))
+            .collect();
+        storage.write_db(&db).unwrap();
+
+        //answering one item only changes its row, and adds a review
+        let total_changes = |s: &SqliteStorage| -> i64 {
+            s.conn
+                .query_row("SELECT total_changes()", [], |row| row.get(0))
+                .unwrap()
+        };
+        let before = total_changes(&storage);
+        db[50].record(true, now);
+        storage.write_db(&db).unwrap();
+        assert_eq!(total_changes(&storage) - before, 2);
+        storage.write_db(&db).unwrap();
+        assert_eq!(total_changes(&storage) - before, 2);
+        assert_eq!(storage.read_db().unwrap(), db);
+
+        //removing items removes their rows and reviews
+        db.remove(0);
+        storage.write_db(&db).unwrap();
+        assert_eq!(storage.read_db().unwrap(), db);
+        db.truncate(10);
+        storage.write_db(&db).unwrap();
+        assert_eq!(storage.read_db().unwrap(), db);
+    }
+
+    #[test]
+    fn game_test() {
+        let mut game: AnkiGame<SqliteStorage, GiveFacts> = AnkiGame::new_params(
+            vec![],
+            SqliteStorage::open_in_memory().unwrap(),
+            default_sag(),
+        );
+        game.add_facts((0..10).map(|i| f(i.to_string(), "def")).collect())
+            .unwrap();
+        game.record_answer(3, true).unwrap();
+        game.record_cram_answer(3, false).unwrap();
+        game.delete_at_index(1).unwrap();
+        assert_eq!(game.storage.read_db().unwrap(), game.v);
+        assert_eq!(game.v[2].reviews.len(), 2);
+
+        //failed transactions leave the database how it was
+        let before = game.v.clone();
+        let result: Result<(), _> = transaction(&mut game.storage, |s| {
+            s.delete_item(&before[1..].to_vec(), 0)?;
+            Err(SqliteStorageError::SqliteError(
+                rusqlite::Error::InvalidQuery,
+            ))
+        });
+        assert!(result.is_err());
+        assert_eq!(game.storage.read_db().unwrap(), before);
+        game.add_fact(f("new", "def")).unwrap();
+        assert_eq!(game.storage.read_db().unwrap(), game.v);
+    }
+}
@@ -0,0 +1,391 @@
+use crate::{
+    game::{Extrapolation, SeeAgainGaps},
+    item::{Fact, Item},
+};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+///The number of correct answers in a row needed for an item to stop being [`CardState::Learning`]
+pub const LEARNING_STREAK: u32 = 2;
+///The gap after which an item counts as [`CardState::Mature`] rather than [`CardState::Young`]
+pub const MATURE_DAYS: i64 = 21;
+///The number of items kept in [`Stats::hardest`]
+pub const HARDEST_KEPT: usize = 10;
+///The number of days covered by the reviews per day and due forecast in a [`Stats`] report
+pub const REPORT_DAYS: usize = 7;
+
+///How well the user knows an item
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum CardState {
+    ///Never tested
+    New,
+    ///Tested, but not yet answered correctly [`LEARNING_STREAK`] times in a row
+    Learning,
+    ///Known, with a gap of less than [`MATURE_DAYS`] days
+    Young,
+    ///Known, with a gap of at least [`MATURE_DAYS`] days
+    Mature,
+}
+
+impl CardState {
+    ///Works out the state of an item, using the gaps to see how long until it'll be seen again
+    #[must_use]
+    pub fn of(item: &Item, sag: &SeeAgainGaps, extrapolation: Extrapolation) -> Self {
+        if item.history.is_empty() {
+            Self::New
+        } else if item.true_streak() < LEARNING_STREAK {
+            Self::Learning
+        } else if extrapolation
+            .gap(sag, item.get_streak())
+            .is_some_and(|gap| gap >= Duration::days(MATURE_DAYS))
+        {
+            Self::Mature
+        } else {
+            Self::Young
+        }
+    }
+}
+
+///The number of items in each [`CardState`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateCounts {
+    ///The number of [`CardState::New`] items
+    pub new: usize,
+    ///The number of [`CardState::Learning`] items
+    pub learning: usize,
+    ///The number of [`CardState::Young`] items
+    pub young: usize,
+    ///The number of [`CardState::Mature`] items
+    pub mature: usize,
+}
+
+impl StateCounts {
+    ///Counts up the states of a list of items
+    #[must_use]
+    pub fn new(items: &[Item], sag: &SeeAgainGaps, extrapolation: Extrapolation) -> Self {
+        let mut counts = Self::default();
+        for item in items {
+            match CardState::of(item, sag, extrapolation) {
+                CardState::New => counts.new += 1,
+                CardState::Learning => counts.learning += 1,
+                CardState::Young => counts.young += 1,
+                CardState::Mature => counts.mature += 1,
+            }
+        }
+        counts
+    }
+}
+
+///The true retention over a period of time - how many reviews of already-seen items were correct
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Retention {
+    ///The number of reviews
+    pub reviews: usize,
+    ///The number of correct reviews
+    pub correct: usize,
+}
+
+impl Retention {
+    ///Works out the retention for every review since a given time. Pass [`Option::None`] for all time.
+    ///
+    ///Only reviews that affected the scheduling count, and the first review of each item is left out, as it wasn't remembering anything
+    #[must_use]
+    pub fn since(items: &[Item], since: Option<DateTime<Utc>>) -> Self {
+        let mut retention = Self::default();
+        for item in items {
+            for review in item
+                .reviews()
+                .iter()
+                .filter(|r| !r.cram)
+                .skip(1)
+                .filter(|r| since.is_none_or(|since| r.at >= since))
+            {
+                retention.reviews += 1;
+                if review.correct {
+                    retention.correct += 1;
+                }
+            }
+        }
+        retention
+    }
+
+    ///Gets the fraction of reviews that were correct, from `0.0` to `1.0` - or [`Option::None`] if there were no reviews
+    #[must_use]
+    pub fn rate(&self) -> Option<f64> {
+        #[allow(clippy::cast_precision_loss)]
+        (self.reviews > 0).then(|| self.correct as f64 / self.reviews as f64)
+    }
+}
+
+///The number of reviews done on one day
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DayReviews {
+    ///The day (in UTC)
+    pub day: NaiveDate,
+    ///The number of reviews, including ones done while cramming
+    pub reviews: usize,
+    ///The number of correct reviews
+    pub correct: usize,
+}
+
+///Gets the number of reviews done on each of the last `days` days, oldest first, including today and any days with no reviews
+#[must_use]
+pub fn reviews_per_day(items: &[Item], days: usize, now: DateTime<Utc>) -> Vec<DayReviews> {
+    let today = now.date_naive();
+    #[allow(clippy::cast_possible_wrap)]
+    let mut list: Vec<DayReviews> = (0..days)
+        .rev()
+        .map(|ago| DayReviews {
+            day: today - Duration::days(ago as i64),
+            reviews: 0,
+            correct: 0,
+        })
+        .collect();
+
+    for review in items.iter().flat_map(Item::reviews) {
+        let ago = (today - review.at.date_naive()).num_days();
+        if ago < 0 {
+            continue;
+        }
+        if let Some(day) = usize::try_from(ago)
+            .ok()
+            .and_then(|ago| days.checked_sub(ago + 1))
+            .and_then(|index| list.get_mut(index))
+        {
+            day.reviews += 1;
+            if review.correct {
+                day.correct += 1;
+            }
+        }
+    }
+
+    list
+}
+
+///Gets the number of items due on each of the next `days` days, starting with today - which also includes anything overdue.
+///
+///Items which have never been tested aren't included
+#[must_use]
+pub fn due_forecast(
+    items: &[Item],
+    sag: &SeeAgainGaps,
+    extrapolation: Extrapolation,
+    days: usize,
+    now: DateTime<Utc>,
+) -> Vec<usize> {
+    let today = now.date_naive();
+    let mut forecast = vec![0; days];
+
+    for due in items
+        .iter()
+        .filter_map(|item| item.next_due(sag, extrapolation))
+    {
+        let index = usize::try_from((due.date_naive() - today).num_days()).unwrap_or(0);
+        if let Some(count) = forecast.get_mut(index) {
+            *count += 1;
+        }
+    }
+
+    forecast
+}
+
+///Gets the hardest items that have been tested, hardest first, along with their [`Item::difficulty`]
+#[must_use]
+pub fn hardest(items: &[Item], count: usize) -> Vec<(Fact, f64)> {
+    let mut list: Vec<(Fact, f64)> = items
+        .iter()
+        .filter(|item| !item.history.is_empty())
+        .map(|item| (item.fact.clone(), item.difficulty()))
+        .collect();
+    list.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    list.truncate(count);
+    list
+}
+
+///Gets the average number of correct answers in a row across every tested item - or `0.0` if nothing has been tested
+#[must_use]
+pub fn average_streak(items: &[Item]) -> f64 {
+    let streaks: Vec<u32> = items
+        .iter()
+        .filter(|item| !item.history.is_empty())
+        .map(Item::true_streak)
+        .collect();
+
+    if streaks.is_empty() {
+        return 0.0;
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    let average = f64::from(streaks.iter().sum::<u32>()) / streaks.len() as f64;
+    average
+}
+
+///Gets how many tested items have each number of correct answers in a row - `distribution[n]` is the number of items with a streak of `n`
+#[must_use]
+pub fn streak_distribution(items: &[Item]) -> Vec<usize> {
+    let mut distribution = vec![];
+    for streak in items
+        .iter()
+        .filter(|item| !item.history.is_empty())
+        .map(Item::true_streak)
+    {
+        let streak = streak as usize;
+        if distribution.len() <= streak {
+            distribution.resize(streak + 1, 0);
+        }
+        distribution[streak] += 1;
+    }
+    distribution
+}
+
+///A report of statistics about a collection of items - good for a weekly summary.
+///
+///Use the functions in this module directly for other time windows
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    ///When the report was made
+    pub at: DateTime<Utc>,
+    ///The total number of items
+    pub total: usize,
+    ///The number of items in each [`CardState`]
+    pub states: StateCounts,
+    ///The true retention over the last 7 days
+    pub retention_week: Retention,
+    ///The true retention over the last 30 days
+    pub retention_month: Retention,
+    ///The true retention over all time
+    pub retention_all: Retention,
+    ///The average number of correct answers in a row, from [`average_streak`]
+    pub average_streak: f64,
+    ///The number of items with each streak, from [`streak_distribution`]
+    pub streaks: Vec<usize>,
+    ///The [`HARDEST_KEPT`] hardest items, from [`hardest`]
+    pub hardest: Vec<(Fact, f64)>,
+    ///The reviews over the last [`REPORT_DAYS`] days, from [`reviews_per_day`]
+    pub reviews_per_day: Vec<DayReviews>,
+    ///The items due over the next [`REPORT_DAYS`] days, from [`due_forecast`]
+    pub forecast: Vec<usize>,
+}
+
+impl Stats {
+    ///Works out all of the statistics for a list of items
+    #[must_use]
+    pub fn new(
+        items: &[Item],
+        sag: &SeeAgainGaps,
+        extrapolation: Extrapolation,
+        now: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            at: now,
+            total: items.len(),
+            states: StateCounts::new(items, sag, extrapolation),
+            retention_week: Retention::since(items, Some(now - Duration::days(7))),
+            retention_month: Retention::since(items, Some(now - Duration::days(30))),
+            retention_all: Retention::since(items, None),
+            average_streak: average_streak(items),
+            streaks: streak_distribution(items),
+            hardest: hardest(items, HARDEST_KEPT),
+            reviews_per_day: reviews_per_day(items, REPORT_DAYS, now),
+            forecast: due_forecast(items, sag, extrapolation, REPORT_DAYS, now),
+        }
+    }
+
+    ///Serialises the report to pretty-printed JSON
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        game::Extrapolation,
+        item::{Item, Review},
+        stats::{
+            average_streak, due_forecast, hardest, reviews_per_day, streak_distribution, CardState,
+            Retention, StateCounts, Stats,
+        },
+        test_utils::f,
+    };
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use std::collections::HashMap;
+
+    fn reviewed(term: &str, answers: &[(DateTime<Utc>, bool)]) -> Item {
+        let mut item = Item::new(f(term, "def"));
+        for (at, correct) in answers {
+            item.history.push(*correct);
+            item.last_tested = Some(*at);
+            item.reviews.push(Review {
+                at: *at,
+                correct: *correct,
+                cram: false,
+            });
+        }
+        item
+    }
+
+    #[test]
+    fn stats_test() {
+        let now = Utc.with_ymd_and_hms(2022, 7, 10, 12, 0, 0).unwrap();
+        let day = |ago| now - Duration::days(ago);
+        let sag = HashMap::from([
+            (1, Duration::hours(12)),
+            (2, Duration::days(2)),
+            (3, Duration::days(30)),
+        ]);
+
+        let items = vec![
+            Item::new(f("new", "def")),
+            reviewed("learning", &[(day(1), false)]),
+            reviewed(
+                "young",
+                &[(day(20), true), (now - Duration::hours(1), true)],
+            ),
+            reviewed(
+                "mature",
+                &[(day(40), true), (day(35), true), (day(1), true)],
+            ),
+            reviewed("hard", &[(day(2), true), (day(2), false), (day(1), false)]),
+        ];
+
+        let ex = Extrapolation::Immediate;
+        assert_eq!(CardState::of(&items[0], &sag, ex), CardState::New);
+        assert_eq!(
+            StateCounts::new(&items, &sag, ex),
+            StateCounts {
+                new: 1,
+                learning: 2,
+                young: 1,
+                mature: 1
+            }
+        );
+
+        let week = Retention::since(&items, Some(day(7)));
+        assert_eq!((week.reviews, week.correct), (4, 2));
+        assert_eq!(Retention::since(&items, None).reviews, 5);
+        assert_eq!(Retention::default().rate(), None);
+        assert!((week.rate().unwrap() - 0.5).abs() < 1e-9);
+
+        assert!((average_streak(&items) - 1.25).abs() < 1e-9);
+        assert_eq!(streak_distribution(&items), vec![2, 0, 1, 1]);
+        let hardest = hardest(&items, 2);
+        assert_eq!(hardest[0].0.term, "learning");
+        assert_eq!(hardest[1].0.term, "hard");
+
+        let per_day = reviews_per_day(&items, 3, now);
+        assert_eq!(per_day[0].day, day(2).date_naive());
+        assert_eq!(
+            per_day.iter().map(|d| d.reviews).collect::<Vec<_>>(),
+            vec![2, 3, 1]
+        );
+
+        //learning and hard are overdue, young is due in 2 days, and mature in 29 days
+        assert_eq!(due_forecast(&items, &sag, ex, 3, now), vec![2, 0, 1]);
+
+        let stats = Stats::new(&items, &sag, ex, now);
+        assert_eq!(stats.total, 5);
+        assert_eq!(stats.reviews_per_day.len(), 7);
+        assert!(stats.to_json().is_ok());
+    }
+}
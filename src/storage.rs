@@ -1,4 +1,6 @@
 use crate::game::AnkiDB;
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
 
 ///Trait for a place to store the database
 pub trait Storage {
@@ -14,4 +16,118 @@ pub trait Storage {
     fn write_db(&mut self, db: &AnkiDB) -> Result<(), Self::ErrorType>;
     ///Exits the application - not always necessary, as things like files can be automatically dropped
     fn exit_application(&mut self) {}
+
+    ///Writes one item which has just been added to the end of the [`AnkiDB`] or changed - `db` has already been updated.
+    ///
+    ///By default, this writes the whole database with [`Storage::write_db`]
+    fn upsert_item(&mut self, db: &AnkiDB, _index: usize) -> Result<(), Self::ErrorType> {
+        self.write_db(db)
+    }
+    ///Removes the item that was at `index` - `db` has already had it removed, so every item after it has moved down one.
+    ///
+    ///By default, this writes the whole database with [`Storage::write_db`]
+    fn delete_item(&mut self, db: &AnkiDB, _index: usize) -> Result<(), Self::ErrorType> {
+        self.write_db(db)
+    }
+    ///Writes the newest [`crate::item::Review`] of the item at `index`, which has just been answered - `db` has already been updated, so the rest of the item (like the history) has changed too.
+    ///
+    ///By default, this writes the whole database with [`Storage::write_db`]
+    fn append_review(&mut self, db: &AnkiDB, _index: usize) -> Result<(), Self::ErrorType> {
+        self.write_db(db)
+    }
+
+    ///Starts a transaction - every change until [`Storage::commit_transaction`] is written all at once, or not at all. Use [`transaction`] to make sure it is finished.
+    ///
+    ///By default, this does nothing, and every change is written straight away
+    fn begin_transaction(&mut self) -> Result<(), Self::ErrorType> {
+        Ok(())
+    }
+    ///Finishes a transaction, writing all of the changes made since [`Storage::begin_transaction`] - by default, this does nothing
+    fn commit_transaction(&mut self) -> Result<(), Self::ErrorType> {
+        Ok(())
+    }
+    ///Cancels a transaction, throwing away all of the changes made since [`Storage::begin_transaction`] - by default, this does nothing, as the changes have already been written
+    fn rollback_transaction(&mut self) -> Result<(), Self::ErrorType> {
+        Ok(())
+    }
+
+    ///Reads some extra data that is stored alongside the database, like high scores. Keys look like file names, eg. `"high_scores.json"`.
+    ///
+    ///Returns [`Option::None`] if nothing has been stored for that key - which is always the case by default, as not every [`Storage`] can store extra data
+    fn read_extra(&self, _key: &str) -> Result<Option<String>, Self::ErrorType> {
+        Ok(None)
+    }
+    ///Writes some extra data alongside the database - by default, this does nothing
+    fn write_extra(&mut self, _key: &str, _contents: &str) -> Result<(), Self::ErrorType> {
+        Ok(())
+    }
+
+    ///Protects a backup of the database before it is written by [`crate::backup::BackupManager`] - storages that protect the database, like [`crate::encrypted_storage::EncryptedStorage`], should protect backups the same way.
+    ///
+    ///By default, the backup is written as it is
+    fn seal_backup(&self, contents: String) -> Result<String, Self::ErrorType> {
+        Ok(contents)
+    }
+    ///Undoes [`Storage::seal_backup`] when a backup is read - by default, the backup is read as it is
+    fn open_backup(&self, contents: String) -> Result<String, Self::ErrorType> {
+        Ok(contents)
+    }
+}
+
+///Runs `f` in a transaction on the [`Storage`] - if it succeeds, the transaction is committed, and if not, it is rolled back
+pub fn transaction<S: Storage + ?Sized, T>(
+    storage: &mut S,
+    f: impl FnOnce(&mut S) -> Result<T, S::ErrorType>,
+) -> Result<T, S::ErrorType> {
+    storage.begin_transaction()?;
+    match f(storage) {
+        Ok(t) => {
+            storage.commit_transaction()?;
+            Ok(t)
+        }
+        Err(e) => {
+            if let Err(rollback) = storage.rollback_transaction() {
+                error!("Unable to roll back transaction: {rollback:?}");
+            }
+            Err(e)
+        }
+    }
+}
+
+///Error for reading and writing extra data as JSON, using `thiserror`
+#[derive(Error, Debug)]
+pub enum ExtraDataError<E> {
+    ///Error from the [`Storage`]
+    #[error("storage error: {0:?}")]
+    StorageError(E),
+
+    ///Error with `serde_json` - comes from [`serde_json::Error`]
+    #[error("serde_json error: {0}")]
+    SJError(#[from] serde_json::Error),
+}
+
+///Reads extra data as JSON using [`Storage::read_extra`], or [`Default::default`] if nothing has been stored
+pub fn read_extra_json<T: DeserializeOwned + Default, S: Storage + ?Sized>(
+    storage: &S,
+    key: &str,
+) -> Result<T, ExtraDataError<S::ErrorType>> {
+    match storage
+        .read_extra(key)
+        .map_err(ExtraDataError::StorageError)?
+    {
+        Some(contents) => Ok(serde_json::from_str(&contents)?),
+        None => Ok(T::default()),
+    }
+}
+
+///Writes extra data as JSON using [`Storage::write_extra`]
+pub fn write_extra_json<T: Serialize, S: Storage + ?Sized>(
+    storage: &mut S,
+    key: &str,
+    value: &T,
+) -> Result<(), ExtraDataError<S::ErrorType>> {
+    let contents = serde_json::to_string(value)?;
+    storage
+        .write_extra(key, &contents)
+        .map_err(ExtraDataError::StorageError)
 }